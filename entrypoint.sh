@@ -1,7 +1,10 @@
 #!/bin/sh
 
-# Génère le contenu du fichier auth.txt à partir des variables d'environnement
-echo -n "$USERNAME:$PASSWORD" | base64 > /auth.txt
+# Génère le contenu du fichier auth.txt à partir des variables d'environnement,
+# sans identifiants les topics ntfy sont utilisés anonymement
+if [ -n "$USERNAME" ]; then
+    echo -n "$USERNAME:$PASSWORD" | base64 > /auth.txt
+fi
 
 # Démarrer nginx en arrière-plan
 nginx -g 'daemon off;' &
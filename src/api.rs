@@ -1,20 +1,83 @@
 use log::{error, info};
-use rusqlite::{Connection, Result as SqliteResult, params};
+use rusqlite::{Connection, params};
 use serde_json::json;
 use std::env;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use warp::{Filter, Reply, Rejection};
 use warp::http::StatusCode;
 use serde::{Serialize, Deserialize};
-use warp::cors::Cors;
+use crate::config::SharedConfig;
+use crate::database;
+use crate::manifest;
+use crate::drift;
+use crate::health;
+use crate::metrics;
+use crate::models::{Priority, WatchKind, WatchSource};
+use crate::notifications::{self, markdown::Dialect};
+use crate::notifications::template::{self, MessageContext, MessageTemplate, TemplateEvent, Templates};
+use crate::watchlist::{self, Format};
 
 #[derive(Debug, Serialize, Deserialize)]
 struct RepoRequest {
     repo: String,
 }
 
-pub async fn start_api() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+#[derive(Debug, Deserialize)]
+struct SourceIdRequest {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeployedVersionRequest {
+    #[serde(rename = "type", default = "default_watch_kind")]
+    kind: WatchKind,
+    repo: String,
+    version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PriorityRequest {
+    #[serde(rename = "type", default = "default_watch_kind")]
+    kind: WatchKind,
+    repo: String,
+    priority: Option<String>,
+}
+
+fn default_watch_kind() -> WatchKind {
+    WatchKind::Github
+}
+
+fn default_template_event() -> TemplateEvent {
+    TemplateEvent::Github
+}
+
+#[derive(Debug, Deserialize)]
+struct ManifestQuery {
+    name: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreviewRequest {
+    #[serde(default = "default_template_event")]
+    event: TemplateEvent,
+    // Start from the templates of this target instead of the defaults
+    target: Option<String>,
+    title: Option<String>,
+    body: Option<String>,
+    // Markup of the changelog when no target is given: markdown, discord, slack, html or plain
+    dialect: Option<String>,
+    // Changelog to render instead of the sample one
+    changelog: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+pub async fn start_api(config: SharedConfig, mut shutdown: watch::Receiver<bool>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     // Open the database
     let db_path = env::var("DB_PATH").unwrap_or_else(|_| "/github-ntfy".to_string());
     std::fs::create_dir_all(&db_path).ok();
@@ -60,6 +123,87 @@ pub async fn start_api() -> Result<(), Box<dyn std::error::Error + Send + Sync>>
                 .and(with_db(db.clone()))
                 .and_then(delete_docker_repo);
 
+            let export_watches = warp::path("export_watches")
+                .and(warp::get())
+                .and(warp::query::<FormatQuery>())
+                .and(with_db(db.clone()))
+                .and_then(export_watch_list);
+
+            let import_watches = warp::path("import_watches")
+                .and(warp::post())
+                .and(warp::query::<FormatQuery>())
+                .and(warp::body::bytes())
+                .and(with_db(db.clone()))
+                .and_then(import_watch_list);
+
+            let add_source = warp::path("app_source")
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(with_db(db.clone()))
+                .and_then(add_watch_source);
+
+            let get_sources = warp::path("watch_sources")
+                .and(warp::get())
+                .and(with_db(db.clone()))
+                .and_then(get_watch_sources);
+
+            let delete_source = warp::path("delete_source")
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(with_db(db.clone()))
+                .and_then(delete_watch_source);
+
+            let import_manifest = warp::path("import_manifest")
+                .and(warp::post())
+                .and(warp::query::<ManifestQuery>())
+                .and(warp::body::bytes())
+                .and(with_db(db.clone()))
+                .and_then(import_manifest_file);
+
+            let set_deployed = warp::path("deployed_version")
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(with_db(db.clone()))
+                .and_then(set_deployed_version);
+
+            let set_priority = warp::path("priority")
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(with_db(db.clone()))
+                .and_then(set_watch_priority);
+
+            let get_drift = warp::path("drift")
+                .and(warp::get())
+                .and(with_db(db.clone()))
+                .and_then(get_watch_drift);
+
+            let reload_config = warp::path!("admin" / "reload")
+                .and(warp::post())
+                .and(warp::header::optional::<String>("authorization"))
+                .and(with_config(config.clone()))
+                .and_then(reload_configuration);
+
+            let get_metrics = warp::path("metrics")
+                .and(warp::get())
+                .map(|| warp::reply::with_header(metrics::render(), "Content-Type", "text/plain; version=0.0.4"));
+
+            let healthz = warp::path("healthz")
+                .and(warp::get())
+                .and(with_config(config.clone()))
+                .and_then(get_health);
+
+            let readyz = warp::path("readyz")
+                .and(warp::get())
+                .and(with_db(db.clone()))
+                .and(with_config(config.clone()))
+                .and_then(get_readiness);
+
+            let preview_template = warp::path!("templates" / "preview")
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(with_config(config.clone()))
+                .and_then(preview_template);
+
             // Configure CORS
             let cors = warp::cors()
                 .allow_any_origin()
@@ -73,11 +217,28 @@ pub async fn start_api() -> Result<(), Box<dyn std::error::Error + Send + Sync>>
                 .or(get_docker)
                 .or(delete_github)
                 .or(delete_docker)
+                .or(export_watches)
+                .or(import_watches)
+                .or(add_source)
+                .or(get_sources)
+                .or(delete_source)
+                .or(import_manifest)
+                .or(set_deployed)
+                .or(set_priority)
+                .or(get_drift)
+                .or(reload_config)
+                .or(get_metrics)
+                .or(healthz)
+                .or(readyz)
+                .or(preview_template)
                 .with(cors);
 
-            // Start the server
-            info!("Starting API on 0.0.0.0:5000");
-            warp::serve(routes).run(([0, 0, 0, 0], 5000)).await;
+            // Start the server, in-flight requests are completed once a shutdown is requested
+            let (addr, server) = warp::serve(routes).try_bind_with_graceful_shutdown(([0, 0, 0, 0], 5000), async move {
+                let _ = shutdown.wait_for(|stop| *stop).await;
+            })?;
+            info!("Starting API on {}", addr);
+            server.await;
             Ok(())
         },
         Err(e) => {
@@ -91,6 +252,10 @@ fn with_db(db: Arc<Mutex<Connection>>) -> impl Filter<Extract = (Arc<Mutex<Conne
     warp::any().map(move || db.clone())
 }
 
+fn with_config(config: SharedConfig) -> impl Filter<Extract = (SharedConfig,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || config.clone())
+}
+
 async fn add_github_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
     let repo = body.repo;
 
@@ -101,7 +266,7 @@ async fn add_github_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Resul
         ));
     }
 
-    let mut db_guard = db.lock().await;
+    let db_guard = db.lock().await;
 
     // Check if repository already exists
     match db_guard.query_row(
@@ -153,7 +318,7 @@ async fn add_docker_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Resul
         ));
     }
 
-    let mut db_guard = db.lock().await;
+    let db_guard = db.lock().await;
 
     // Check if repository already exists
     match db_guard.query_row(
@@ -223,10 +388,8 @@ async fn get_github_repos(db: Arc<Mutex<Connection>>) -> Result<impl Reply, Reje
         };
 
         let mut repos = Vec::new();
-        for row in rows {
-            if let Ok(repo) = row {
-                repos.push(repo);
-            }
+        for repo in rows.flatten() {
+            repos.push(repo);
         }
 
         repos
@@ -266,10 +429,8 @@ async fn get_docker_repos(db: Arc<Mutex<Connection>>) -> Result<impl Reply, Reje
         };
 
         let mut repos = Vec::new();
-        for row in rows {
-            if let Ok(repo) = row {
-                repos.push(repo);
-            }
+        for repo in rows.flatten() {
+            repos.push(repo);
         }
 
         repos
@@ -291,7 +452,7 @@ async fn delete_github_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Re
         ));
     }
 
-    let mut db_guard = db.lock().await;
+    let db_guard = db.lock().await;
 
     // Check if repository exists
     match db_guard.query_row(
@@ -299,7 +460,7 @@ async fn delete_github_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Re
         params![repo],
         |row| row.get::<_, i64>(0)
     ) {
-        Ok(count) if count == 0 => {
+        Ok(0) => {
             return Ok(warp::reply::with_status(
                 warp::reply::json(&json!({"error": format!("GitHub repository {} is not in the database.", repo)})),
                 StatusCode::NOT_FOUND
@@ -343,7 +504,7 @@ async fn delete_docker_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Re
         ));
     }
 
-    let mut db_guard = db.lock().await;
+    let db_guard = db.lock().await;
 
     // Check if repository exists
     match db_guard.query_row(
@@ -351,7 +512,7 @@ async fn delete_docker_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Re
         params![repo],
         |row| row.get::<_, i64>(0)
     ) {
-        Ok(count) if count == 0 => {
+        Ok(0) => {
             return Ok(warp::reply::with_status(
                 warp::reply::json(&json!({"error": format!("Docker repository {} is not in the database.", repo)})),
                 StatusCode::NOT_FOUND
@@ -383,4 +544,464 @@ async fn delete_docker_repo(body: RepoRequest, db: Arc<Mutex<Connection>>) -> Re
             ))
         }
     }
-}
\ No newline at end of file
+}
+
+fn parse_format(query: &FormatQuery) -> Result<Format, String> {
+    match &query.format {
+        None => Ok(Format::Json),
+        Some(name) => Format::from_name(name)
+            .ok_or_else(|| format!("Unsupported format '{}', expected json, yaml or csv.", name)),
+    }
+}
+
+async fn export_watch_list(query: FormatQuery, db: Arc<Mutex<Connection>>) -> Result<Box<dyn Reply>, Rejection> {
+    let format = match parse_format(&query) {
+        Ok(format) => format,
+        Err(e) => {
+            return Ok(Box::new(warp::reply::with_status(
+                warp::reply::json(&json!({"error": e})),
+                StatusCode::BAD_REQUEST
+            )));
+        }
+    };
+
+    let db_guard = db.lock().await;
+
+    match watchlist::export_watches(&db_guard, format) {
+        Ok(export) => {
+            let reply = warp::reply::with_header(export.data, "Content-Type", format.content_type());
+            // CSV leaves the watch sources out, the header tells how many
+            Ok(Box::new(warp::reply::with_header(reply, "X-Skipped-Sources", export.skipped_sources.to_string())))
+        }
+        Err(e) => {
+            error!("Error while exporting watched repositories: {}", e);
+            Ok(Box::new(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Export error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            )))
+        }
+    }
+}
+
+async fn import_watch_list(query: FormatQuery, body: warp::hyper::body::Bytes, db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let format = match parse_format(&query) {
+        Ok(format) => format,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": e})),
+                StatusCode::BAD_REQUEST
+            ));
+        }
+    };
+
+    let data = match std::str::from_utf8(&body) {
+        Ok(data) => data,
+        Err(_) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "The request body must be valid UTF-8."})),
+                StatusCode::BAD_REQUEST
+            ));
+        }
+    };
+
+    let db_guard = db.lock().await;
+
+    match watchlist::import_watches(&db_guard, data, format) {
+        Ok(report) => {
+            info!("Imported {} watched repositories ({} duplicates, {} errors)",
+                  report.added.len(), report.duplicates.len(), report.errors.len());
+            Ok(warp::reply::with_status(
+                warp::reply::json(&report),
+                StatusCode::OK
+            ))
+        },
+        Err(e) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Unable to parse import file: {}", e)})),
+                StatusCode::BAD_REQUEST
+            ))
+        }
+    }
+}
+
+async fn add_watch_source(body: WatchSource, db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let source = WatchSource { name: body.name.trim().to_string(), ..body };
+
+    if let Err(e) = watchlist::validate_source(&source) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": format!("Invalid source: {}.", e)})),
+            StatusCode::BAD_REQUEST
+        ));
+    }
+
+    let db_guard = db.lock().await;
+
+    match database::source_exists(&db_guard, source.kind, &source.name) {
+        Ok(true) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Source {} is already in the database.", source.marker())})),
+                StatusCode::CONFLICT
+            ));
+        },
+        Err(e) => {
+            error!("Error while checking source: {}", e);
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ));
+        },
+        _ => {}
+    }
+
+    match database::add_watch_source(&db_guard, &source) {
+        Ok(id) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"id": id, "message": format!("Source {} has been added, its repositories will be synchronized on the next check.", source.marker())})),
+                StatusCode::OK
+            ))
+        },
+        Err(e) => {
+            error!("Error while adding source: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+async fn get_watch_sources(db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let db_guard = db.lock().await;
+
+    match database::get_watch_sources(&db_guard) {
+        Ok(sources) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&sources),
+                StatusCode::OK
+            ))
+        },
+        Err(e) => {
+            error!("Error while reading sources: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+async fn delete_watch_source(body: SourceIdRequest, db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let db_guard = db.lock().await;
+
+    match database::delete_watch_source(&db_guard, body.id) {
+        Ok(true) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"message": format!("Source {} and its repositories have been removed.", body.id)})),
+                StatusCode::OK
+            ))
+        },
+        Ok(false) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Source {} is not in the database.", body.id)})),
+                StatusCode::NOT_FOUND
+            ))
+        },
+        Err(e) => {
+            error!("Error while deleting source: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+async fn import_manifest_file(query: ManifestQuery, body: warp::hyper::body::Bytes, db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let client = reqwest::Client::new();
+
+    // The manifest is either uploaded in the body or downloaded from `url`
+    let content = match &query.url {
+        Some(url) => match client.get(url).send().await.and_then(|r| r.error_for_status()) {
+            Ok(response) => response.text().await.map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        },
+        None => String::from_utf8(body.to_vec()).map_err(|_| "The request body must be valid UTF-8.".to_string()),
+    };
+    let name = query.name.clone()
+        .or_else(|| query.url.as_deref().and_then(|u| u.rsplit('/').next()).map(str::to_string));
+
+    let (name, content) = match (name, content) {
+        (Some(name), Ok(content)) => (name, content),
+        (None, _) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "The 'name' parameter is required, e.g. ?name=Cargo.lock"})),
+                StatusCode::BAD_REQUEST
+            ));
+        },
+        (_, Err(e)) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Unable to read manifest: {}", e)})),
+                StatusCode::BAD_REQUEST
+            ));
+        }
+    };
+
+    let resolved = match manifest::resolve_manifest(&client, &name, &content).await {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Unable to parse manifest: {}", e)})),
+                StatusCode::BAD_REQUEST
+            ));
+        }
+    };
+
+    let db_guard = db.lock().await;
+
+    match manifest::apply_manifest(&db_guard, resolved) {
+        Ok(report) => {
+            info!("Imported manifest {}: {} added, {} updated, {} unresolved",
+                  name, report.added.len(), report.duplicates.len(), report.errors.len());
+            Ok(warp::reply::with_status(
+                warp::reply::json(&report),
+                StatusCode::OK
+            ))
+        },
+        Err(e) => {
+            error!("Error while importing manifest: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+async fn set_deployed_version(body: DeployedVersionRequest, db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let version = body.version.as_deref().map(str::trim).filter(|v| !v.is_empty());
+    let db_guard = db.lock().await;
+
+    match database::set_deployed_version(&db_guard, body.kind, &body.repo, version) {
+        Ok(true) => {
+            let message = match version {
+                Some(version) => format!("{} repository {} is now deployed in version {}.", body.kind.label(), body.repo, version),
+                None => format!("Deployed version of {} repository {} has been cleared.", body.kind.label(), body.repo),
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"message": message})),
+                StatusCode::OK
+            ))
+        },
+        Ok(false) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("{} repository {} is not in the database.", body.kind.label(), body.repo)})),
+                StatusCode::NOT_FOUND
+            ))
+        },
+        Err(e) => {
+            error!("Error while updating deployed version: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+async fn set_watch_priority(body: PriorityRequest, db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let priority = match body.priority.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+        Some(name) => match Priority::parse(name) {
+            Some(priority) => Some(priority),
+            None => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": format!("Unknown priority '{}', expected min, low, default, high or urgent.", name)})),
+                    StatusCode::BAD_REQUEST
+                ));
+            }
+        },
+        None => None,
+    };
+    let db_guard = db.lock().await;
+
+    match database::set_priority(&db_guard, body.kind, &body.repo, priority) {
+        Ok(true) => {
+            let message = match priority {
+                Some(priority) => format!("{} repository {} is now notified with priority {}.", body.kind.label(), body.repo, priority.as_str()),
+                None => format!("Priority of {} repository {} now follows the version bump.", body.kind.label(), body.repo),
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"message": message})),
+                StatusCode::OK
+            ))
+        },
+        Ok(false) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("{} repository {} is not in the database.", body.kind.label(), body.repo)})),
+                StatusCode::NOT_FOUND
+            ))
+        },
+        Err(e) => {
+            error!("Error while updating priority: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+async fn get_watch_drift(db: Arc<Mutex<Connection>>) -> Result<impl Reply, Rejection> {
+    let db_guard = db.lock().await;
+
+    match drift::watch_drifts(&db_guard) {
+        Ok(drifts) => {
+            Ok(warp::reply::with_status(
+                warp::reply::json(&drifts),
+                StatusCode::OK
+            ))
+        },
+        Err(e) => {
+            error!("Error while reading drift: {}", e);
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": format!("Database error: {}", e)})),
+                StatusCode::INTERNAL_SERVER_ERROR
+            ))
+        }
+    }
+}
+
+// Compares every byte whatever the first difference, so the response time does not tell how much of a guessed token is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+async fn reload_configuration(authorization: Option<String>, config: SharedConfig) -> Result<impl Reply, Rejection> {
+    // The endpoint is disabled without an admin token, SIGHUP still reloads the configuration
+    let Some(token) = config.current().admin_token.clone() else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "Set an admin token to reload the configuration from the API."})),
+            StatusCode::FORBIDDEN
+        ));
+    };
+    let expected = format!("Bearer {}", token);
+    if !authorization.is_some_and(|given| constant_time_eq(given.as_bytes(), expected.as_bytes())) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": "A valid admin token is required."})),
+            StatusCode::UNAUTHORIZED
+        ));
+    }
+
+    match config.reload().await {
+        Ok(config) => {
+            info!("Configuration reloaded from the API, notification targets: {}", config.describe_targets());
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"message": "Configuration reloaded.", "targets": config.describe_targets()})),
+                StatusCode::OK
+            ))
+        },
+        Err(e) => {
+            error!("Configuration reload rejected: {}", e.0.join("; "));
+            Ok(warp::reply::with_status(
+                warp::reply::json(&json!({"error": "Invalid configuration, the previous one is kept.", "details": e.0})),
+                StatusCode::BAD_REQUEST
+            ))
+        }
+    }
+}
+
+// Liveness: the poll loop is running and completes its cycles in time
+async fn get_health(config: SharedConfig) -> Result<impl Reply, Rejection> {
+    let poller = health::poller_status(config.current().timeout);
+    let status = if poller.alive && !poller.late { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({"status": if status == StatusCode::OK { "ok" } else { "failing" }, "poller": poller})),
+        status
+    ))
+}
+
+// Readiness: the database answers and at least one poll cycle completed
+async fn get_readiness(db: Arc<Mutex<Connection>>, config: SharedConfig) -> Result<impl Reply, Rejection> {
+    let database = {
+        let db_guard = db.lock().await;
+        db_guard.query_row("SELECT COUNT(*) FROM watched_repos", [], |row| row.get::<_, i64>(0))
+    };
+    let poller = health::poller_status(config.current().timeout);
+
+    let ready = database.is_ok() && poller.alive && poller.last_cycle.is_some();
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&json!({
+            "status": if ready { "ready" } else { "not ready" },
+            "database": match &database {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            },
+            "poller": poller,
+        })),
+        status
+    ))
+}
+
+// Render templates against sample data, so they can be checked before being put in the configuration
+async fn preview_template(request: PreviewRequest, config: SharedConfig) -> Result<impl Reply, Rejection> {
+    let config = config.current();
+    let (mut templates, dialect, max_len) = match &request.target {
+        Some(name) => match config.targets.iter().find(|t| &t.name == name) {
+            Some(target) => (target.templates.clone(), notifications::dialect(&target.kind), notifications::max_length(&target.kind)),
+            None => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": format!("Unknown target '{}'", name)})),
+                    StatusCode::NOT_FOUND
+                ));
+            }
+        },
+        None => match request.dialect.as_deref().map(Dialect::from_name) {
+            Some(None) => {
+                return Ok(warp::reply::with_status(
+                    warp::reply::json(&json!({"error": "Unsupported dialect, expected markdown, discord, slack, html or plain"})),
+                    StatusCode::BAD_REQUEST
+                ));
+            }
+            dialect => (Templates::default(), dialect.flatten().unwrap_or(Dialect::Markdown), None),
+        },
+    };
+
+    if request.title.is_some() || request.body.is_some() {
+        let base = templates.get(request.event).cloned().unwrap_or_default();
+        let custom = MessageTemplate {
+            title: request.title.or(base.title),
+            body: request.body.or(base.body),
+        };
+        templates.set(request.event, custom);
+    }
+
+    let mut context = MessageContext::sample(request.event);
+    if request.changelog.is_some() {
+        context.changelog = request.changelog;
+    }
+    match template::render(&templates, &context, dialect, max_len) {
+        Ok(message) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"title": message.title, "body": message.body, "variables": context})),
+            StatusCode::OK
+        )),
+        Err(e) => Ok(warp::reply::with_status(
+            warp::reply::json(&json!({"error": format!("Invalid template: {}", e)})),
+            StatusCode::BAD_REQUEST
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_equal_only_when_every_byte_is() {
+        assert!(constant_time_eq(b"Bearer s3cret", b"Bearer s3cret"));
+        assert!(!constant_time_eq(b"Bearer s3cret", b"Bearer s3creT"));
+        assert!(!constant_time_eq(b"Bearer s3cret", b"Bearer s3cre"));
+        assert!(!constant_time_eq(b"", b"Bearer s3cret"));
+    }
+}
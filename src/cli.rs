@@ -0,0 +1,346 @@
+use clap::{Args, Parser, Subcommand};
+use std::error::Error;
+use std::fs;
+use crate::config::Config;
+use crate::database;
+use crate::manifest;
+use crate::models::{Priority, WatchKind};
+use crate::notifications;
+use crate::watchlist::{self, Format, ImportReport};
+
+#[derive(Parser)]
+#[command(name = "github-ntfy", version, about = "Get notified about new GitHub releases and Docker image updates")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the notifier and the API (default)
+    Serve,
+    /// Run a single poll cycle, then exit
+    Once,
+    #[command(flatten)]
+    Admin(AdminCommand),
+}
+
+// Commands working on the databases and the configuration, run by `run`
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Manage watched GitHub repositories and Docker images
+    #[command(subcommand)]
+    Watch(WatchCommand),
+    /// Inspect or reset the last notified versions
+    #[command(subcommand)]
+    State(StateCommand),
+    /// Send notifications by hand
+    #[command(subcommand)]
+    Notify(NotifyCommand),
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Export the watch list
+    Export {
+        #[arg(short, long, value_parser = parse_format)]
+        format: Option<Format>,
+        /// Output file, standard output when omitted
+        file: Option<String>,
+    },
+    /// Import a watch list
+    Import {
+        #[arg(short, long, value_parser = parse_format)]
+        format: Option<Format>,
+        file: String,
+    },
+    /// Watch the dependencies of a Cargo.lock, package-lock.json, go.mod or docker-compose.yml
+    ImportManifest {
+        file: String,
+    },
+    /// Record the version actually running, no VERSION clears it
+    Deployed {
+        #[command(flatten)]
+        kind: KindArg,
+        repo: String,
+        version: Option<String>,
+    },
+    /// Set the priority of the notifications of a watch, no PRIORITY derives it from the version bump again
+    Priority {
+        #[command(flatten)]
+        kind: KindArg,
+        repo: String,
+        /// min, low, default, high or urgent
+        #[arg(value_parser = parse_priority)]
+        priority: Option<Priority>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WatchCommand {
+    /// Watch a repository (owner/name) or a Docker image with --docker
+    Add {
+        #[command(flatten)]
+        kind: KindArg,
+        repo: String,
+    },
+    /// List watched repositories and images
+    List,
+    /// Stop watching a repository or a Docker image with --docker
+    Remove {
+        #[command(flatten)]
+        kind: KindArg,
+        repo: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StateCommand {
+    /// Show the last notified version of a repository, or of all of them
+    Show {
+        repo: Option<String>,
+    },
+    /// Forget the last notified version so the current release is notified again
+    Reset {
+        repo: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NotifyCommand {
+    /// Send a test notification to a configured target
+    Test {
+        target: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and list the notification targets
+    Check,
+}
+
+#[derive(Args)]
+pub struct KindArg {
+    /// Docker image instead of a GitHub repository
+    #[arg(long)]
+    docker: bool,
+}
+
+impl KindArg {
+    fn kind(&self) -> WatchKind {
+        if self.docker { WatchKind::Docker } else { WatchKind::Github }
+    }
+}
+
+fn parse_format(name: &str) -> Result<Format, String> {
+    Format::from_name(name).ok_or_else(|| format!("unsupported format '{}', expected json, yaml or csv", name))
+}
+
+fn parse_priority(name: &str) -> Result<Priority, String> {
+    Priority::parse(name).ok_or_else(|| format!("unknown priority '{}', expected min, low, default, high or urgent", name))
+}
+
+pub async fn run(command: AdminCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        AdminCommand::Watch(WatchCommand::Add { kind, repo }) => watch_add(kind.kind(), &repo),
+        AdminCommand::Watch(WatchCommand::List) => watch_list(),
+        AdminCommand::Watch(WatchCommand::Remove { kind, repo }) => watch_remove(kind.kind(), &repo),
+        AdminCommand::State(StateCommand::Show { repo }) => state_show(repo.as_deref()),
+        AdminCommand::State(StateCommand::Reset { repo }) => state_reset(&repo),
+        AdminCommand::Notify(NotifyCommand::Test { target }) => notify_test(&target).await,
+        AdminCommand::Config(ConfigCommand::Check) => config_check(),
+        AdminCommand::Export { format, file } => export(format, file),
+        AdminCommand::Import { format, file } => import(format, &file),
+        AdminCommand::ImportManifest { file } => import_manifest(&file).await,
+        AdminCommand::Deployed { kind, repo, version } => deployed(kind.kind(), &repo, version.as_deref()),
+        AdminCommand::Priority { kind, repo, priority } => priority_set(kind.kind(), &repo, priority),
+    }
+}
+
+fn watch_add(kind: WatchKind, repo: &str) -> Result<(), Box<dyn Error>> {
+    watchlist::validate_repo(kind, repo)?;
+
+    let (_, conn_repos) = database::init_databases()?;
+    if database::is_watched(&conn_repos, kind, repo)? {
+        println!("{} repository {} is already watched", kind.label(), repo);
+        return Ok(());
+    }
+
+    database::add_watch(&conn_repos, kind, repo)?;
+    println!("{} repository {} added", kind.label(), repo);
+    Ok(())
+}
+
+fn watch_list() -> Result<(), Box<dyn Error>> {
+    let (_, conn_repos) = database::init_databases()?;
+
+    for repo in database::get_watched_repos(&conn_repos)? {
+        println!("github  {}", repo);
+    }
+    for repo in database::get_docker_watched_repos(&conn_repos)? {
+        println!("docker  {}", repo);
+    }
+    Ok(())
+}
+
+fn watch_remove(kind: WatchKind, repo: &str) -> Result<(), Box<dyn Error>> {
+    let (_, conn_repos) = database::init_databases()?;
+    if !database::is_watched(&conn_repos, kind, repo)? {
+        return Err(format!("{} repository {} is not watched", kind.label(), repo).into());
+    }
+
+    database::remove_watch(&conn_repos, kind, repo)?;
+    println!("{} repository {} removed", kind.label(), repo);
+    Ok(())
+}
+
+fn state_show(repo: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (conn_versions, _) = database::init_databases()?;
+    let versions: Vec<(String, String)> = database::get_versions(&conn_versions)?
+        .into_iter()
+        .filter(|(name, _)| repo.is_none_or(|repo| repo == name))
+        .collect();
+
+    if let (Some(repo), true) = (repo, versions.is_empty()) {
+        return Err(format!("No version recorded for {}", repo).into());
+    }
+    for (repo, version) in versions {
+        println!("{:<40} {}", repo, version);
+    }
+    Ok(())
+}
+
+fn state_reset(repo: &str) -> Result<(), Box<dyn Error>> {
+    let (conn_versions, _) = database::init_databases()?;
+    if !database::reset_version(&conn_versions, repo)? {
+        return Err(format!("No version recorded for {}", repo).into());
+    }
+
+    println!("State of {} reset, its latest release will be notified on the next cycle", repo);
+    Ok(())
+}
+
+async fn notify_test(name: &str) -> Result<(), Box<dyn Error>> {
+    let config = Config::load()?;
+    let target = config.targets.iter()
+        .find(|target| target.name == name)
+        .ok_or_else(|| format!("Unknown target '{}', configured targets: {}", name, config.describe_targets()))?;
+
+    let sent = notifications::send_alert(
+        target.clone(),
+        "github-ntfy test".to_string(),
+        format!("✅ *Test notification*: target {} ({}) is working", target.name, target.kind.service()),
+        "https://github.com/BreizhHardware/ntfy_alerts".to_string(),
+        None,
+    ).await;
+
+    if sent {
+        println!("Test notification sent to {}", target.name);
+        Ok(())
+    } else {
+        Err(format!("Test notification to {} failed", target.name).into())
+    }
+}
+
+fn config_check() -> Result<(), Box<dyn Error>> {
+    let config = Config::load()?;
+
+    match Config::file_path() {
+        Some(path) => println!("Configuration file {} is valid", path),
+        None => println!("Configuration from the environment is valid"),
+    }
+    println!("Notification targets: {}", config.describe_targets());
+    if let Some(broker) = &config.mqtt {
+        println!("Home Assistant update entities: {}:{}", broker.host, broker.port);
+    }
+    Ok(())
+}
+
+fn export(format: Option<Format>, file: Option<String>) -> Result<(), Box<dyn Error>> {
+    let format = format
+        .or_else(|| file.as_deref().and_then(Format::from_path))
+        .unwrap_or(Format::Json);
+
+    let (_, conn_repos) = database::init_databases()?;
+    let export = watchlist::export_watches(&conn_repos, format)?;
+
+    match file {
+        Some(path) => fs::write(&path, export.data)?,
+        None => print!("{}", export.data),
+    }
+    if export.skipped_sources > 0 {
+        eprintln!("{} watch sources were not exported, CSV only holds watches, use JSON or YAML to keep them", export.skipped_sources);
+    }
+
+    Ok(())
+}
+
+fn print_report(report: &ImportReport, updated_label: &str) {
+    for repo in &report.added {
+        println!("added      {}", repo);
+    }
+    for repo in &report.duplicates {
+        println!("{:<10} {}", updated_label, repo);
+    }
+    for error in &report.errors {
+        eprintln!("error      entry {} ({}): {}", error.entry, error.repo, error.error);
+    }
+    println!("{} added, {} {}, {} errors",
+             report.added.len(), report.duplicates.len(), updated_label, report.errors.len());
+}
+
+fn import(format: Option<Format>, file: &str) -> Result<(), Box<dyn Error>> {
+    let format = format
+        .or_else(|| Format::from_path(file))
+        .ok_or("Unable to guess the file format, use --format")?;
+
+    let data = fs::read_to_string(file)?;
+    let (_, conn_repos) = database::init_databases()?;
+    let report = watchlist::import_watches(&conn_repos, &data, format)?;
+
+    print_report(&report, "duplicate");
+
+    if report.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} entries could not be imported", report.errors.len()).into())
+    }
+}
+
+async fn import_manifest(file: &str) -> Result<(), Box<dyn Error>> {
+    let data = fs::read_to_string(file)?;
+    let client = reqwest::Client::new();
+    let resolved = manifest::resolve_manifest(&client, file, &data).await.map_err(|e| e.to_string())?;
+
+    let (_, conn_repos) = database::init_databases()?;
+    let report = manifest::apply_manifest(&conn_repos, resolved)?;
+    print_report(&report, "updated");
+
+    Ok(())
+}
+
+fn deployed(kind: WatchKind, repo: &str, version: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let (_, conn_repos) = database::init_databases()?;
+    if !database::set_deployed_version(&conn_repos, kind, repo, version)? {
+        return Err(format!("{} repository {} is not watched", kind.label(), repo).into());
+    }
+
+    match version {
+        Some(version) => println!("{} is deployed in version {}", repo, version),
+        None => println!("Deployed version of {} cleared", repo),
+    }
+    Ok(())
+}
+
+fn priority_set(kind: WatchKind, repo: &str, priority: Option<Priority>) -> Result<(), Box<dyn Error>> {
+    let (_, conn_repos) = database::init_databases()?;
+    if !database::set_priority(&conn_repos, kind, repo, priority)? {
+        return Err(format!("{} repository {} is not watched", kind.label(), repo).into());
+    }
+
+    match priority {
+        Some(priority) => println!("{} is notified with priority {}", repo, priority.as_str()),
+        None => println!("Priority of {} follows the version bump", repo),
+    }
+    Ok(())
+}
@@ -1,59 +1,1009 @@
 use dotenv::dotenv;
 use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::fs::File;
-use std::io::Read;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
 use crate::docker::create_dockerhub_token;
+use crate::models::WatchKind;
+use crate::mqtt;
+use crate::notifications::apprise;
+use crate::notifications::email::{self, RecipientRoute, Security};
+use crate::notifications::gotify;
+use crate::notifications::ntfy::Priorities;
+use crate::notifications::telegram::{Chat, ChatId, ParseMode};
+use crate::notifications::{nats, pushover, redis_stream, webhook};
+use crate::notifications::template::{self, Templates};
+
+// Notification target and the settings of its service
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TargetKind {
+    Ntfy {
+        url: String,
+        // Base64 encoded username:password, the global credentials are used when neither auth nor token is set
+        auth: Option<String>,
+        // Access token, replaces the username and password
+        token: Option<String>,
+        // Address the notifications are also forwarded to by the ntfy server
+        email: Option<String>,
+        // Delivery delay of releases, e.g. "30m" or "tomorrow, 9am"
+        delay: Option<String>,
+        #[serde(default)]
+        priorities: Priorities,
+    },
+    Gotify {
+        url: String,
+        token: String,
+        #[serde(default)]
+        priorities: gotify::Priorities,
+    },
+    Discord {
+        webhook_url: String,
+    },
+    // Either an incoming webhook, or a bot token and a default channel for the Web API
+    Slack {
+        webhook_url: Option<String>,
+        bot_token: Option<String>,
+        channel: Option<String>,
+        // Web API base URL, to go through a proxy or a mock
+        api_url: Option<String>,
+        // Channels of the repositories matching each route, the first matching one wins
+        #[serde(default)]
+        channels: Vec<ChannelRoute>,
+    },
+    // Incoming webhook of a Teams workflow
+    Teams {
+        webhook_url: String,
+    },
+    // Bot API, messages go to every chat (and forum topic) listed
+    Telegram {
+        bot_token: String,
+        chats: Vec<Chat>,
+        #[serde(default)]
+        parse_mode: ParseMode,
+        api_url: Option<String>,
+    },
+    // SMTP server, each message goes to `to` and to the recipients of the routes matching the repository
+    Email {
+        host: String,
+        port: Option<u16>,
+        #[serde(default)]
+        security: Security,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        #[serde(default)]
+        to: Vec<String>,
+        #[serde(default)]
+        cc: Vec<String>,
+        #[serde(default)]
+        bcc: Vec<String>,
+        #[serde(default)]
+        recipients: Vec<RecipientRoute>,
+    },
+    // Message API, with the user or group key of the recipients and the token of the application
+    Pushover {
+        user_key: String,
+        token: String,
+        device: Option<String>,
+        priority: Option<i8>,
+        api_url: Option<String>,
+    },
+    // Release events published to a NATS subject and acknowledged by JetStream, core NATS (`jetstream: false`) is fire-and-forget
+    Nats {
+        url: String,
+        subject: String,
+        token: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+        #[serde(default = "default_jetstream")]
+        jetstream: bool,
+    },
+    // Release events appended to a Redis stream
+    Redis {
+        url: String,
+        stream: String,
+        max_len: Option<usize>,
+    },
+    // Versioned JSON events posted to any URL, signed with the secret when there is one
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        secret: Option<String>,
+    },
+    // Client-server API of a homeserver, rooms are ids (!id:server) or aliases (#alias:server)
+    Matrix {
+        homeserver: String,
+        access_token: String,
+        rooms: Vec<String>,
+    },
+}
+
+fn default_jetstream() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelRoute {
+    pub repos: Patterns,
+    pub channel: String,
+}
+
+// Repository patterns, compiled once when the configuration is loaded.
+// Invalid ones never match, validation reports them from the patterns as written.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(from = "Vec<String>")]
+pub struct Patterns {
+    written: Vec<String>,
+    compiled: Vec<glob::Pattern>,
+}
+
+impl From<Vec<String>> for Patterns {
+    fn from(written: Vec<String>) -> Self {
+        let compiled = written.iter().filter_map(|p| glob::Pattern::new(p).ok()).collect();
+        Patterns { written, compiled }
+    }
+}
+
+impl Patterns {
+    pub fn matches(&self, repo: &str) -> bool {
+        self.compiled.iter().any(|p| p.matches(repo))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.written.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        self.written.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Patterns {
+    type Item = &'a String;
+    type IntoIter = std::slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl TargetKind {
+    pub fn service(&self) -> &'static str {
+        match self {
+            TargetKind::Ntfy { .. } => "ntfy",
+            TargetKind::Gotify { .. } => "gotify",
+            TargetKind::Discord { .. } => "discord",
+            TargetKind::Slack { .. } => "slack",
+            TargetKind::Teams { .. } => "teams",
+            TargetKind::Matrix { .. } => "matrix",
+            TargetKind::Telegram { .. } => "telegram",
+            TargetKind::Email { .. } => "email",
+            TargetKind::Webhook { .. } => "webhook",
+            TargetKind::Pushover { .. } => "pushover",
+            TargetKind::Nats { .. } => "nats",
+            TargetKind::Redis { .. } => "redis",
+        }
+    }
+
+    fn urls(&self) -> Vec<&str> {
+        match self {
+            TargetKind::Ntfy { url, .. } | TargetKind::Gotify { url, .. } | TargetKind::Webhook { url, .. } => vec![url],
+            TargetKind::Discord { webhook_url } | TargetKind::Teams { webhook_url } => vec![webhook_url],
+            TargetKind::Matrix { homeserver, .. } => vec![homeserver],
+            TargetKind::Telegram { api_url, .. } | TargetKind::Pushover { api_url, .. } => api_url.iter().map(String::as_str).collect(),
+            // Checked by their own client, they are not http URLs
+            TargetKind::Email { .. } | TargetKind::Nats { .. } | TargetKind::Redis { .. } => Vec::new(),
+            TargetKind::Slack { webhook_url, api_url, .. } => webhook_url.iter().chain(api_url).map(String::as_str).collect(),
+        }
+    }
+
+    // Apply the settings of the legacy environment variables: required fields are replaced, optional ones only when
+    // set, and the settings the variables cannot express (priorities, channels, headers...) keep their file value.
+    // Email security and NATS JetStream are set by the caller, their default cannot be told from an unset variable.
+    fn override_with(&mut self, env: TargetKind) {
+        fn set<T>(field: &mut Option<T>, value: Option<T>) {
+            if value.is_some() {
+                *field = value;
+            }
+        }
+        match (self, env) {
+            (TargetKind::Ntfy { url, token, .. }, TargetKind::Ntfy { url: env_url, token: env_token, .. }) => {
+                *url = env_url;
+                set(token, env_token);
+            }
+            (TargetKind::Gotify { url, token, .. }, TargetKind::Gotify { url: env_url, token: env_token, .. }) => {
+                *url = env_url;
+                *token = env_token;
+            }
+            (
+                TargetKind::Slack { webhook_url, bot_token, channel, .. },
+                TargetKind::Slack { webhook_url: env_webhook_url, bot_token: env_bot_token, channel: env_channel, .. },
+            ) => {
+                set(webhook_url, env_webhook_url);
+                set(bot_token, env_bot_token);
+                set(channel, env_channel);
+            }
+            (TargetKind::Telegram { bot_token, chats, .. }, TargetKind::Telegram { bot_token: env_bot_token, chats: env_chats, .. }) => {
+                *bot_token = env_bot_token;
+                *chats = env_chats;
+            }
+            (
+                TargetKind::Email { host, port, username, password, from, to, .. },
+                TargetKind::Email { host: env_host, port: env_port, username: env_username, password: env_password, from: env_from, to: env_to, .. },
+            ) => {
+                *host = env_host;
+                set(port, env_port);
+                set(username, env_username);
+                set(password, env_password);
+                *from = env_from;
+                *to = env_to;
+            }
+            (TargetKind::Pushover { user_key, token, .. }, TargetKind::Pushover { user_key: env_user_key, token: env_token, .. }) => {
+                *user_key = env_user_key;
+                *token = env_token;
+            }
+            (
+                TargetKind::Nats { url, subject, token, username, password, .. },
+                TargetKind::Nats { url: env_url, subject: env_subject, token: env_token, username: env_username, password: env_password, .. },
+            ) => {
+                *url = env_url;
+                *subject = env_subject;
+                set(token, env_token);
+                set(username, env_username);
+                set(password, env_password);
+            }
+            (TargetKind::Redis { url, stream, max_len }, TargetKind::Redis { url: env_url, stream: env_stream, max_len: env_max_len }) => {
+                *url = env_url;
+                *stream = env_stream;
+                set(max_len, env_max_len);
+            }
+            (TargetKind::Webhook { url, secret, .. }, TargetKind::Webhook { url: env_url, secret: env_secret, .. }) => {
+                *url = env_url;
+                set(secret, env_secret);
+            }
+            // Discord, Teams and Matrix have no other settings, and a target of another service is replaced
+            (kind, env) => *kind = env,
+        }
+    }
+
+    // Slack channel to post the messages of a repository in
+    pub fn slack_channel(&self, repo: &str) -> Option<&str> {
+        match self {
+            TargetKind::Slack { channel, channels, .. } => channels.iter()
+                .find(|route| route.repos.matches(repo))
+                .map(|route| route.channel.as_str())
+                .or(channel.as_deref()),
+            _ => None,
+        }
+    }
+
+    // Email addresses to send the messages of a repository to
+    pub fn email_recipients(&self, repo: &str) -> Vec<&str> {
+        match self {
+            TargetKind::Email { to, recipients, .. } => {
+                let mut addresses: Vec<&str> = to.iter().map(String::as_str).collect();
+                for address in recipients.iter().filter(|route| route.repos.matches(repo)).flat_map(|route| &route.to) {
+                    if !addresses.contains(&address.as_str()) {
+                        addresses.push(address);
+                    }
+                }
+                addresses
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "TargetEntry")]
+pub struct Target {
+    pub name: String,
+    pub kind: TargetKind,
+    // Routing rules: repository patterns to include and exclude, and the kinds of watch to notify
+    #[serde(default)]
+    pub repos: Patterns,
+    #[serde(default)]
+    pub exclude: Patterns,
+    #[serde(default)]
+    pub events: Vec<WatchKind>,
+    // Message templates for each kind of event
+    #[serde(default)]
+    pub templates: Templates,
+    // Send changelogs that do not fit in one message as follow-ups (thread or attachment)
+    #[serde(default)]
+    pub split_changelog: bool,
+}
+
+// Target as written in the file: the settings of its service, or an Apprise-style URL without a type
+#[derive(Deserialize)]
+struct TargetEntry {
+    name: String,
+    #[serde(default)]
+    repos: Patterns,
+    #[serde(default)]
+    exclude: Patterns,
+    #[serde(default)]
+    events: Vec<WatchKind>,
+    #[serde(default)]
+    templates: Templates,
+    #[serde(default)]
+    split_changelog: bool,
+    #[serde(flatten)]
+    settings: serde_json::Map<String, serde_json::Value>,
+}
+
+impl TryFrom<TargetEntry> for Target {
+    type Error = String;
+
+    fn try_from(entry: TargetEntry) -> Result<Self, Self::Error> {
+        let kind = match (entry.settings.contains_key("type"), entry.settings.get("url")) {
+            (false, Some(serde_json::Value::String(url))) => {
+                if let Some(key) = entry.settings.keys().find(|key| *key != "url") {
+                    return Err(format!("target '{}': '{}' needs a 'type', the URL holds the settings", entry.name, key));
+                }
+                apprise::parse(url).map_err(|e| format!("target '{}': {}", entry.name, e))?
+            }
+            _ => serde_json::from_value(serde_json::Value::Object(entry.settings))
+                .map_err(|e| format!("target '{}': {}", entry.name, e))?,
+        };
+        Ok(Target {
+            name: entry.name,
+            kind,
+            repos: entry.repos,
+            exclude: entry.exclude,
+            events: entry.events,
+            templates: entry.templates,
+            split_changelog: entry.split_changelog,
+        })
+    }
+}
+
+impl Target {
+    fn new(name: &str, kind: TargetKind) -> Self {
+        Target { name: name.to_string(), kind, repos: Patterns::default(), exclude: Patterns::default(), events: Vec::new(), templates: Templates::default(), split_changelog: false }
+    }
+
+    pub fn accepts(&self, kind: WatchKind, repo: &str) -> bool {
+        (self.events.is_empty() || self.events.contains(&kind))
+            && (self.repos.is_empty() || self.repos.matches(repo))
+            && !self.exclude.matches(repo)
+    }
+}
+
+// Settings overridden for a single repository
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RepoOverride {
+    pub targets: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DockerSection {
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DriftSection {
+    major: Option<u64>,
+    minor: Option<u64>,
+    days: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MqttSection {
+    host: Option<String>,
+    port: Option<u16>,
+    username: Option<String>,
+    password: Option<String>,
+    tls: Option<bool>,
+    client_id: Option<String>,
+    discovery_prefix: Option<String>,
+    topic_prefix: Option<String>,
+}
+
+// Content of the configuration file, every value can also come from the environment
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    github_token: Option<String>,
+    auth: Option<String>,
+    timeout: Option<f64>,
+    sources_refresh: Option<f64>,
+    admin_token: Option<String>,
+    #[serde(default)]
+    docker: DockerSection,
+    #[serde(default)]
+    drift: DriftSection,
+    #[serde(default)]
+    mqtt: MqttSection,
+    #[serde(default)]
+    targets: Vec<Target>,
+    #[serde(default)]
+    repos: HashMap<String, RepoOverride>,
+}
+
+// Every problem found while loading the configuration
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Incorrect configuration:")?;
+        for error in &self.0 {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    fn single(message: String) -> Self {
+        ConfigError(vec![message])
+    }
+}
 
 // Configuration
+#[derive(Clone)]
 pub struct Config {
     pub github_token: Option<String>,
     pub docker_username: Option<String>,
     pub docker_password: Option<String>,
     pub docker_token: Option<String>,
-    pub ntfy_url: Option<String>,
-    pub gotify_url: Option<String>,
-    pub gotify_token: Option<String>,
-    pub discord_webhook_url: Option<String>,
-    pub slack_webhook_url: Option<String>,
-    pub auth: String,
+    pub targets: Vec<Target>,
+    pub repos: HashMap<String, RepoOverride>,
     pub timeout: f64,
+    pub sources_refresh: f64,
+    pub drift_major: Option<u64>,
+    pub drift_minor: Option<u64>,
+    pub drift_days: Option<i64>,
+    pub admin_token: Option<String>,
+    // Broker of the Home Assistant update entities, if any
+    pub mqtt: Option<mqtt::Broker>,
+}
+
+// Replace `${VAR}` and `${VAR:-default}` with the value of the environment variable.
+// Only string values are expanded, after parsing, so a value cannot change the structure of the file.
+fn expand(text: &str, key: &str, errors: &mut Vec<String>) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            errors.push(format!("{}: unterminated '${{' in '{}'", key, text));
+            return output;
+        };
+        let expression = &rest[start + 2..start + end];
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        match (env::var(name), default) {
+            (Ok(value), _) => output.push_str(&value),
+            (Err(_), Some(default)) => output.push_str(default),
+            (Err(_), None) => errors.push(format!("{}: environment variable {} is not set", key, name)),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn child_key(parent: &str, name: &str) -> String {
+    if parent.is_empty() { name.to_string() } else { format!("{}.{}", parent, name) }
+}
+
+fn interpolate_toml(value: &mut toml::Value, key: &str, errors: &mut Vec<String>) {
+    match value {
+        toml::Value::String(text) => *text = expand(text, key, errors),
+        toml::Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                interpolate_toml(item, &format!("{}[{}]", key, index), errors);
+            }
+        }
+        toml::Value::Table(table) => {
+            for (name, item) in table.iter_mut() {
+                interpolate_toml(item, &child_key(key, name), errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn interpolate_yaml(value: &mut serde_yaml::Value, key: &str, errors: &mut Vec<String>) {
+    match value {
+        serde_yaml::Value::String(text) => *text = expand(text, key, errors),
+        serde_yaml::Value::Sequence(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                interpolate_yaml(item, &format!("{}[{}]", key, index), errors);
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (name, item) in mapping.iter_mut() {
+                interpolate_yaml(item, &child_key(key, name.as_str().unwrap_or("?")), errors);
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => interpolate_yaml(&mut tagged.value, key, errors),
+        _ => {}
+    }
+}
+
+fn parse_file(path: &str) -> Result<FileConfig, ConfigError> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| ConfigError::single(format!("{}: unable to read configuration file: {}", path, e)))?;
+    let mut errors = Vec::new();
+
+    let extension = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("");
+    let file = match extension {
+        "toml" => {
+            let mut value: toml::Value = toml::from_str(&content)
+                .map_err(|e| ConfigError::single(format!("{}: {}", path, e.to_string().trim_end())))?;
+            interpolate_toml(&mut value, "", &mut errors);
+            value.try_into::<FileConfig>()
+                .map_err(|e| ConfigError::single(format!("{}: {}", path, e.to_string().trim_end().replace('\n', " "))))
+        }
+        "yaml" | "yml" => {
+            let mut value: serde_yaml::Value = serde_yaml::from_str(&content)
+                .map_err(|e| ConfigError::single(format!("{}: {}", path, e)))?;
+            interpolate_yaml(&mut value, "", &mut errors);
+            serde_yaml::from_value::<FileConfig>(value)
+                .map_err(|e| ConfigError::single(format!("{}: {}", path, e)))
+        }
+        _ => Err(ConfigError::single(format!("{}: unknown configuration format, expected .toml, .yaml or .yml", path))),
+    };
+
+    if !errors.is_empty() {
+        return Err(ConfigError(errors.into_iter().map(|e| format!("{}: {}", path, e)).collect()));
+    }
+    file
+}
+
+// Target of a legacy environment variable. A file target with the same name keeps its settings,
+// only the fields the variables name are overridden (see `TargetKind::override_with`).
+fn set_target<'a>(targets: &'a mut Vec<Target>, name: &str, kind: TargetKind) -> &'a mut TargetKind {
+    match targets.iter().position(|t| t.name == name) {
+        Some(index) => {
+            targets[index].kind.override_with(kind);
+            &mut targets[index].kind
+        }
+        None => {
+            targets.push(Target::new(name, kind));
+            &mut targets.last_mut().unwrap().kind
+        }
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str, errors: &mut Vec<String>) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.trim().parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            errors.push(format!("{}: '{}' is not a valid value", name, value));
+            None
+        }
+    }
 }
 
 impl Config {
-    pub fn from_env() -> Self {
+    // Configuration file named by GHNTFY_CONFIG, if any
+    pub fn file_path() -> Option<String> {
+        env::var("GHNTFY_CONFIG").ok().filter(|p| !p.is_empty())
+    }
+
+    // Load the configuration file, apply the environment on top of it and validate the result
+    pub fn load() -> Result<Self, ConfigError> {
         dotenv().ok();
 
-        let docker_username = env::var("DOCKER_USERNAME").ok();
-        let docker_password = env::var("DOCKER_PASSWORD").ok();
-        let docker_token = if let (Some(username), Some(password)) = (&docker_username, &docker_password) {
-            create_dockerhub_token(username, password)
-        } else {
-            None
+        let file = match Config::file_path() {
+            Some(path) => parse_file(&path)?,
+            None => FileConfig::default(),
         };
+        let mut errors = Vec::new();
 
         // Read authentication file
-        let mut auth = String::new();
-        if let Ok(mut file) = File::open("/auth.txt") {
-            file.read_to_string(&mut auth).ok();
-            auth = auth.trim().to_string();
-        }
-
-        Config {
-            github_token: env::var("GHNTFY_TOKEN").ok(),
-            docker_username,
-            docker_password,
-            docker_token,
-            ntfy_url: env::var("NTFY_URL").ok(),
-            gotify_url: env::var("GOTIFY_URL").ok(),
-            gotify_token: env::var("GOTIFY_TOKEN").ok(),
-            discord_webhook_url: env::var("DISCORD_WEBHOOK_URL").ok(),
-            slack_webhook_url: env::var("SLACK_WEBHOOK_URL").ok(),
-            auth,
-            timeout: env::var("GHNTFY_TIMEOUT")
-                .unwrap_or_else(|_| "3600".to_string())
-                .parse()
-                .unwrap_or(3600.0),
+        let auth = env::var("GHNTFY_AUTH").ok()
+            .or_else(|| fs::read_to_string("/auth.txt").ok())
+            .or(file.auth)
+            .map(|a| a.trim().to_string())
+            .unwrap_or_default();
+
+        let mut targets = file.targets;
+
+        // Legacy environment variables define one target per service, or override the fields they name in the file target
+        if let Ok(url) = env::var("NTFY_URL") {
+            set_target(&mut targets, "ntfy", TargetKind::Ntfy { url, auth: None, token: env::var("NTFY_TOKEN").ok(), email: None, delay: None, priorities: Priorities::default() });
+        }
+        if let (Ok(url), Ok(token)) = (env::var("GOTIFY_URL"), env::var("GOTIFY_TOKEN")) {
+            set_target(&mut targets, "gotify", TargetKind::Gotify { url, token, priorities: gotify::Priorities::default() });
+        }
+        if let Ok(webhook_url) = env::var("DISCORD_WEBHOOK_URL") {
+            set_target(&mut targets, "discord", TargetKind::Discord { webhook_url });
+        }
+        if let Ok(webhook_url) = env::var("SLACK_WEBHOOK_URL") {
+            set_target(&mut targets, "slack", TargetKind::Slack { webhook_url: Some(webhook_url), bot_token: None, channel: None, api_url: None, channels: Vec::new() });
+        }
+        if let Ok(webhook_url) = env::var("TEAMS_WEBHOOK_URL") {
+            set_target(&mut targets, "teams", TargetKind::Teams { webhook_url });
+        }
+        if let (Ok(homeserver), Ok(access_token), Ok(rooms)) =
+            (env::var("MATRIX_HOMESERVER"), env::var("MATRIX_ACCESS_TOKEN"), env::var("MATRIX_ROOMS"))
+        {
+            let rooms = rooms.split(',').map(str::trim).filter(|r| !r.is_empty()).map(String::from).collect();
+            set_target(&mut targets, "matrix", TargetKind::Matrix { homeserver, access_token, rooms });
+        }
+        if let (Ok(bot_token), Ok(chat_ids)) = (env::var("TELEGRAM_BOT_TOKEN"), env::var("TELEGRAM_CHAT_IDS")) {
+            let mut chats = Vec::new();
+            for chat in chat_ids.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                match Chat::parse(chat) {
+                    Some(chat) => chats.push(chat),
+                    None => errors.push(format!("TELEGRAM_CHAT_IDS: invalid chat '{}', expected an id, @channel or chat:topic", chat)),
+                }
+            }
+            set_target(&mut targets, "telegram", TargetKind::Telegram { bot_token, chats, parse_mode: ParseMode::default(), api_url: None });
+        }
+        if let (Ok(host), Ok(from), Ok(to)) = (env::var("SMTP_HOST"), env::var("SMTP_FROM"), env::var("SMTP_TO")) {
+            let port = env_parse("SMTP_PORT", &mut errors);
+            let env_security = env_parse("SMTP_SECURITY", &mut errors);
+            let kind = set_target(&mut targets, "email", TargetKind::Email {
+                host,
+                port,
+                security: Security::default(),
+                username: env::var("SMTP_USERNAME").ok(),
+                password: env::var("SMTP_PASSWORD").ok(),
+                from,
+                to: to.split(',').map(str::trim).filter(|a| !a.is_empty()).map(String::from).collect(),
+                cc: Vec::new(),
+                bcc: Vec::new(),
+                recipients: Vec::new(),
+            });
+            if let (TargetKind::Email { security, .. }, Some(env_security)) = (kind, env_security) {
+                *security = env_security;
+            }
+        }
+        if let Ok(url) = env::var("WEBHOOK_URL") {
+            set_target(&mut targets, "webhook", TargetKind::Webhook { url, headers: BTreeMap::new(), secret: env::var("WEBHOOK_SECRET").ok() });
+        }
+        if let (Ok(url), Ok(subject)) = (env::var("NATS_URL"), env::var("NATS_SUBJECT")) {
+            let env_jetstream = env_parse("NATS_JETSTREAM", &mut errors);
+            let kind = set_target(&mut targets, "nats", TargetKind::Nats {
+                url,
+                subject,
+                token: env::var("NATS_TOKEN").ok(),
+                username: env::var("NATS_USERNAME").ok(),
+                password: env::var("NATS_PASSWORD").ok(),
+                jetstream: default_jetstream(),
+            });
+            if let (TargetKind::Nats { jetstream, .. }, Some(env_jetstream)) = (kind, env_jetstream) {
+                *jetstream = env_jetstream;
+            }
+        }
+        if let (Ok(url), Ok(stream)) = (env::var("REDIS_URL"), env::var("REDIS_STREAM")) {
+            set_target(&mut targets, "redis", TargetKind::Redis { url, stream, max_len: env_parse("REDIS_MAX_LEN", &mut errors) });
+        }
+        if let (Ok(user_key), Ok(token)) = (env::var("PUSHOVER_USER_KEY"), env::var("PUSHOVER_TOKEN")) {
+            set_target(&mut targets, "pushover", TargetKind::Pushover { user_key, token, device: None, priority: None, api_url: None });
+        }
+        if let (Ok(bot_token), Ok(channel)) = (env::var("SLACK_BOT_TOKEN"), env::var("SLACK_CHANNEL")) {
+            // Both would define the `slack` target, one replacing the other
+            if env::var("SLACK_WEBHOOK_URL").is_ok() {
+                errors.push("SLACK_WEBHOOK_URL and SLACK_BOT_TOKEN cannot be used together, keep one of them".to_string());
+            }
+            set_target(&mut targets, "slack", TargetKind::Slack { webhook_url: None, bot_token: Some(bot_token), channel: Some(channel), api_url: None, channels: Vec::new() });
+        }
+
+        // Apprise-style URLs add one target each, named after the service
+        if let Ok(urls) = env::var("GHNTFY_URLS") {
+            for (index, url) in urls.split(|c: char| c == ',' || c.is_whitespace()).filter(|u| !u.is_empty()).enumerate() {
+                match apprise::parse(url) {
+                    Ok(kind) => {
+                        let service = kind.service();
+                        let name = (1..).map(|n| if n == 1 { service.to_string() } else { format!("{}-{}", service, n) })
+                            .find(|name| targets.iter().all(|t| &t.name != name))
+                            .unwrap_or_default();
+                        targets.push(Target::new(&name, kind));
+                    }
+                    Err(e) => errors.push(format!("GHNTFY_URLS: URL {}: {}", index + 1, e)),
+                }
+            }
+        }
+
+        // ntfy targets without credentials use the global ones, if any, topics without both are anonymous
+        for target in &mut targets {
+            if let TargetKind::Ntfy { auth: target_auth @ None, token: None, .. } = &mut target.kind {
+                *target_auth = Some(auth.clone()).filter(|a| !a.is_empty());
+            }
+        }
+
+        // Home Assistant update entities are published once a broker is set
+        let mqtt = env::var("MQTT_HOST").ok().or(file.mqtt.host).filter(|h| !h.is_empty()).map(|host| {
+            let tls = env_parse("MQTT_TLS", &mut errors).or(file.mqtt.tls).unwrap_or(false);
+            mqtt::Broker {
+                host,
+                port: env_parse("MQTT_PORT", &mut errors).or(file.mqtt.port).unwrap_or(if tls { 8883 } else { 1883 }),
+                username: env::var("MQTT_USERNAME").ok().or(file.mqtt.username).filter(|u| !u.is_empty()),
+                password: env::var("MQTT_PASSWORD").ok().or(file.mqtt.password).filter(|p| !p.is_empty()),
+                tls,
+                client_id: file.mqtt.client_id.unwrap_or_else(|| "github-ntfy".to_string()),
+                discovery_prefix: env::var("MQTT_DISCOVERY_PREFIX").ok().or(file.mqtt.discovery_prefix)
+                    .unwrap_or_else(|| "homeassistant".to_string()),
+                topic_prefix: file.mqtt.topic_prefix.unwrap_or_else(|| "github-ntfy".to_string()),
+            }
+        });
+
+        let config = Config {
+            github_token: env::var("GHNTFY_TOKEN").ok().or(file.github_token),
+            docker_username: env::var("DOCKER_USERNAME").ok().or(file.docker.username),
+            docker_password: env::var("DOCKER_PASSWORD").ok().or(file.docker.password),
+            docker_token: None,
+            targets,
+            repos: file.repos,
+            timeout: env_parse("GHNTFY_TIMEOUT", &mut errors).or(file.timeout).unwrap_or(3600.0),
+            sources_refresh: env_parse("GHNTFY_SOURCES_REFRESH", &mut errors).or(file.sources_refresh).unwrap_or(86400.0),
+            drift_major: env_parse("GHNTFY_DRIFT_MAJOR", &mut errors).or(file.drift.major),
+            drift_minor: env_parse("GHNTFY_DRIFT_MINOR", &mut errors).or(file.drift.minor),
+            drift_days: env_parse("GHNTFY_DRIFT_DAYS", &mut errors).or(file.drift.days),
+            admin_token: env::var("GHNTFY_ADMIN_TOKEN").ok().or(file.admin_token).filter(|t| !t.is_empty()),
+            mqtt,
+        };
+
+        errors.extend(config.validate());
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError(errors))
+        }
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.targets.is_empty() {
+            errors.push("no notification target configured, set NTFY_URL, GOTIFY_URL and GOTIFY_TOKEN, \
+                         DISCORD_WEBHOOK_URL, SLACK_WEBHOOK_URL, NATS_URL and NATS_SUBJECT, REDIS_URL and REDIS_STREAM, \
+                         GHNTFY_URLS or add [[targets]] to the configuration file".to_string());
+        }
+        // NaN and infinity would pass a `<= 0.0` check and panic in `Duration::from_secs_f64`
+        if !(self.timeout.is_finite() && self.timeout > 0.0) {
+            errors.push(format!("timeout: must be a positive number of seconds, got {}", self.timeout));
+        }
+        if !(self.sources_refresh.is_finite() && self.sources_refresh > 0.0) {
+            errors.push(format!("sources_refresh: must be a positive number of seconds, got {}", self.sources_refresh));
+        }
+        if self.docker_username.is_some() != self.docker_password.is_some() {
+            errors.push("docker: username and password must be set together".to_string());
+        }
+        if let Some(broker) = &self.mqtt {
+            if broker.username.is_some() != broker.password.is_some() {
+                errors.push("mqtt: username and password must be set together".to_string());
+            }
+            if broker.client_id.is_empty() {
+                errors.push("mqtt: 'client_id' must not be empty".to_string());
+            }
+            for prefix in [&broker.discovery_prefix, &broker.topic_prefix] {
+                if let Err(e) = mqtt::check_prefix(prefix) {
+                    errors.push(format!("mqtt: {}", e));
+                }
+            }
+        }
+
+        let mut names = HashSet::new();
+        for (index, target) in self.targets.iter().enumerate() {
+            let label = if target.name.is_empty() {
+                format!("targets[{}]", index)
+            } else {
+                format!("target '{}'", target.name)
+            };
+
+            if target.name.is_empty() {
+                errors.push(format!("{}: 'name' is required", label));
+            } else if !names.insert(target.name.as_str()) {
+                errors.push(format!("{}: name is used by several targets", label));
+            }
+
+            for url in target.kind.urls() {
+                match reqwest::Url::parse(url) {
+                    Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {}
+                    Ok(_) => errors.push(format!("{}: '{}' must be an http or https URL", label, url)),
+                    Err(e) => errors.push(format!("{}: invalid URL '{}': {}", label, url, e)),
+                }
+            }
+
+            match &target.kind {
+                TargetKind::Ntfy { auth, token, email, delay, .. } => {
+                    if auth.is_some() && token.is_some() {
+                        errors.push(format!("{}: 'auth' and 'token' cannot be used together", label));
+                    }
+                    if token.as_deref() == Some("") {
+                        errors.push(format!("{}: 'token' must not be empty, remove it for an anonymous topic", label));
+                    }
+                    if let Some(Err(e)) = email.as_deref().map(email::check_address) {
+                        errors.push(format!("{}: 'email': {}", label, e));
+                    }
+                    if delay.as_deref().is_some_and(|d| d.trim().is_empty()) {
+                        errors.push(format!("{}: 'delay' must not be empty", label));
+                    }
+                }
+                TargetKind::Gotify { token, priorities, .. } => {
+                    if token.is_empty() {
+                        errors.push(format!("{}: 'token' is required", label));
+                    }
+                    if let Err(e) = priorities.check() {
+                        errors.push(format!("{}: {}", label, e));
+                    }
+                }
+                TargetKind::Telegram { bot_token, chats, .. } => {
+                    if bot_token.is_empty() {
+                        errors.push(format!("{}: 'bot_token' is required", label));
+                    }
+                    if chats.is_empty() {
+                        errors.push(format!("{}: at least one chat is required in 'chats'", label));
+                    }
+                    for chat in chats {
+                        if let ChatId::Username(name) = &chat.id {
+                            if !name.starts_with('@') {
+                                errors.push(format!("{}: chat '{}' must be a numeric id or a @channel", label, name));
+                            }
+                        }
+                    }
+                }
+                TargetKind::Email { host, username, password, from, to, cc, bcc, recipients, .. } => {
+                    if host.is_empty() {
+                        errors.push(format!("{}: 'host' is required", label));
+                    }
+                    if username.is_some() != password.is_some() {
+                        errors.push(format!("{}: 'username' and 'password' must be set together", label));
+                    }
+                    if to.is_empty() && recipients.is_empty() {
+                        errors.push(format!("{}: at least one recipient is required in 'to' or 'recipients'", label));
+                    }
+                    let addresses = std::iter::once(from).chain(to).chain(cc).chain(bcc).chain(recipients.iter().flat_map(|r| &r.to));
+                    for address in addresses {
+                        if let Err(e) = email::check_address(address) {
+                            errors.push(format!("{}: {}", label, e));
+                        }
+                    }
+                    for pattern in recipients.iter().flat_map(|route| &route.repos) {
+                        if let Err(e) = glob::Pattern::new(pattern) {
+                            errors.push(format!("{}: invalid repository pattern '{}': {}", label, pattern, e));
+                        }
+                    }
+                }
+                TargetKind::Webhook { headers, secret, .. } => {
+                    if let Err(e) = webhook::check_headers(headers) {
+                        errors.push(format!("{}: {}", label, e));
+                    }
+                    if secret.as_deref() == Some("") {
+                        errors.push(format!("{}: 'secret' must not be empty, remove it to send unsigned events", label));
+                    }
+                }
+                TargetKind::Nats { url, subject, token, username, password, .. } => {
+                    if let Err(e) = nats::check_url(url) {
+                        errors.push(format!("{}: {}", label, e));
+                    }
+                    if let Err(e) = nats::check_subject(subject) {
+                        errors.push(format!("{}: {}", label, e));
+                    }
+                    if username.is_some() != password.is_some() {
+                        errors.push(format!("{}: 'username' and 'password' must be set together", label));
+                    }
+                    if token.is_some() && username.is_some() {
+                        errors.push(format!("{}: 'token' and 'username' cannot be used together", label));
+                    }
+                }
+                TargetKind::Redis { url, stream, max_len } => {
+                    if let Err(e) = redis_stream::check_url(url) {
+                        errors.push(format!("{}: {}", label, e));
+                    }
+                    if stream.is_empty() {
+                        errors.push(format!("{}: 'stream' is required", label));
+                    }
+                    if *max_len == Some(0) {
+                        errors.push(format!("{}: 'max_len' must be positive, remove it to keep every event", label));
+                    }
+                }
+                TargetKind::Pushover { user_key, token, priority, .. } => {
+                    if user_key.is_empty() || token.is_empty() {
+                        errors.push(format!("{}: 'user_key' and 'token' are required", label));
+                    }
+                    if let Some(Err(e)) = priority.map(pushover::check_priority) {
+                        errors.push(format!("{}: {}", label, e));
+                    }
+                }
+                TargetKind::Matrix { access_token, rooms, .. } => {
+                    if access_token.is_empty() {
+                        errors.push(format!("{}: 'access_token' is required", label));
+                    }
+                    if rooms.is_empty() {
+                        errors.push(format!("{}: at least one room is required in 'rooms'", label));
+                    }
+                    for room in rooms.iter().filter(|r| !(r.starts_with('!') || r.starts_with('#')) || !r.contains(':')) {
+                        errors.push(format!("{}: '{}' is neither a room id (!id:server) nor an alias (#alias:server)", label, room));
+                    }
+                }
+                TargetKind::Slack { webhook_url, bot_token, channel, channels, .. } => {
+                    let bot = bot_token.is_some() && channel.is_some();
+                    if bot_token.is_some() != channel.is_some() {
+                        errors.push(format!("{}: 'bot_token' and 'channel' must be set together", label));
+                    } else if webhook_url.is_none() && !bot {
+                        errors.push(format!("{}: set 'webhook_url', or 'bot_token' and 'channel'", label));
+                    }
+                    if !channels.is_empty() && !bot {
+                        errors.push(format!("{}: 'channels' needs 'bot_token' and 'channel'", label));
+                    }
+                    for pattern in channels.iter().flat_map(|route| &route.repos) {
+                        if let Err(e) = glob::Pattern::new(pattern) {
+                            errors.push(format!("{}: invalid repository pattern '{}': {}", label, pattern, e));
+                        }
+                    }
+                    if target.split_changelog && !bot {
+                        errors.push(format!("{}: split_changelog needs 'bot_token' and 'channel' to post in threads", label));
+                    }
+                }
+                _ => {}
+            }
+
+            if target.split_changelog && matches!(target.kind, TargetKind::Gotify { .. } | TargetKind::Teams { .. } | TargetKind::Matrix { .. } | TargetKind::Telegram { .. } | TargetKind::Email { .. } | TargetKind::Webhook { .. } | TargetKind::Pushover { .. } | TargetKind::Nats { .. } | TargetKind::Redis { .. }) {
+                errors.push(format!("{}: split_changelog is not supported by {}", label, target.kind.service()));
+            }
+
+            for (event, template) in [("github", &target.templates.github), ("docker", &target.templates.docker), ("drift", &target.templates.drift)] {
+                let Some(template) = template else { continue };
+                for (part, source) in [("title", &template.title), ("body", &template.body)] {
+                    if let Some(Err(e)) = source.as_deref().map(template::check) {
+                        errors.push(format!("{}: invalid {} template for {} events: {}", label, part, event, e));
+                    }
+                }
+            }
+
+            for pattern in target.repos.iter().chain(&target.exclude) {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    errors.push(format!("{}: invalid repository pattern '{}': {}", label, pattern, e));
+                }
+            }
+        }
+
+        for (repo, settings) in &self.repos {
+            for name in settings.targets.iter().flatten() {
+                if !names.contains(name.as_str()) {
+                    errors.push(format!("repos.\"{}\": unknown target '{}'", repo, name));
+                }
+            }
+        }
+
+        errors
+    }
+
+    // Targets that should be notified for a repository: the per-repository list when there is one,
+    // otherwise every target whose routing rules accept it
+    pub fn targets_for(&self, kind: WatchKind, repo: &str) -> Vec<&Target> {
+        match self.repos.get(repo).and_then(|r| r.targets.as_ref()) {
+            Some(names) => self.targets.iter().filter(|t| names.contains(&t.name)).collect(),
+            None => self.targets.iter().filter(|t| t.accepts(kind, repo)).collect(),
+        }
+    }
+
+    pub fn describe_targets(&self) -> String {
+        self.targets.iter()
+            .map(|t| format!("{} ({})", t.name, t.kind.service()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    // Log in to Docker Hub when credentials are configured
+    pub async fn login_docker(&mut self) {
+        if let (Some(username), Some(password)) = (self.docker_username.clone(), self.docker_password.clone()) {
+            self.docker_token = tokio::task::spawn_blocking(move || create_dockerhub_token(&username, &password))
+                .await
+                .ok()
+                .flatten();
         }
     }
 
@@ -78,4 +1028,110 @@ impl Config {
         }
         headers
     }
-}
\ No newline at end of file
+}
+
+// Configuration shared by the poller and the API, replaced as a whole on reload
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    // Load and validate the configuration again, the current one is kept when the new one is invalid
+    pub async fn reload(&self) -> Result<Arc<Config>, ConfigError> {
+        let mut config = Config::load()?;
+        config.login_docker().await;
+
+        let config = Arc::new(config);
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_targets(yaml: &str) -> Vec<Target> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn environment_overrides_only_the_fields_it_names() {
+        let mut targets = file_targets(
+            "- name: ntfy\n  type: ntfy\n  url: https://ntfy.example.com/old\n  email: me@example.com\n  delay: 30m\n\
+             - name: telegram\n  type: telegram\n  bot_token: old\n  chats: [{id: 1}]\n  parse_mode: html\n  api_url: http://proxy\n",
+        );
+
+        set_target(&mut targets, "ntfy", TargetKind::Ntfy {
+            url: "https://ntfy.example.com/new".to_string(), auth: None, token: Some("tk_env".to_string()), email: None, delay: None, priorities: Priorities::default(),
+        });
+        set_target(&mut targets, "telegram", TargetKind::Telegram {
+            bot_token: "new".to_string(), chats: vec![Chat::parse("2").unwrap()], parse_mode: ParseMode::default(), api_url: None,
+        });
+
+        match &targets[0].kind {
+            TargetKind::Ntfy { url, token, email, delay, .. } => {
+                assert_eq!(url, "https://ntfy.example.com/new");
+                assert_eq!(token.as_deref(), Some("tk_env"));
+                assert_eq!(email.as_deref(), Some("me@example.com"));
+                assert_eq!(delay.as_deref(), Some("30m"));
+            }
+            kind => panic!("unexpected {:?}", kind),
+        }
+        match &targets[1].kind {
+            TargetKind::Telegram { bot_token, chats, parse_mode, api_url } => {
+                assert_eq!(bot_token, "new");
+                assert_eq!(chats[0].id, ChatId::Id(2));
+                assert_eq!(*parse_mode, ParseMode::Html);
+                assert_eq!(api_url.as_deref(), Some("http://proxy"));
+            }
+            kind => panic!("unexpected {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn environment_replaces_a_target_of_another_service_and_adds_missing_ones() {
+        let mut targets = file_targets("- name: slack\n  type: discord\n  webhook_url: https://discord.example.com\n");
+
+        set_target(&mut targets, "slack", TargetKind::Slack {
+            webhook_url: Some("https://hooks.example.com".to_string()), bot_token: None, channel: None, api_url: None, channels: Vec::new(),
+        });
+        set_target(&mut targets, "teams", TargetKind::Teams { webhook_url: "https://teams.example.com".to_string() });
+
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].kind.service(), "slack");
+        assert_eq!(targets[1].name, "teams");
+    }
+
+    #[test]
+    fn nats_targets_wait_for_jetstream_unless_disabled() {
+        let targets = file_targets(
+            "- name: nats\n  type: nats\n  url: nats://nats:4222\n  subject: releases\n\
+             - name: core\n  type: nats\n  url: nats://nats:4222\n  subject: releases\n  jetstream: false\n",
+        );
+        assert!(matches!(targets[0].kind, TargetKind::Nats { jetstream: true, .. }));
+        assert!(matches!(targets[1].kind, TargetKind::Nats { jetstream: false, .. }));
+    }
+
+    #[test]
+    fn unknown_target_settings_are_rejected() {
+        for (yaml, field) in [
+            ("- name: gotify\n  type: gotify\n  url: https://gotify\n  token: t\n  prioritys: {major: 8}\n", "prioritys"),
+            ("- name: discord\n  type: discord\n  webhook: https://discord\n  webhook_url: https://discord\n", "webhook"),
+            ("- name: slack\n  type: slack\n  webhook_url: https://hooks\n  channels:\n    - repos: ['*']\n      chanel: '#a'\n", "chanel"),
+        ] {
+            let error = serde_yaml::from_str::<Vec<Target>>(yaml).unwrap_err().to_string();
+            assert!(error.contains(&format!("unknown field `{}`", field)), "{}", error);
+        }
+
+        let targets = file_targets("- name: ntfy\n  type: ntfy\n  url: https://ntfy.example.com/topic\n  delay: 1h\n");
+        assert_eq!(targets[0].kind.service(), "ntfy");
+    }
+}
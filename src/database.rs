@@ -1,7 +1,8 @@
 use log::info;
+use std::collections::HashMap;
+use crate::models::{DeploymentState, PendingEvent, Priority, SlackThread, SourceKind, WatchKind, WatchSource};
 pub(crate) use rusqlite::{Connection, Result as SqliteResult, OpenFlags};
 use std::env;
-use std::path::Path;
 
 pub fn init_databases() -> SqliteResult<(Connection, Connection)> {
     let db_path = env::var("DB_PATH").unwrap_or_else(|_| "/github-ntfy".to_string());
@@ -26,6 +27,53 @@ pub fn init_databases() -> SqliteResult<(Connection, Connection)> {
         [],
     )?;
 
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS slack_threads (
+            target TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            thread_ts TEXT NOT NULL,
+            version TEXT NOT NULL,
+            ts TEXT NOT NULL,
+            PRIMARY KEY (target, repo)
+        )",
+        [],
+    )?;
+
+    // Version last delivered to each target, so a release retried after a failure skips the targets that got it
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS deliveries (
+            target TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            version TEXT NOT NULL,
+            PRIMARY KEY (target, repo)
+        )",
+        [],
+    )?;
+
+    // Release events of the event bus targets, deleted once published
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS pending_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            target TEXT NOT NULL,
+            event_id TEXT NOT NULL,
+            payload BLOB NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            last_error TEXT,
+            UNIQUE (target, event_id)
+        )",
+        [],
+    )?;
+
+    // Update entities published to MQTT, removed from the broker when their watch is deleted
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS mqtt_entities (
+            object_id TEXT PRIMARY KEY
+        )",
+        [],
+    )?;
+
     conn.execute(
         "CREATE TABLE IF NOT EXISTS docker_versions (
             repo TEXT PRIMARY KEY,
@@ -54,13 +102,72 @@ pub fn init_databases() -> SqliteResult<(Connection, Connection)> {
         [],
     )?;
 
+    for table in ["watched_repos", "docker_watched_repos"] {
+        ensure_column(&conn2, table, "source", "TEXT")?;
+        ensure_column(&conn2, table, "deployed_version", "TEXT")?;
+        ensure_column(&conn2, table, "latest_version", "TEXT")?;
+        ensure_column(&conn2, table, "behind_since", "TEXT")?;
+        ensure_column(&conn2, table, "drift_alerted", "INTEGER NOT NULL DEFAULT 0")?;
+        ensure_column(&conn2, table, "priority", "TEXT")?;
+    }
+
+    conn2.execute(
+        "CREATE TABLE IF NOT EXISTS watch_sources (
+            id INTEGER PRIMARY KEY,
+            kind TEXT NOT NULL,
+            name TEXT NOT NULL,
+            exclude TEXT,
+            releases_only INTEGER NOT NULL DEFAULT 1,
+            last_synced TEXT
+        )",
+        [],
+    )?;
+
+    // Whether a repository of a source has releases, so syncs do not ask GitHub for every repository again
+    conn2.execute(
+        "CREATE TABLE IF NOT EXISTS release_checks (
+            repo TEXT PRIMARY KEY,
+            has_releases INTEGER NOT NULL,
+            checked_at TEXT NOT NULL
+        )",
+        [],
+    )?;
+
     Ok((conn, conn2))
 }
 
+// Add a column to an existing table created by an older version
+pub fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> SqliteResult<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .flatten()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+        info!("Added column {} to table {}", column, table);
+    }
+
+    Ok(())
+}
+
 // Functions to retrieve watched repositories
 pub fn get_watched_repos(conn: &Connection) -> SqliteResult<Vec<String>> {
     let mut stmt = conn.prepare("SELECT repo FROM watched_repos")?;
-    let repos_iter = stmt.query_map([], |row| Ok(row.get::<_, String>(0)?))?;
+    let repos_iter = stmt.query_map([], |row| row.get::<_, String>(0))?;
+
+    let mut repos = Vec::new();
+    for repo in repos_iter {
+        repos.push(repo?);
+    }
+    Ok(repos)
+}
+
+// Repositories added by hand, without those managed by a watch source
+pub fn get_manual_watched_repos(conn: &Connection) -> SqliteResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT repo FROM watched_repos WHERE source IS NULL")?;
+    let repos_iter = stmt.query_map([], |row| row.get::<_, String>(0))?;
 
     let mut repos = Vec::new();
     for repo in repos_iter {
@@ -71,7 +178,7 @@ pub fn get_watched_repos(conn: &Connection) -> SqliteResult<Vec<String>> {
 
 pub fn get_docker_watched_repos(conn: &Connection) -> SqliteResult<Vec<String>> {
     let mut stmt = conn.prepare("SELECT repo FROM docker_watched_repos")?;
-    let repos_iter = stmt.query_map([], |row| Ok(row.get::<_, String>(0)?))?;
+    let repos_iter = stmt.query_map([], |row| row.get::<_, String>(0))?;
 
     let mut repos = Vec::new();
     for repo in repos_iter {
@@ -80,17 +187,230 @@ pub fn get_docker_watched_repos(conn: &Connection) -> SqliteResult<Vec<String>>
     Ok(repos)
 }
 
-pub fn is_new_version(conn: &Connection, repo: &str, version: &str) -> SqliteResult<bool> {
-    let mut stmt = conn.prepare("SELECT version FROM versions WHERE repo = ?")?;
-    let result = stmt.query_map([repo], |row| row.get::<_, String>(0))?;
+pub fn is_watched(conn: &Connection, kind: WatchKind, repo: &str) -> SqliteResult<bool> {
+    let count: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM {} WHERE repo = ?", kind.table()),
+        [repo],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+pub fn add_watch(conn: &Connection, kind: WatchKind, repo: &str) -> SqliteResult<()> {
+    conn.execute(&format!("INSERT INTO {} (repo) VALUES (?)", kind.table()), [repo])?;
+    Ok(())
+}
+
+pub fn add_manifest_watch(conn: &Connection, kind: WatchKind, repo: &str, marker: &str, deployed_version: &str) -> SqliteResult<()> {
+    conn.execute(
+        &format!("INSERT INTO {} (repo, source, deployed_version) VALUES (?, ?, ?)", kind.table()),
+        [repo, marker, deployed_version],
+    )?;
+    Ok(())
+}
+
+// A new deployed version restarts drift tracking from scratch
+pub fn set_deployed_version(conn: &Connection, kind: WatchKind, repo: &str, version: Option<&str>) -> SqliteResult<bool> {
+    let updated = conn.execute(
+        &format!("UPDATE {} SET deployed_version = ?, behind_since = NULL, drift_alerted = 0 WHERE repo = ?", kind.table()),
+        rusqlite::params![version, repo],
+    )?;
+    Ok(updated > 0)
+}
+
+pub fn get_deployed_versions(conn: &Connection, kind: WatchKind) -> SqliteResult<HashMap<String, String>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT repo, deployed_version FROM {} WHERE deployed_version IS NOT NULL", kind.table()
+    ))?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+    let mut versions = HashMap::new();
+    for row in rows {
+        let (repo, version) = row?;
+        versions.insert(repo, version);
+    }
+    Ok(versions)
+}
+
+pub fn set_priority(conn: &Connection, kind: WatchKind, repo: &str, priority: Option<Priority>) -> SqliteResult<bool> {
+    let updated = conn.execute(
+        &format!("UPDATE {} SET priority = ? WHERE repo = ?", kind.table()),
+        rusqlite::params![priority.map(|p| p.as_str()), repo],
+    )?;
+    Ok(updated > 0)
+}
+
+pub fn get_priorities(conn: &Connection, kind: WatchKind) -> SqliteResult<HashMap<String, Priority>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT repo, priority FROM {} WHERE priority IS NOT NULL", kind.table()
+    ))?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+    let mut priorities = HashMap::new();
+    for row in rows {
+        let (repo, priority) = row?;
+        if let Some(priority) = Priority::parse(&priority) {
+            priorities.insert(repo, priority);
+        }
+    }
+    Ok(priorities)
+}
+
+pub fn get_drift_state(conn: &Connection, kind: WatchKind, repo: &str) -> SqliteResult<(Option<String>, bool)> {
+    conn.query_row(
+        &format!("SELECT behind_since, drift_alerted FROM {} WHERE repo = ?", kind.table()),
+        [repo],
+        |row| Ok((row.get(0)?, row.get::<_, i64>(1)? != 0)),
+    )
+}
+
+pub fn set_drift_state(
+    conn: &Connection,
+    kind: WatchKind,
+    repo: &str,
+    latest_version: &str,
+    behind_since: Option<&str>,
+    alerted: bool,
+) -> SqliteResult<()> {
+    conn.execute(
+        &format!("UPDATE {} SET latest_version = ?, behind_since = ?, drift_alerted = ? WHERE repo = ?", kind.table()),
+        rusqlite::params![latest_version, behind_since, alerted, repo],
+    )?;
+    Ok(())
+}
+
+pub fn get_deployment_states(conn: &Connection, kind: WatchKind) -> SqliteResult<Vec<DeploymentState>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT repo, deployed_version, latest_version, behind_since FROM {} WHERE deployed_version IS NOT NULL",
+        kind.table()
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        Ok(DeploymentState {
+            repo: row.get(0)?,
+            deployed_version: row.get(1)?,
+            latest_version: row.get(2)?,
+            behind_since: row.get(3)?,
+        })
+    })?;
+
+    let mut states = Vec::new();
+    for row in rows {
+        states.push(row?);
+    }
+    Ok(states)
+}
+
+pub fn remove_watch(conn: &Connection, kind: WatchKind, repo: &str) -> SqliteResult<()> {
+    conn.execute(&format!("DELETE FROM {} WHERE repo = ?", kind.table()), [repo])?;
+    Ok(())
+}
+
+// Functions for watch sources (starred repositories, organizations)
+pub fn get_watch_sources(conn: &Connection) -> SqliteResult<Vec<WatchSource>> {
+    let mut stmt = conn.prepare("SELECT id, kind, name, exclude, releases_only, last_synced FROM watch_sources")?;
+    let sources_iter = stmt.query_map([], |row| {
+        let kind: String = row.get(1)?;
+        let exclude: Option<String> = row.get(3)?;
+        Ok(WatchSource {
+            id: row.get(0)?,
+            kind: SourceKind::parse(&kind).unwrap_or(SourceKind::Starred),
+            name: row.get(2)?,
+            exclude: exclude
+                .map(|e| e.lines().map(str::to_string).filter(|p| !p.is_empty()).collect())
+                .unwrap_or_default(),
+            releases_only: row.get::<_, i64>(4)? != 0,
+            last_synced: row.get(5)?,
+        })
+    })?;
+
+    let mut sources = Vec::new();
+    for source in sources_iter {
+        sources.push(source?);
+    }
+    Ok(sources)
+}
 
-    for stored_version in result {
-        if let Ok(v) = stored_version {
-            return Ok(v != version);
+pub fn source_exists(conn: &Connection, kind: SourceKind, name: &str) -> SqliteResult<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM watch_sources WHERE kind = ? AND name = ?",
+        [kind.as_str(), name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+pub fn add_watch_source(conn: &Connection, source: &WatchSource) -> SqliteResult<i64> {
+    conn.execute(
+        "INSERT INTO watch_sources (kind, name, exclude, releases_only) VALUES (?, ?, ?, ?)",
+        rusqlite::params![source.kind.as_str(), source.name, source.exclude.join("\n"), source.releases_only],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+// Delete a source together with the repositories it created
+pub fn delete_watch_source(conn: &Connection, id: i64) -> SqliteResult<bool> {
+    let source = get_watch_sources(conn)?.into_iter().find(|s| s.id == id);
+    match source {
+        Some(source) => {
+            conn.execute("DELETE FROM watched_repos WHERE source = ?", [source.marker()])?;
+            conn.execute("DELETE FROM watch_sources WHERE id = ?", [id])?;
+            Ok(true)
         }
+        None => Ok(false),
     }
+}
+
+pub fn mark_source_synced(conn: &Connection, id: i64) -> SqliteResult<()> {
+    conn.execute(
+        "UPDATE watch_sources SET last_synced = ? WHERE id = ?",
+        rusqlite::params![chrono::Utc::now().to_rfc3339(), id],
+    )?;
+    Ok(())
+}
+
+pub fn get_source_repos(conn: &Connection, marker: &str) -> SqliteResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT repo FROM watched_repos WHERE source = ?")?;
+    let repos_iter = stmt.query_map([marker], |row| row.get::<_, String>(0))?;
+
+    let mut repos = Vec::new();
+    for repo in repos_iter {
+        repos.push(repo?);
+    }
+    Ok(repos)
+}
+
+// Last answer for a repository with the time it was checked
+pub fn get_release_check(conn: &Connection, repo: &str) -> SqliteResult<Option<(bool, String)>> {
+    let mut stmt = conn.prepare("SELECT has_releases, checked_at FROM release_checks WHERE repo = ?")?;
+    let mut rows = stmt.query_map([repo], |row| Ok((row.get::<_, bool>(0)?, row.get::<_, String>(1)?)))?;
+    rows.next().transpose()
+}
 
-    Ok(true)
+pub fn save_release_check(conn: &Connection, repo: &str, has_releases: bool) -> SqliteResult<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO release_checks (repo, has_releases, checked_at) VALUES (?, ?, ?)",
+        rusqlite::params![repo, has_releases, chrono::Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+pub fn add_source_watch(conn: &Connection, repo: &str, marker: &str) -> SqliteResult<()> {
+    conn.execute("INSERT INTO watched_repos (repo, source) VALUES (?, ?)", [repo, marker])?;
+    Ok(())
+}
+
+// Last notified version of a repository or Docker image
+pub fn get_version(conn: &Connection, repo: &str) -> SqliteResult<Option<String>> {
+    let mut stmt = conn.prepare("SELECT version FROM versions WHERE repo = ?")?;
+    let version = stmt.query_map([repo], |row| row.get::<_, String>(0))?.flatten().next();
+    Ok(version)
+}
+
+// Changelog recorded with the last notified version, to detect edited releases
+pub fn get_changelog(conn: &Connection, repo: &str) -> SqliteResult<Option<String>> {
+    let mut stmt = conn.prepare("SELECT changelog FROM versions WHERE repo = ?")?;
+    let changelog = stmt.query_map([repo], |row| row.get::<_, Option<String>>(0))?.flatten().next().flatten();
+    Ok(changelog)
 }
 
 pub fn update_version(conn: &Connection, repo: &str, version: &str, changelog: Option<&str>) -> SqliteResult<()> {
@@ -100,4 +420,157 @@ pub fn update_version(conn: &Connection, repo: &str, version: &str, changelog: O
     )?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// Last notified version of every repository and Docker image
+pub fn get_versions(conn: &Connection) -> SqliteResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare("SELECT repo, version FROM versions ORDER BY repo")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?;
+
+    let mut versions = Vec::new();
+    for row in rows {
+        versions.push(row?);
+    }
+    Ok(versions)
+}
+
+// Forget the last notified version, the current release is notified again on the next cycle
+pub fn reset_version(conn: &Connection, repo: &str) -> SqliteResult<bool> {
+    let deleted = conn.execute("DELETE FROM versions WHERE repo = ?", [repo])?;
+    Ok(deleted > 0)
+}
+
+pub fn get_slack_thread(conn: &Connection, target: &str, repo: &str) -> SqliteResult<Option<SlackThread>> {
+    let mut stmt = conn.prepare("SELECT channel, thread_ts, version, ts FROM slack_threads WHERE target = ? AND repo = ?")?;
+    let thread = stmt.query_map([target, repo], |row| Ok(SlackThread {
+        channel: row.get(0)?,
+        thread_ts: row.get(1)?,
+        version: row.get(2)?,
+        ts: row.get(3)?,
+    }))?.flatten().next();
+    Ok(thread)
+}
+
+pub fn save_slack_thread(conn: &Connection, target: &str, repo: &str, thread: &SlackThread) -> SqliteResult<()> {
+    conn.execute(
+        "REPLACE INTO slack_threads (target, repo, channel, thread_ts, version, ts) VALUES (?, ?, ?, ?, ?, ?)",
+        [target, repo, &thread.channel, &thread.thread_ts, &thread.version, &thread.ts],
+    )?;
+    Ok(())
+}
+
+pub fn is_delivered(conn: &Connection, target: &str, repo: &str, version: &str) -> SqliteResult<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM deliveries WHERE target = ? AND repo = ? AND version = ?",
+        [target, repo, version],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+pub fn save_delivery(conn: &Connection, target: &str, repo: &str, version: &str) -> SqliteResult<()> {
+    conn.execute("REPLACE INTO deliveries (target, repo, version) VALUES (?, ?, ?)", [target, repo, version])?;
+    Ok(())
+}
+
+pub fn get_mqtt_entities(conn: &Connection) -> SqliteResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT object_id FROM mqtt_entities")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+
+    let mut entities = Vec::new();
+    for row in rows {
+        entities.push(row?);
+    }
+    Ok(entities)
+}
+
+pub fn save_mqtt_entity(conn: &Connection, object_id: &str) -> SqliteResult<()> {
+    conn.execute("INSERT OR IGNORE INTO mqtt_entities (object_id) VALUES (?)", [object_id])?;
+    Ok(())
+}
+
+pub fn delete_mqtt_entity(conn: &Connection, object_id: &str) -> SqliteResult<()> {
+    conn.execute("DELETE FROM mqtt_entities WHERE object_id = ?", [object_id])?;
+    Ok(())
+}
+
+// An event already waiting for the same target is kept, with its first payload
+pub fn add_pending_event(conn: &Connection, target: &str, event_id: &str, payload: &[u8]) -> SqliteResult<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO pending_events (target, event_id, payload, created_at) VALUES (?, ?, ?, ?)",
+        rusqlite::params![target, event_id, payload, chrono::Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+// Oldest first, events are published in the order they were detected
+pub fn get_pending_events(conn: &Connection) -> SqliteResult<Vec<PendingEvent>> {
+    let mut stmt = conn.prepare("SELECT id, target, event_id, payload, attempts FROM pending_events ORDER BY id")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(PendingEvent {
+            id: row.get(0)?,
+            target: row.get(1)?,
+            event_id: row.get(2)?,
+            payload: row.get(3)?,
+            attempts: row.get(4)?,
+        })
+    })?;
+
+    let mut events = Vec::new();
+    for row in rows {
+        events.push(row?);
+    }
+    Ok(events)
+}
+
+pub fn count_pending_events(conn: &Connection) -> SqliteResult<i64> {
+    conn.query_row("SELECT COUNT(*) FROM pending_events", [], |row| row.get(0))
+}
+
+pub fn delete_pending_event(conn: &Connection, id: i64) -> SqliteResult<()> {
+    conn.execute("DELETE FROM pending_events WHERE id = ?", [id])?;
+    Ok(())
+}
+
+// Events of a target queued before the given RFC 3339 time, returns how many were deleted
+pub fn delete_pending_events_before(conn: &Connection, target: &str, before: &str) -> SqliteResult<usize> {
+    conn.execute("DELETE FROM pending_events WHERE target = ? AND created_at < ?", rusqlite::params![target, before])
+}
+
+pub fn record_event_failure(conn: &Connection, id: i64, error: &str) -> SqliteResult<()> {
+    conn.execute(
+        "UPDATE pending_events SET attempts = attempts + 1, last_error = ? WHERE id = ?",
+        rusqlite::params![error, id],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_old_events_of_the_target_are_deleted() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE pending_events (id INTEGER PRIMARY KEY AUTOINCREMENT, target TEXT NOT NULL, event_id TEXT NOT NULL, \
+             payload BLOB NOT NULL, attempts INTEGER NOT NULL DEFAULT 0, created_at TEXT NOT NULL, last_error TEXT, UNIQUE (target, event_id))",
+            [],
+        ).unwrap();
+        for (target, event_id, created_at) in [
+            ("nats", "github:a/b:v1", "2026-01-01T00:00:00+00:00"),
+            ("nats", "github:a/b:v2", "2026-03-01T00:00:00+00:00"),
+            ("redis", "github:a/b:v1", "2026-01-01T00:00:00+00:00"),
+        ] {
+            conn.execute(
+                "INSERT INTO pending_events (target, event_id, payload, created_at) VALUES (?, ?, ?, ?)",
+                rusqlite::params![target, event_id, b"{}".to_vec(), created_at],
+            ).unwrap();
+        }
+
+        assert_eq!(delete_pending_events_before(&conn, "nats", "2026-02-01T00:00:00+00:00").unwrap(), 1);
+
+        let left: Vec<(String, String)> = get_pending_events(&conn).unwrap().into_iter().map(|e| (e.target, e.event_id)).collect();
+        assert_eq!(left, [("nats".to_string(), "github:a/b:v2".to_string()), ("redis".to_string(), "github:a/b:v1".to_string())]);
+    }
+}
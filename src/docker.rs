@@ -1,6 +1,7 @@
 use log::error;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use serde_json::json;
+use crate::metrics;
 use crate::models::{DockerTag, DockerReleaseInfo};
 
 pub fn create_dockerhub_token(username: &str, password: &str) -> Option<String> {
@@ -48,7 +49,10 @@ pub async fn get_latest_docker_releases(
 
     for repo in repos {
         let url = format!("https://hub.docker.com/v2/repositories/{}/tags/latest", repo);
-        match client.get(&url).headers(headers.clone()).send().await {
+        let response = client.get(&url).headers(headers.clone()).send().await;
+        metrics::record_fetch("docker", &response);
+
+        match response {
             Ok(response) => {
                 if response.status().is_success() {
                     if let Ok(tag) = response.json::<DockerTag>().await {
@@ -57,6 +61,9 @@ pub async fn get_latest_docker_releases(
                             digest: tag.digest.clone(),
                             html_url: format!("https://hub.docker.com/r/{}", repo),
                             published_at: tag.last_updated,
+                            deployed_version: None,
+                            drift: None,
+                            priority: None,
                         });
                     }
                 } else {
@@ -0,0 +1,323 @@
+use log::{error, info};
+use serde::Serialize;
+use crate::config::Config;
+use crate::database::{self, Connection, SqliteResult};
+use crate::models::{DockerReleaseInfo, GithubReleaseInfo, WatchKind};
+
+// Size of the jump between two versions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+}
+
+// How far the deployed version is from the latest one
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Drift {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub days_behind: Option<i64>,
+}
+
+impl Drift {
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if self.major > 0 {
+            parts.push(format!("{} major", self.major));
+        }
+        if self.minor > 0 {
+            parts.push(format!("{} minor", self.minor));
+        }
+        if self.patch > 0 {
+            parts.push(format!("{} patch", self.patch));
+        }
+        if let Some(days) = self.days_behind {
+            parts.push(format!("{} days", days));
+        }
+
+        if parts.is_empty() {
+            "behind".to_string()
+        } else {
+            format!("{} behind", parts.join(", "))
+        }
+    }
+}
+
+// Deployment state of a watch, as shown by the API and the dashboard
+#[derive(Debug, Serialize)]
+pub struct WatchDrift {
+    #[serde(rename = "type")]
+    pub kind: WatchKind,
+    pub repo: String,
+    pub deployed_version: String,
+    pub latest_version: Option<String>,
+    pub drift: Option<Drift>,
+}
+
+pub struct DriftAlert {
+    pub kind: WatchKind,
+    pub repo: String,
+    pub deployed_version: String,
+    pub latest_version: String,
+    pub html_url: String,
+    pub drift: Drift,
+}
+
+// Lenient version parsing: `v1.2.3`, `release-1.2`, `1.2.3-rc.1` all give (major, minor, patch).
+// The number must start the tag or follow a `v` or a separator, digits inside a word (`a1b2c3d`) are no version.
+pub fn parse_version(tag: &str) -> Option<(u64, u64, u64)> {
+    let start = tag.find(|c: char| c.is_ascii_digit())?;
+    let separator = |c: char| matches!(c, '-' | '_' | '/' | '@' | ' ');
+    let prefix = tag[..start].trim_end_matches(['v', 'V']);
+    let prefix_ok = match tag[..start].chars().last() {
+        None => true,
+        Some('v' | 'V') => prefix.len() + 1 == start && prefix.chars().last().is_none_or(separator),
+        Some(c) => separator(c),
+    };
+    if !prefix_ok {
+        return None;
+    }
+    let core = tag[start..].split(['-', '+', ' ']).next()?;
+
+    let mut numbers = core.split('.').map(|part| {
+        let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+        digits.parse::<u64>().ok()
+    });
+
+    let major = numbers.next()??;
+    let minor = numbers.next().flatten().unwrap_or(0);
+    let patch = numbers.next().flatten().unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+// Bump between two versions, None when `to` is not newer or a version cannot be parsed
+pub fn bump(from: &str, to: &str) -> Option<Bump> {
+    let (from, to) = (parse_version(from)?, parse_version(to)?);
+    if to.0 > from.0 {
+        Some(Bump::Major)
+    } else if to.0 == from.0 && to.1 > from.1 {
+        Some(Bump::Minor)
+    } else if to.0 == from.0 && to.1 == from.1 && to.2 > from.2 {
+        Some(Bump::Patch)
+    } else {
+        None
+    }
+}
+
+// Distance counted on the highest differing component only: 1.4.2 -> 3.0.1 is 2 major
+pub fn version_distance(deployed: &str, latest: &str) -> Option<(u64, u64, u64)> {
+    let (d, l) = (parse_version(deployed)?, parse_version(latest)?);
+    Some(match bump(deployed, latest) {
+        Some(Bump::Major) => (l.0 - d.0, 0, 0),
+        Some(Bump::Minor) => (0, l.1 - d.1, 0),
+        Some(Bump::Patch) => (0, 0, l.2 - d.2),
+        None => (0, 0, 0),
+    })
+}
+
+fn days_since(timestamp: &str) -> Option<i64> {
+    let since = chrono::DateTime::parse_from_rfc3339(timestamp).ok()?;
+    Some(chrono::Utc::now().signed_duration_since(since).num_days().max(0))
+}
+
+fn exceeds_threshold(drift: &Drift, config: &Config) -> bool {
+    config.drift_major.is_some_and(|max| drift.major >= max)
+        || config.drift_minor.is_some_and(|max| drift.major > 0 || drift.minor >= max)
+        || config.drift_days.is_some_and(|max| drift.days_behind.is_some_and(|days| days >= max))
+}
+
+// Record the latest version of a watch, compute its drift and tell whether an escalation alert is due
+fn evaluate_watch(
+    conn: &Connection,
+    kind: WatchKind,
+    repo: &str,
+    deployed: Option<&str>,
+    latest: &str,
+    published_at: &str,
+    config: &Config,
+) -> SqliteResult<(Option<Drift>, bool)> {
+    let (behind_since, alerted) = database::get_drift_state(conn, kind, repo)?;
+
+    let behind = match deployed {
+        Some(deployed) if deployed == latest => false,
+        // Docker watches follow the digest of `latest`, a deployed tag cannot be compared to it
+        Some(deployed) if kind == WatchKind::Docker => deployed.starts_with("sha256:"),
+        Some(deployed) => bump(deployed, latest).is_some(),
+        None => false,
+    };
+
+    if !behind {
+        database::set_drift_state(conn, kind, repo, latest, None, false)?;
+        return Ok((None, false));
+    }
+
+    // Behind since the latest release we know of was published, or since now if its date is unknown
+    let behind_since = behind_since.unwrap_or_else(|| {
+        chrono::DateTime::parse_from_rfc3339(published_at)
+            .map(|d| d.to_rfc3339())
+            .unwrap_or_else(|_| chrono::Utc::now().to_rfc3339())
+    });
+
+    let (major, minor, patch) = deployed
+        .and_then(|d| version_distance(d, latest))
+        .unwrap_or((0, 0, 0));
+    let drift = Drift { major, minor, patch, days_behind: days_since(&behind_since) };
+
+    let alert = !alerted && exceeds_threshold(&drift, config);
+    database::set_drift_state(conn, kind, repo, latest, Some(&behind_since), alerted || alert)?;
+
+    Ok((Some(drift), alert))
+}
+
+// Attach deployed versions and drift to the fetched releases and collect the escalation alerts to send
+pub fn evaluate(
+    conn: &Connection,
+    github_releases: &mut [GithubReleaseInfo],
+    docker_releases: &mut [DockerReleaseInfo],
+    config: &Config,
+) -> SqliteResult<Vec<DriftAlert>> {
+    let mut alerts = Vec::new();
+
+    let github_deployed = database::get_deployed_versions(conn, WatchKind::Github)?;
+    for release in github_releases.iter_mut() {
+        release.deployed_version = github_deployed.get(&release.repo).cloned();
+        match evaluate_watch(conn, WatchKind::Github, &release.repo, release.deployed_version.as_deref(),
+                             &release.tag_name, &release.published_at, config) {
+            Ok((drift, alert)) => {
+                if alert {
+                    if let (Some(deployed), Some(drift)) = (&release.deployed_version, &drift) {
+                        alerts.push(DriftAlert {
+                            kind: WatchKind::Github,
+                            repo: release.repo.clone(),
+                            deployed_version: deployed.clone(),
+                            latest_version: release.tag_name.clone(),
+                            html_url: release.html_url.clone(),
+                            drift: drift.clone(),
+                        });
+                    }
+                }
+                release.drift = drift;
+            }
+            Err(e) => error!("Error while computing drift for {}: {}", release.repo, e),
+        }
+    }
+
+    let docker_deployed = database::get_deployed_versions(conn, WatchKind::Docker)?;
+    for release in docker_releases.iter_mut() {
+        release.deployed_version = docker_deployed.get(&release.repo).cloned();
+        match evaluate_watch(conn, WatchKind::Docker, &release.repo, release.deployed_version.as_deref(),
+                             &release.digest, &release.published_at, config) {
+            Ok((drift, alert)) => {
+                if alert {
+                    if let (Some(deployed), Some(drift)) = (&release.deployed_version, &drift) {
+                        alerts.push(DriftAlert {
+                            kind: WatchKind::Docker,
+                            repo: release.repo.clone(),
+                            deployed_version: deployed.clone(),
+                            latest_version: release.digest.clone(),
+                            html_url: release.html_url.clone(),
+                            drift: drift.clone(),
+                        });
+                    }
+                }
+                release.drift = drift;
+            }
+            Err(e) => error!("Error while computing drift for {}: {}", release.repo, e),
+        }
+    }
+
+    if !alerts.is_empty() {
+        info!("{} watches drifted past the configured threshold", alerts.len());
+    }
+
+    Ok(alerts)
+}
+
+pub fn watch_drifts(conn: &Connection) -> SqliteResult<Vec<WatchDrift>> {
+    let mut drifts = Vec::new();
+
+    for kind in [WatchKind::Github, WatchKind::Docker] {
+        for state in database::get_deployment_states(conn, kind)? {
+            let drift = state.behind_since.map(|since| {
+                let (major, minor, patch) = state.latest_version.as_deref()
+                    .and_then(|latest| version_distance(&state.deployed_version, latest))
+                    .unwrap_or((0, 0, 0));
+                Drift { major, minor, patch, days_behind: days_since(&since) }
+            });
+            drifts.push(WatchDrift {
+                kind,
+                repo: state.repo,
+                deployed_version: state.deployed_version,
+                latest_version: state.latest_version,
+                drift,
+            });
+        }
+    }
+
+    Ok(drifts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_parsed_with_or_without_prefix() {
+        assert_eq!(parse_version("1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_version("v1.2.3"), Some((1, 2, 3)));
+        assert_eq!(parse_version("V10.0"), Some((10, 0, 0)));
+        assert_eq!(parse_version("release-1.2"), Some((1, 2, 0)));
+        assert_eq!(parse_version("ntfy_alerts-v2"), Some((2, 0, 0)));
+        assert_eq!(parse_version("cli/v0.4.1"), Some((0, 4, 1)));
+    }
+
+    #[test]
+    fn pre_release_and_build_suffixes_are_ignored() {
+        assert_eq!(parse_version("1.2.3-rc.1"), Some((1, 2, 3)));
+        assert_eq!(parse_version("v1.2.3+build.5"), Some((1, 2, 3)));
+        assert_eq!(parse_version("2.0.0-beta+exp.sha.5114f85"), Some((2, 0, 0)));
+        assert_eq!(parse_version("1.2.3rc1"), Some((1, 2, 3)));
+        assert_eq!(bump("1.2.3-rc.1", "1.2.3"), None);
+    }
+
+    #[test]
+    fn tags_that_are_not_versions() {
+        for tag in ["latest", "nightly", "", "a1b2c3d", "stable2", "vv1.0"] {
+            assert_eq!(parse_version(tag), None, "{}", tag);
+        }
+        assert_eq!(bump("latest", "v1.0.0"), None);
+        assert_eq!(version_distance("v1.0.0", "nightly"), None);
+    }
+
+    #[test]
+    fn bump_is_the_highest_changed_component() {
+        assert_eq!(bump("v1.2.3", "v2.0.0"), Some(Bump::Major));
+        assert_eq!(bump("v1.2.3", "v1.3.0"), Some(Bump::Minor));
+        assert_eq!(bump("v1.2.3", "v1.2.4"), Some(Bump::Patch));
+        assert_eq!(bump("v1.2.3", "1.2.3"), None);
+    }
+
+    #[test]
+    fn distance_counts_the_highest_component_only() {
+        assert_eq!(version_distance("1.4.2", "3.0.1"), Some((2, 0, 0)));
+        assert_eq!(version_distance("v1.4.2", "v1.7.0"), Some((0, 3, 0)));
+        assert_eq!(version_distance("v1.4.2", "v1.4.9"), Some((0, 0, 7)));
+        assert_eq!(version_distance("v1.4.2", "v1.4.2"), Some((0, 0, 0)));
+    }
+
+    #[test]
+    fn deployed_version_newer_than_the_latest_is_not_behind() {
+        assert_eq!(bump("v2.1.0", "v2.0.5"), None);
+        assert_eq!(version_distance("v2.1.0", "v2.0.5"), Some((0, 0, 0)));
+        assert_eq!(version_distance("v3.0.0", "v2.9.9"), Some((0, 0, 0)));
+    }
+
+    #[test]
+    fn summary_lists_every_component() {
+        let drift = Drift { major: 1, minor: 2, patch: 0, days_behind: Some(45) };
+        assert_eq!(drift.summary(), "1 major, 2 minor, 45 days behind");
+    }
+}
@@ -1,5 +1,6 @@
 use log::{error, info};
 use reqwest::header::HeaderMap;
+use crate::metrics;
 use crate::models::{GithubRelease, GithubReleaseInfo};
 
 pub async fn get_latest_releases(
@@ -15,36 +16,42 @@ pub async fn get_latest_releases(
 
     let has_auth = headers.contains_key("Authorization");
     if !has_auth {
-        info!("Aucun token GitHub configuré, les requêtes seront limitées");
+        info!("No GitHub token configured, requests will be rate limited");
     }
 
     for repo in repos {
         let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
 
-        match client.get(&url).headers(headers.clone()).send().await {
+        let response = client.get(&url).headers(headers.clone()).send().await;
+        metrics::record_fetch("github", &response);
+
+        match response {
             Ok(response) => {
+                metrics::record_github_rate_limit(&response);
                 if response.status().is_success() {
                     if let Ok(release) = response.json::<GithubRelease>().await {
                         let changelog = get_changelog(repo, client, headers.clone()).await;
 
                         releases.push(GithubReleaseInfo {
                             repo: repo.clone(),
-                            name: release.name,
                             tag_name: release.tag_name,
                             html_url: release.html_url,
                             changelog,
                             published_at: release.published_at.unwrap_or_else(|| "Unknown date".to_string()),
+                            deployed_version: None,
+                            drift: None,
+                            priority: None,
                         });
                     }
                 } else {
                     let status = response.status();
                     let body = response.text().await.unwrap_or_default();
-                    error!("Erreur lors de la récupération de la release GitHub pour {}: {} - {}",
+                    error!("Error fetching the latest GitHub release of {}: {} - {}",
                            repo, status, body);
                 }
             },
             Err(e) => {
-                error!("Erreur de connexion pour {}: {}", repo, e);
+                error!("Error fetching the latest GitHub release of {}: {}", repo, e);
             }
         }
     }
@@ -52,6 +59,9 @@ pub async fn get_latest_releases(
     releases
 }
 
+// Placeholder used when the release notes could not be fetched
+pub const CHANGELOG_UNAVAILABLE: &str = "Changelog not available";
+
 pub async fn get_changelog(
     repo: &str,
     client: &reqwest::Client,
@@ -59,8 +69,12 @@ pub async fn get_changelog(
 ) -> String {
     let url = format!("https://api.github.com/repos/{}/releases", repo);
 
-    match client.get(&url).headers(headers).send().await {
+    let response = client.get(&url).headers(headers).send().await;
+    metrics::record_fetch("github", &response);
+
+    match response {
         Ok(response) => {
+            metrics::record_github_rate_limit(&response);
             if response.status().is_success() {
                 if let Ok(releases) = response.json::<Vec<GithubRelease>>().await {
                     if !releases.is_empty() {
@@ -76,5 +90,5 @@ pub async fn get_changelog(
         }
     }
 
-    "Changelog not available".to_string()
+    CHANGELOG_UNAVAILABLE.to_string()
 }
\ No newline at end of file
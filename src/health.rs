@@ -0,0 +1,52 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, Mutex};
+
+// Extra time allowed on top of the poll interval before a cycle is considered late
+const GRACE_SECONDS: i64 = 300;
+
+static STARTED_AT: LazyLock<DateTime<Utc>> = LazyLock::new(Utc::now);
+static POLLER_ALIVE: AtomicBool = AtomicBool::new(false);
+static LAST_CYCLE: Mutex<Option<DateTime<Utc>>> = Mutex::new(None);
+
+// Marks the poller as running for as long as it is held, including when the loop unwinds on a panic
+pub struct PollerGuard;
+
+impl PollerGuard {
+    pub fn start() -> Self {
+        LazyLock::force(&STARTED_AT);
+        POLLER_ALIVE.store(true, Ordering::SeqCst);
+        PollerGuard
+    }
+}
+
+impl Drop for PollerGuard {
+    fn drop(&mut self) {
+        POLLER_ALIVE.store(false, Ordering::SeqCst);
+    }
+}
+
+pub fn record_cycle() {
+    *LAST_CYCLE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Utc::now());
+}
+
+#[derive(Debug, Serialize)]
+pub struct PollerStatus {
+    pub alive: bool,
+    pub last_cycle: Option<String>,
+    pub late: bool,
+}
+
+// State of the poll loop, a cycle is late when none completed for two intervals plus a grace period
+pub fn poller_status(interval_seconds: f64) -> PollerStatus {
+    let last_cycle = *LAST_CYCLE.lock().unwrap_or_else(|e| e.into_inner());
+    let max_age = chrono::Duration::seconds(2 * interval_seconds as i64 + GRACE_SECONDS);
+    let reference = last_cycle.unwrap_or(*STARTED_AT);
+
+    PollerStatus {
+        alive: POLLER_ALIVE.load(Ordering::SeqCst),
+        last_cycle: last_cycle.map(|d| d.to_rfc3339()),
+        late: Utc::now().signed_duration_since(reference) > max_age,
+    }
+}
@@ -5,60 +5,229 @@ mod github;
 mod docker;
 mod notifications;
 mod api;
+mod watchlist;
+mod cli;
+mod sources;
+mod manifest;
+mod drift;
+mod metrics;
+mod health;
+mod mqtt;
+#[cfg(test)]
+mod test_support;
 
-use log::{error, info};
-use std::thread;
+use clap::Parser;
+use config::{Config, SharedConfig};
+use database::Connection;
+use models::WatchKind;
+use log::{error, info, warn};
 use std::time::Duration;
-use tokio::task;
-
-// Function to start the API in a separate thread
-fn start_api() {
-    std::thread::spawn(|| {
-        let runtime = tokio::runtime::Runtime::new().unwrap();
-        runtime.block_on(async {
-            match api::start_api().await {
-                Ok(_) => info!("API closed correctly"),
-                Err(e) => error!("API error: {}", e),
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+
+// Reload the configuration every time the process receives SIGHUP
+fn watch_hangup(config: SharedConfig) -> std::io::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("SIGHUP received, reloading configuration");
+            match config.reload().await {
+                Ok(config) => info!("Configuration reloaded, notification targets: {}", config.describe_targets()),
+                Err(e) => {
+                    for line in e.to_string().lines() {
+                        error!("{}", line);
+                    }
+                    error!("Keeping the previous configuration");
+                }
             }
-        });
+        }
     });
+    Ok(())
+}
+
+// Request a shutdown on SIGTERM or SIGINT
+fn watch_shutdown(shutdown: watch::Sender<bool>) -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = terminate.recv() => info!("SIGTERM received, shutting down"),
+            _ = interrupt.recv() => info!("SIGINT received, shutting down"),
+        }
+        let _ = shutdown.send(true);
+    });
+    Ok(())
+}
+
+// Function to start the API on the main runtime
+fn start_api(config: SharedConfig, shutdown: watch::Receiver<bool>) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        match api::start_api(config, shutdown).await {
+            Ok(_) => info!("API closed correctly"),
+            Err(e) => error!("API error: {}", e),
+        }
+    })
+}
+
+// Load the configuration, reporting every problem before exiting
+async fn load_config() -> config::Config {
+    let mut config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            for line in e.to_string().lines() {
+                error!("{}", line);
+            }
+            std::process::exit(1);
+        }
+    };
+    config.login_docker().await;
+    info!("Notification targets: {}", config.describe_targets());
+    config
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
 
-    let config = config::Config::from_env();
+    match cli::Cli::parse().command.unwrap_or(cli::Command::Serve) {
+        cli::Command::Serve => serve().await,
+        cli::Command::Once => once().await,
+        cli::Command::Admin(command) => {
+            if let Err(e) = cli::run(command).await {
+                eprintln!("{}", e.to_string().trim_end());
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    let shared_config = SharedConfig::new(load_config().await);
+
     let (conn_versions, conn_repos) = database::init_databases()?;
 
-    start_api();
+    let (shutdown_tx, shutdown) = watch::channel(false);
+    watch_shutdown(shutdown_tx)?;
+    let api = start_api(shared_config.clone(), shutdown.clone());
+    watch_hangup(shared_config.clone())?;
+
+    if let Err(e) = poll(&shared_config, &conn_versions, &conn_repos, shutdown).await {
+        error!("Version monitoring stopped: {}", e);
+        std::process::exit(1);
+    }
+
+    let _ = api.await;
+    close_databases(conn_versions, conn_repos);
+    info!("Shutdown complete");
+    Ok(())
+}
 
+// Single poll cycle, for cron jobs and CI
+async fn once() -> Result<(), Box<dyn std::error::Error>> {
+    let config = load_config().await;
+    let (conn_versions, conn_repos) = database::init_databases()?;
     let client = reqwest::Client::new();
 
-    if config.auth.is_empty() || (config.ntfy_url.is_none() && config.gotify_url.is_none()
-        && config.discord_webhook_url.is_none() && config.slack_webhook_url.is_none()) {
-        error!("Incorrect configuration!");
-        error!("auth: can be generated with the command: echo -n 'username:password' | base64");
-        error!("NTFY_URL: URL of the ntfy server");
-        error!("GOTIFY_URL: URL of the gotify server");
-        error!("GOTIFY_TOKEN: Gotify token");
-        error!("DISCORD_WEBHOOK_URL: Discord webhook URL");
-        error!("SLACK_WEBHOOK_URL: Slack webhook URL");
-        error!("GHNTFY_TIMEOUT: interval between checks");
-        return Ok(());
+    run_cycle(&config, &client, &conn_versions, &conn_repos).await?;
+
+    close_databases(conn_versions, conn_repos);
+    Ok(())
+}
+
+fn close_databases(conn_versions: Connection, conn_repos: Connection) {
+    for conn in [conn_versions, conn_repos] {
+        if let Err((_, e)) = conn.close() {
+            error!("Error while closing database: {}", e);
+        }
     }
+}
+
+// Time left to the poll cycle in progress when a shutdown is requested, below the 10s a container stop waits
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+// Poll loop, returns when a shutdown is requested or when a cycle fails.
+// A shutdown starts no new cycle and gives the current one SHUTDOWN_GRACE to send and record its notifications,
+// past that it is interrupted and the releases it did not record are notified again on the next start.
+async fn poll(
+    shared_config: &SharedConfig,
+    conn_versions: &Connection,
+    conn_repos: &Connection,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let _guard = health::PollerGuard::start();
+    let client = reqwest::Client::new();
 
     info!("Starting version monitoring...");
 
-    loop {
-        let github_repos = database::get_watched_repos(&conn_repos)?;
-        let docker_repos = database::get_docker_watched_repos(&conn_repos)?;
+    while !*shutdown.borrow() {
+        // Each cycle works on a snapshot, a reload takes effect on the next one
+        let config = shared_config.current();
+        let cycle_start = std::time::Instant::now();
 
-        let github_releases = github::get_latest_releases(&github_repos, &client, config.github_headers()).await;
-        let docker_releases = docker::get_latest_docker_releases(&docker_repos, &client, config.docker_headers()).await;
+        let cycle = run_cycle(&config, &client, conn_versions, conn_repos);
+        tokio::pin!(cycle);
+        tokio::select! {
+            result = &mut cycle => result?,
+            _ = shutdown.wait_for(|stop| *stop) => {
+                info!("Shutdown requested, waiting up to {}s for the current poll cycle", SHUTDOWN_GRACE.as_secs());
+                match tokio::time::timeout(SHUTDOWN_GRACE, &mut cycle).await {
+                    Ok(result) => result?,
+                    Err(_) => warn!("The current poll cycle did not finish in time, it is interrupted"),
+                }
+                break;
+            }
+        }
+        metrics::record_poll_cycle(cycle_start.elapsed().as_secs_f64());
+        health::record_cycle();
 
-        notifications::send_notifications(github_releases, docker_releases, &config, &conn_versions).await;
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs_f64(config.timeout)) => {}
+            _ = shutdown.wait_for(|stop| *stop) => {}
+        }
+    }
+
+    info!("Version monitoring stopped");
+    Ok(())
+}
 
-        tokio::time::sleep(Duration::from_secs_f64(config.timeout)).await;
+// Fetch every watched repository and image, then send the notifications and drift alerts
+async fn run_cycle(
+    config: &Config,
+    client: &reqwest::Client,
+    conn_versions: &Connection,
+    conn_repos: &Connection,
+) -> Result<(), Box<dyn std::error::Error>> {
+    sources::sync_sources(conn_repos, client, config.github_headers(), Duration::from_secs_f64(config.sources_refresh)).await;
+
+    let github_repos = database::get_watched_repos(conn_repos)?;
+    let docker_repos = database::get_docker_watched_repos(conn_repos)?;
+
+    let mut github_releases = github::get_latest_releases(&github_repos, client, config.github_headers()).await;
+    let mut docker_releases = docker::get_latest_docker_releases(&docker_repos, client, config.docker_headers()).await;
+    let drift_alerts = drift::evaluate(conn_repos, &mut github_releases, &mut docker_releases, config)?;
+
+    let priorities = database::get_priorities(conn_repos, WatchKind::Github)?;
+    for release in &mut github_releases {
+        release.priority = priorities.get(&release.repo).copied();
     }
-}
\ No newline at end of file
+    let priorities = database::get_priorities(conn_repos, WatchKind::Docker)?;
+    for release in &mut docker_releases {
+        release.priority = priorities.get(&release.repo).copied();
+    }
+
+    if let Some(broker) = &config.mqtt {
+        let watched: Vec<(WatchKind, String)> = github_repos.iter().map(|repo| (WatchKind::Github, repo.clone()))
+            .chain(docker_repos.iter().map(|repo| (WatchKind::Docker, repo.clone())))
+            .collect();
+        if let Err(e) = mqtt::publish_updates(broker, &watched, &github_releases, &docker_releases, conn_versions).await {
+            error!("Error while publishing update entities: {}", e);
+        }
+    }
+
+    if let Err(e) = notifications::send_notifications(github_releases, docker_releases, config, conn_versions).await {
+        error!("Error while sending notifications: {}", e);
+    }
+    notifications::send_drift_alerts(drift_alerts, config).await;
+    Ok(())
+}
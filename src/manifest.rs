@@ -0,0 +1,479 @@
+use log::info;
+use reqwest::header::{HeaderMap, HeaderValue, USER_AGENT};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use crate::database::{self, Connection};
+use crate::models::WatchKind;
+use crate::watchlist::{ImportError, ImportReport};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestKind {
+    CargoLock,
+    PackageLock,
+    GoMod,
+    DockerCompose,
+}
+
+impl ManifestKind {
+    // Detect the manifest type from its file name
+    pub fn from_name(name: &str) -> Option<Self> {
+        let file_name = Path::new(name).file_name()?.to_str()?;
+        match file_name {
+            "Cargo.lock" => Some(ManifestKind::CargoLock),
+            "package-lock.json" => Some(ManifestKind::PackageLock),
+            "go.mod" => Some(ManifestKind::GoMod),
+            "docker-compose.yml" | "docker-compose.yaml" | "compose.yml" | "compose.yaml" => Some(ManifestKind::DockerCompose),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ecosystem {
+    Crate,
+    Npm,
+    Go,
+    Image,
+}
+
+// A dependency found in a manifest, with the version in use
+#[derive(Debug, Clone)]
+struct Dependency {
+    ecosystem: Ecosystem,
+    name: String,
+    version: String,
+}
+
+#[derive(Deserialize)]
+struct CargoLock {
+    #[serde(default)]
+    package: Vec<CargoPackage>,
+}
+
+#[derive(Deserialize)]
+struct CargoPackage {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PackageLock {
+    #[serde(default)]
+    packages: HashMap<String, NpmPackage>,
+    #[serde(default)]
+    dependencies: HashMap<String, NpmPackage>,
+}
+
+#[derive(Deserialize)]
+struct NpmPackage {
+    version: Option<String>,
+    #[serde(default)]
+    dependencies: HashMap<String, serde_json::Value>,
+    #[serde(default, rename = "devDependencies")]
+    dev_dependencies: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ComposeFile {
+    #[serde(default)]
+    services: HashMap<String, ComposeService>,
+}
+
+#[derive(Deserialize)]
+struct ComposeService {
+    image: Option<String>,
+}
+
+fn parse_cargo_lock(data: &str) -> Result<Vec<Dependency>, Box<dyn Error>> {
+    let lock: CargoLock = toml::from_str(data)?;
+
+    // Packages without a source are the workspace members themselves
+    Ok(lock.package.into_iter()
+        .filter(|p| p.source.as_deref().is_some_and(|s| s.starts_with("registry+")))
+        .map(|p| Dependency { ecosystem: Ecosystem::Crate, name: p.name, version: p.version })
+        .collect())
+}
+
+fn parse_package_lock(data: &str) -> Result<Vec<Dependency>, Box<dyn Error>> {
+    let lock: PackageLock = serde_json::from_str(data)?;
+    let mut deps = Vec::new();
+
+    // Lockfile v2/v3: only keep the direct dependencies of the root package
+    if let Some(root) = lock.packages.get("") {
+        for name in root.dependencies.keys().chain(root.dev_dependencies.keys()) {
+            let version = lock.packages.get(&format!("node_modules/{}", name)).and_then(|p| p.version.clone());
+            if let Some(version) = version {
+                deps.push(Dependency { ecosystem: Ecosystem::Npm, name: name.clone(), version });
+            }
+        }
+        return Ok(deps);
+    }
+
+    // Lockfile v1
+    for (name, package) in lock.dependencies {
+        if let Some(version) = package.version {
+            deps.push(Dependency { ecosystem: Ecosystem::Npm, name, version });
+        }
+    }
+    Ok(deps)
+}
+
+// `replace old [version] => new [version]`, without a version on the right the module is a local directory
+struct GoReplace<'a> {
+    module: &'a str,
+    version: Option<&'a str>,
+    target: Option<(&'a str, &'a str)>,
+}
+
+fn parse_go_replace(directive: &str) -> Option<GoReplace<'_>> {
+    let (old, new) = directive.split_once("=>")?;
+    let mut old = old.split_whitespace();
+    let mut new = new.split_whitespace();
+    let module = old.next()?;
+    let path = new.next()?;
+    Some(GoReplace { module, version: old.next(), target: new.next().map(|version| (path, version)) })
+}
+
+fn parse_go_mod(data: &str) -> Result<Vec<Dependency>, Box<dyn Error>> {
+    let mut requires = Vec::new();
+    let mut replaces = Vec::new();
+    let mut block = None;
+
+    for line in data.lines() {
+        let (line, comment) = line.split_once("//").unwrap_or((line, ""));
+        let line = line.trim();
+        let (directive, rest) = match block {
+            Some(_) if line == ")" => {
+                block = None;
+                continue;
+            }
+            Some(directive) => (directive, line),
+            None => match line.split_once(char::is_whitespace) {
+                Some((directive @ ("require" | "replace"), rest)) if rest.trim() == "(" => {
+                    block = Some(directive);
+                    continue;
+                }
+                Some((directive @ ("require" | "replace"), rest)) => (directive, rest.trim()),
+                _ => continue,
+            },
+        };
+        match directive {
+            "require" if comment.trim() != "indirect" => requires.push(rest),
+            "replace" => replaces.extend(parse_go_replace(rest)),
+            _ => {}
+        }
+    }
+
+    let mut deps = Vec::new();
+    for requirement in requires {
+        let mut parts = requirement.split_whitespace();
+        let (Some(name), Some(version)) = (parts.next(), parts.next()) else { continue };
+        // A replacement of this version wins over one of every version
+        let replace = replaces.iter().find(|r| r.module == name && r.version == Some(version))
+            .or_else(|| replaces.iter().find(|r| r.module == name && r.version.is_none()));
+        let (name, version) = match replace {
+            Some(GoReplace { target: Some(target), .. }) => *target,
+            // Replaced by a local directory, there is no upstream to watch
+            Some(GoReplace { target: None, .. }) => continue,
+            None => (name, version),
+        };
+        deps.push(Dependency {
+            ecosystem: Ecosystem::Go,
+            name: name.to_string(),
+            version: version.trim_end_matches("+incompatible").to_string(),
+        });
+    }
+
+    Ok(deps)
+}
+
+fn parse_docker_compose(data: &str) -> Result<Vec<Dependency>, Box<dyn Error>> {
+    let compose: ComposeFile = serde_yaml::from_str(data)?;
+
+    Ok(compose.services.into_values()
+        .filter_map(|service| service.image)
+        .map(|image| {
+            // A pinned digest is the version in use, drift of Docker watches is measured between digests
+            let (image, digest) = match image.split_once('@') {
+                Some((image, digest)) => (image.to_string(), Some(digest.to_string())),
+                None => (image, None),
+            };
+            // The tag separator is the last ':' after the last '/', a ':' before it is a registry port
+            let slash = image.rfind('/').map(|i| i + 1).unwrap_or(0);
+            let (name, tag) = match image[slash..].rfind(':') {
+                Some(colon) => (image[..slash + colon].to_string(), image[slash + colon + 1..].to_string()),
+                None => (image, "latest".to_string()),
+            };
+            Dependency { ecosystem: Ecosystem::Image, name, version: digest.unwrap_or(tag) }
+        })
+        .collect())
+}
+
+// Extract `owner/name` from any kind of GitHub URL
+fn github_repo_from_url(url: &str) -> Option<String> {
+    let start = url.find("github.com")? + "github.com".len();
+    let path = url[start..].trim_start_matches([':', '/']);
+    let mut parts = path.split(['/', '#', '?']);
+    let owner = parts.next().filter(|p| !p.is_empty())?;
+    let name = parts.next().filter(|p| !p.is_empty())?.trim_end_matches(".git");
+    Some(format!("{}/{}", owner, name))
+}
+
+async fn resolve(dep: &Dependency, client: &reqwest::Client, headers: &HeaderMap) -> Result<(WatchKind, String), String> {
+    match dep.ecosystem {
+        Ecosystem::Crate => {
+            let url = format!("https://crates.io/api/v1/crates/{}", dep.name);
+            let json = fetch_json(&url, client, headers).await?;
+            json["crate"]["repository"].as_str()
+                .and_then(github_repo_from_url)
+                .map(|repo| (WatchKind::Github, repo))
+                .ok_or_else(|| format!("crate {} has no GitHub repository", dep.name))
+        }
+        Ecosystem::Npm => {
+            let url = format!("https://registry.npmjs.org/{}", dep.name.replace('/', "%2F"));
+            let json = fetch_json(&url, client, headers).await?;
+            let repository = &json["repository"];
+            repository.as_str().or_else(|| repository["url"].as_str())
+                .and_then(github_repo_from_url)
+                .map(|repo| (WatchKind::Github, repo))
+                .ok_or_else(|| format!("npm package {} has no GitHub repository", dep.name))
+        }
+        Ecosystem::Go => {
+            github_repo_from_url(&dep.name)
+                .map(|repo| (WatchKind::Github, repo))
+                .ok_or_else(|| format!("Go module {} is not hosted on GitHub", dep.name))
+        }
+        Ecosystem::Image => {
+            let parts: Vec<&str> = dep.name.split('/').collect();
+            match parts.as_slice() {
+                [name] => Ok((WatchKind::Docker, format!("library/{}", name))),
+                [namespace, name] if !namespace.contains(['.', ':']) => Ok((WatchKind::Docker, format!("{}/{}", namespace, name))),
+                [registry, namespace, name] if *registry == "docker.io" => Ok((WatchKind::Docker, format!("{}/{}", namespace, name))),
+                _ => Err(format!("image {} is not hosted on Docker Hub", dep.name)),
+            }
+        }
+    }
+}
+
+async fn fetch_json(url: &str, client: &reqwest::Client, headers: &HeaderMap) -> Result<serde_json::Value, String> {
+    let response = client.get(url).headers(headers.clone()).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("{} returned {}", url, response.status()));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}
+
+// Repositories and images resolved from a manifest, with the version in use
+pub struct ResolvedManifest {
+    pub name: String,
+    pub watches: HashMap<(WatchKind, String), String>,
+    pub errors: Vec<ImportError>,
+}
+
+// Parse a manifest and resolve every dependency to a GitHub repository or Docker Hub image
+pub async fn resolve_manifest(
+    client: &reqwest::Client,
+    name: &str,
+    data: &str,
+) -> Result<ResolvedManifest, Box<dyn Error + Send + Sync>> {
+    let kind = ManifestKind::from_name(name)
+        .ok_or_else(|| format!("Unsupported manifest '{}', expected Cargo.lock, package-lock.json, go.mod or docker-compose.yml", name))?;
+
+    let deps = match kind {
+        ManifestKind::CargoLock => parse_cargo_lock(data),
+        ManifestKind::PackageLock => parse_package_lock(data),
+        ManifestKind::GoMod => parse_go_mod(data),
+        ManifestKind::DockerCompose => parse_docker_compose(data),
+    }.map_err(|e| e.to_string())?;
+    info!("Found {} dependencies in {}", deps.len(), name);
+
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_static("github-ntfy/1.0"));
+
+    let mut manifest = ResolvedManifest { name: name.to_string(), watches: HashMap::new(), errors: Vec::new() };
+
+    for (index, dep) in deps.iter().enumerate() {
+        match resolve(dep, client, &headers).await {
+            Ok((kind, repo)) => {
+                // Several crates often live in the same repository, prefer the version of the one named after it
+                let named_after = repo.split('/').next_back() == Some(dep.name.as_str());
+                if named_after || !manifest.watches.contains_key(&(kind, repo.clone())) {
+                    manifest.watches.insert((kind, repo), dep.version.clone());
+                }
+            }
+            Err(e) => manifest.errors.push(ImportError { entry: index + 1, repo: dep.name.clone(), error: e }),
+        }
+    }
+
+    Ok(manifest)
+}
+
+// Watch every resolved dependency with the version in use as deployed version,
+// dependencies already watched only get their deployed version updated
+pub fn apply_manifest(conn: &Connection, manifest: ResolvedManifest) -> rusqlite::Result<ImportReport> {
+    let marker = format!("manifest:{}", manifest.name);
+    let mut report = ImportReport { errors: manifest.errors, ..ImportReport::default() };
+
+    for ((kind, repo), version) in manifest.watches {
+        if database::is_watched(conn, kind, &repo)? {
+            database::set_deployed_version(conn, kind, &repo, Some(&version))?;
+            report.duplicates.push(repo);
+        } else {
+            database::add_manifest_watch(conn, kind, &repo, &marker, &version)?;
+            report.added.push(repo);
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(deps: Vec<Dependency>) -> Vec<(String, String)> {
+        let mut versions: Vec<(String, String)> = deps.into_iter().map(|dep| (dep.name, dep.version)).collect();
+        versions.sort();
+        versions
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected.iter().map(|(name, version)| (name.to_string(), version.to_string())).collect()
+    }
+
+    #[test]
+    fn cargo_lock_keeps_registry_crates() {
+        let data = r#"
+version = 4
+
+[[package]]
+name = "github-ntfy"
+version = "2.0.0"
+dependencies = ["serde", "shared"]
+
+[[package]]
+name = "shared"
+version = "0.1.0"
+
+[[package]]
+name = "patched"
+version = "0.3.0"
+source = "git+https://github.com/owner/patched?branch=main#0123456"
+
+[[package]]
+name = "serde"
+version = "1.0.219"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "5f0e2c6ed6606019b4e29e69dbaba95b11854410e5347d525002456dbbb786b6"
+"#;
+
+        // Workspace members, path and git packages have nothing to watch
+        assert_eq!(versions(parse_cargo_lock(data).unwrap()), pairs(&[("serde", "1.0.219")]));
+    }
+
+    #[test]
+    fn package_lock_keeps_the_direct_dependencies() {
+        let data = r#"{
+            "name": "app",
+            "lockfileVersion": 3,
+            "packages": {
+                "": {
+                    "name": "app",
+                    "dependencies": { "express": "^4.19.0" },
+                    "devDependencies": { "@types/node": "^20.0.0" }
+                },
+                "node_modules/express": { "version": "4.19.2" },
+                "node_modules/@types/node": { "version": "20.14.2" },
+                "node_modules/body-parser": { "version": "1.20.2" }
+            }
+        }"#;
+
+        assert_eq!(versions(parse_package_lock(data).unwrap()), pairs(&[("@types/node", "20.14.2"), ("express", "4.19.2")]));
+    }
+
+    #[test]
+    fn package_lock_v1_lists_every_dependency() {
+        let data = r#"{ "lockfileVersion": 1, "dependencies": { "lodash": { "version": "4.17.21" } } }"#;
+
+        assert_eq!(versions(parse_package_lock(data).unwrap()), pairs(&[("lodash", "4.17.21")]));
+    }
+
+    #[test]
+    fn go_mod_skips_indirect_requirements_and_applies_replacements() {
+        let data = r#"
+module example.com/app
+
+go 1.22
+
+require github.com/spf13/cobra v1.8.0
+
+require (
+	github.com/gorilla/mux v1.8.1
+	github.com/old/lib v1.2.0
+	github.com/forked/lib v0.4.0
+	github.com/local/lib v0.1.0
+	github.com/docker/docker v24.0.7+incompatible
+	golang.org/x/sys v0.20.0 // indirect
+)
+
+replace github.com/old/lib => github.com/new/lib v1.3.0
+
+replace (
+	github.com/forked/lib v0.4.0 => github.com/me/lib v0.4.1 // security fix
+	github.com/forked/lib v0.3.0 => github.com/other/lib v0.3.9
+	github.com/local/lib => ../lib
+)
+"#;
+
+        assert_eq!(versions(parse_go_mod(data).unwrap()), pairs(&[
+            ("github.com/docker/docker", "v24.0.7"),
+            ("github.com/gorilla/mux", "v1.8.1"),
+            ("github.com/me/lib", "v0.4.1"),
+            ("github.com/new/lib", "v1.3.0"),
+            ("github.com/spf13/cobra", "v1.8.0"),
+        ]));
+    }
+
+    #[test]
+    fn compose_images_keep_registry_port_and_digest() {
+        let data = r#"
+services:
+  web:
+    image: nginx:1.27
+  db:
+    image: postgres
+  app:
+    image: registry.example.com:5000/team/app:2.3.1
+  local:
+    image: localhost:5000/tool
+  pinned:
+    image: ghcr.io/owner/api:1.0@sha256:4f1c3b6e9a2d7c8b5e0f1a2b3c4d5e6f
+  built:
+    build: .
+"#;
+
+        assert_eq!(versions(parse_docker_compose(data).unwrap()), pairs(&[
+            ("ghcr.io/owner/api", "sha256:4f1c3b6e9a2d7c8b5e0f1a2b3c4d5e6f"),
+            ("localhost:5000/tool", "latest"),
+            ("nginx", "1.27"),
+            ("postgres", "latest"),
+            ("registry.example.com:5000/team/app", "2.3.1"),
+        ]));
+    }
+
+    #[test]
+    fn github_repositories_are_found_in_any_url_form() {
+        for url in [
+            "https://github.com/serde-rs/serde",
+            "git+https://github.com/serde-rs/serde.git",
+            "git@github.com:serde-rs/serde.git",
+            "https://github.com/serde-rs/serde/tree/master/serde",
+        ] {
+            assert_eq!(github_repo_from_url(url).as_deref(), Some("serde-rs/serde"), "{}", url);
+        }
+        assert_eq!(github_repo_from_url("https://gitlab.com/owner/repo"), None);
+    }
+}
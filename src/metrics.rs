@@ -0,0 +1,100 @@
+use prometheus::{Encoder, Gauge, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use std::sync::LazyLock;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(|| {
+    let registry = Registry::new();
+    registry.register(Box::new(FETCHES.clone())).unwrap();
+    registry.register(Box::new(POLL_CYCLE_DURATION.clone())).unwrap();
+    registry.register(Box::new(RELEASES_DETECTED.clone())).unwrap();
+    registry.register(Box::new(NOTIFICATIONS.clone())).unwrap();
+    registry.register(Box::new(GITHUB_RATE_LIMIT_REMAINING.clone())).unwrap();
+    registry.register(Box::new(LAST_SUCCESSFUL_CYCLE.clone())).unwrap();
+    registry.register(Box::new(PENDING_EVENTS.clone())).unwrap();
+    registry
+});
+
+static FETCHES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new("ghntfy_fetches_total", "Requests made to GitHub and Docker Hub, by HTTP status"),
+        &["source", "status"],
+    ).unwrap()
+});
+
+static POLL_CYCLE_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    Histogram::with_opts(
+        HistogramOpts::new("ghntfy_poll_cycle_duration_seconds", "Duration of a complete poll cycle")
+            .buckets(vec![0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0]),
+    ).unwrap()
+});
+
+static RELEASES_DETECTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new("ghntfy_releases_detected_total", "New releases and Docker digests detected"),
+        &["source"],
+    ).unwrap()
+});
+
+static NOTIFICATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    IntCounterVec::new(
+        Opts::new("ghntfy_notifications_total", "Notifications delivered to each target, by result"),
+        &["target", "result"],
+    ).unwrap()
+});
+
+static GITHUB_RATE_LIMIT_REMAINING: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new("ghntfy_github_rate_limit_remaining", "Remaining GitHub API requests in the current window").unwrap()
+});
+
+static LAST_SUCCESSFUL_CYCLE: LazyLock<Gauge> = LazyLock::new(|| {
+    Gauge::new("ghntfy_last_successful_cycle_timestamp_seconds", "Unix time of the last completed poll cycle").unwrap()
+});
+
+static PENDING_EVENTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    IntGauge::new("ghntfy_pending_events", "Release events waiting to be accepted by an event bus").unwrap()
+});
+
+// Record a request made to GitHub (`github`) or Docker Hub (`docker`)
+pub fn record_fetch(source: &str, response: &Result<reqwest::Response, reqwest::Error>) {
+    let status = match response {
+        Ok(response) => response.status().as_u16().to_string(),
+        Err(_) => "error".to_string(),
+    };
+    FETCHES.with_label_values(&[source, &status]).inc();
+}
+
+pub fn record_github_rate_limit(response: &reqwest::Response) {
+    let remaining = response.headers()
+        .get("x-ratelimit-remaining")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i64>().ok());
+    if let Some(remaining) = remaining {
+        GITHUB_RATE_LIMIT_REMAINING.set(remaining);
+    }
+}
+
+pub fn record_poll_cycle(seconds: f64) {
+    POLL_CYCLE_DURATION.observe(seconds);
+    LAST_SUCCESSFUL_CYCLE.set(chrono::Utc::now().timestamp() as f64);
+}
+
+pub fn record_release(source: &str) {
+    RELEASES_DETECTED.with_label_values(&[source]).inc();
+}
+
+pub fn record_notification(target: &str, sent: bool) {
+    let result = if sent { "sent" } else { "failed" };
+    NOTIFICATIONS.with_label_values(&[target, result]).inc();
+}
+
+pub fn record_pending_events(count: i64) {
+    PENDING_EVENTS.set(count);
+}
+
+// Every metric in the Prometheus text exposition format
+pub fn render() -> String {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        log::error!("Error while encoding metrics: {}", e);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
@@ -1,23 +1,32 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use crate::drift::Drift;
 
 // Structures for GitHub data
 #[derive(Debug, Deserialize, Clone)]
 pub struct GithubRelease {
-    pub name: String,
     pub tag_name: String,
     pub html_url: String,
     pub published_at: Option<String>,
     pub body: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct GithubRepo {
+    pub full_name: String,
+    #[serde(default)]
+    pub archived: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct GithubReleaseInfo {
     pub repo: String,
-    pub name: String,
     pub tag_name: String,
     pub html_url: String,
     pub changelog: String,
     pub published_at: String,
+    pub deployed_version: Option<String>,
+    pub drift: Option<Drift>,
+    pub priority: Option<Priority>,
 }
 
 // Structures for Docker data
@@ -33,10 +42,146 @@ pub struct DockerReleaseInfo {
     pub digest: String,
     pub html_url: String,
     pub published_at: String,
+    pub deployed_version: Option<String>,
+    pub drift: Option<Drift>,
+    pub priority: Option<Priority>,
+}
+
+// Slack message posted with the bot API for the last version of a repository,
+// its replies go in the thread of the first message posted for the repository
+#[derive(Debug, Clone)]
+pub struct SlackThread {
+    pub channel: String,
+    pub thread_ts: String,
+    pub version: String,
+    pub ts: String,
+}
+
+// Kind of watched repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchKind {
+    Github,
+    Docker,
+}
+
+impl WatchKind {
+    pub fn table(&self) -> &'static str {
+        match self {
+            WatchKind::Github => "watched_repos",
+            WatchKind::Docker => "docker_watched_repos",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            WatchKind::Github => "GitHub",
+            WatchKind::Docker => "Docker",
+        }
+    }
+}
+
+// Event waiting to be published to an event bus target, kept until the bus acknowledges it
+#[derive(Debug, Clone)]
+pub struct PendingEvent {
+    pub id: i64,
+    pub target: String,
+    // Same for every attempt, consumers use it to drop duplicates
+    pub event_id: String,
+    pub payload: Vec<u8>,
+    pub attempts: i64,
+}
+
+// Priority of the notifications of a watch, on the five levels of ntfy that other services map to their scale
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Min,
+    Low,
+    Default,
+    High,
+    Urgent,
 }
 
-pub struct NotifiedRelease {
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Min => "min",
+            Priority::Low => "low",
+            Priority::Default => "default",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    // Names and the numbers 1 to 5 used by ntfy
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "min" | "1" => Some(Priority::Min),
+            "low" | "2" => Some(Priority::Low),
+            "default" | "3" => Some(Priority::Default),
+            "high" | "4" => Some(Priority::High),
+            "urgent" | "max" | "5" => Some(Priority::Urgent),
+            _ => None,
+        }
+    }
+}
+
+// Source that keeps a set of watched GitHub repositories in sync
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    Starred,
+    Org,
+}
+
+impl SourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceKind::Starred => "starred",
+            SourceKind::Org => "org",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "starred" => Some(SourceKind::Starred),
+            "org" => Some(SourceKind::Org),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchSource {
+    #[serde(default)]
+    pub id: i64,
+    pub kind: SourceKind,
+    pub name: String,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default = "default_releases_only")]
+    pub releases_only: bool,
+    #[serde(default)]
+    pub last_synced: Option<String>,
+}
+
+fn default_releases_only() -> bool {
+    true
+}
+
+impl WatchSource {
+    // Marker stored in the `source` column of every repository created by this source
+    pub fn marker(&self) -> String {
+        format!("{}:{}", self.kind.as_str(), self.name)
+    }
+}
+
+// Version actually running for a watch, next to the latest one seen upstream
+#[derive(Debug, Clone)]
+pub struct DeploymentState {
     pub repo: String,
-    pub tag_name: String,
-    pub notified_at: chrono::DateTime<chrono::Utc>,
-}
\ No newline at end of file
+    pub deployed_version: String,
+    pub latest_version: Option<String>,
+    pub behind_since: Option<String>,
+}
@@ -0,0 +1,370 @@
+use log::{error, info};
+use rumqttc::{AsyncClient, Event, MqttOptions, Outgoing, Packet, QoS, TlsConfiguration, Transport};
+use serde_json::json;
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::time::timeout;
+use crate::database::{self, Connection, SqliteResult};
+use crate::github::CHANGELOG_UNAVAILABLE;
+use crate::models::{DockerReleaseInfo, GithubReleaseInfo, WatchKind};
+use crate::notifications::markdown::{self, truncate_chars, Dialect};
+use crate::notifications::template::{app_name, avatar_url};
+
+// Longest release summary Home Assistant accepts, in characters
+const MAX_SUMMARY: usize = 255;
+// Longest wait for the broker between two packets
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Broker the update entities are published to
+#[derive(Debug, Clone)]
+pub struct Broker {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub tls: bool,
+    pub client_id: String,
+    // Topic prefix Home Assistant listens to for discovery
+    pub discovery_prefix: String,
+    // Prefix of the state topics
+    pub topic_prefix: String,
+}
+
+// Check a topic prefix when the configuration is loaded
+pub fn check_prefix(prefix: &str) -> Result<(), String> {
+    if prefix.is_empty() || prefix.starts_with('/') || prefix.ends_with('/') {
+        return Err(format!("invalid topic prefix '{}'", prefix));
+    }
+    if prefix.contains(['+', '#']) {
+        return Err(format!("topic prefix '{}' must not contain wildcards", prefix));
+    }
+    Ok(())
+}
+
+// Identifier of the entity of a watch, also used in its topics. Topics only take some characters,
+// the hash of the name keeps apart the names that only differ by the others (`a/b-c` and `a/b_c`)
+fn object_id(kind: WatchKind, repo: &str) -> String {
+    let name: String = repo.to_lowercase().chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let hash: String = hmac_sha256::Hash::hash(repo.as_bytes())[..4].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}_{}_{}", kind.label().to_lowercase(), name, hash)
+}
+
+// Node of the discovery topics, only letters, digits, `_` and `-` are allowed
+fn node_id(broker: &Broker) -> String {
+    broker.client_id.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+fn config_topic(broker: &Broker, object_id: &str) -> String {
+    format!("{}/update/{}/{}/config", broker.discovery_prefix, node_id(broker), object_id)
+}
+
+fn state_topic(broker: &Broker, object_id: &str) -> String {
+    format!("{}/{}/state", broker.topic_prefix, object_id)
+}
+
+// Digests are shortened the same way on both sides, so a deployed digest still matches the latest one
+fn short_digest(digest: &str) -> String {
+    match digest.strip_prefix("sha256:") {
+        Some(hash) => format!("sha256:{}", hash.get(..12).unwrap_or(hash)),
+        None => digest.to_string(),
+    }
+}
+
+// State of a watch as shown by its update entity
+struct Entity {
+    object_id: String,
+    repo: String,
+    installed_version: String,
+    latest_version: String,
+    release_url: String,
+    release_summary: Option<String>,
+    picture: Option<String>,
+}
+
+impl Entity {
+    // Without a deployed version the latest one is taken as installed, no update is shown
+    fn github(release: &GithubReleaseInfo) -> Self {
+        let summary = Some(release.changelog.as_str())
+            .filter(|changelog| *changelog != CHANGELOG_UNAVAILABLE && !changelog.trim().is_empty())
+            .map(|changelog| truncate_chars(markdown::convert(changelog, Dialect::Plain, None).trim(), MAX_SUMMARY));
+        Entity {
+            object_id: object_id(WatchKind::Github, &release.repo),
+            repo: release.repo.clone(),
+            installed_version: release.deployed_version.clone().unwrap_or_else(|| release.tag_name.clone()),
+            latest_version: release.tag_name.clone(),
+            release_url: release.html_url.clone(),
+            release_summary: summary,
+            picture: avatar_url(&release.repo),
+        }
+    }
+
+    fn docker(release: &DockerReleaseInfo) -> Self {
+        Entity {
+            object_id: object_id(WatchKind::Docker, &release.repo),
+            repo: release.repo.clone(),
+            installed_version: short_digest(release.deployed_version.as_deref().unwrap_or(&release.digest)),
+            latest_version: short_digest(&release.digest),
+            release_url: release.html_url.clone(),
+            release_summary: None,
+            picture: None,
+        }
+    }
+
+    fn discovery(&self, broker: &Broker) -> serde_json::Value {
+        json!({
+            "name": self.repo,
+            "unique_id": format!("{}_{}", node_id(broker), self.object_id),
+            "state_topic": state_topic(broker, &self.object_id),
+            "entity_picture": self.picture,
+            "device": {
+                "identifiers": [node_id(broker)],
+                "name": "github-ntfy",
+                "manufacturer": "BreizhHardware",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+            },
+            "origin": {
+                "name": "github-ntfy",
+                "sw_version": env!("CARGO_PKG_VERSION"),
+                "support_url": "https://github.com/BreizhHardware/ntfy_alerts",
+            },
+        })
+    }
+
+    fn state(&self) -> serde_json::Value {
+        json!({
+            "installed_version": self.installed_version,
+            "latest_version": self.latest_version,
+            "title": app_name(&self.repo),
+            "release_url": self.release_url,
+            "release_summary": self.release_summary,
+        })
+    }
+}
+
+// Publish retained messages and wait until the broker has acknowledged all of them
+async fn send(broker: &Broker, messages: Vec<(String, Vec<u8>)>) -> Result<(), String> {
+    let mut options = MqttOptions::new(&broker.client_id, &broker.host, broker.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let (Some(username), Some(password)) = (&broker.username, &broker.password) {
+        options.set_credentials(username, password);
+    }
+    if broker.tls {
+        options.set_transport(Transport::Tls(TlsConfiguration::Native));
+    }
+
+    let expected = messages.len();
+    let (client, mut eventloop) = AsyncClient::new(options, 16);
+    let publisher = client.clone();
+    // The client queue is bounded, messages are queued while the event loop below sends them
+    let queue = tokio::spawn(async move {
+        for (topic, payload) in messages {
+            if publisher.publish(topic, QoS::AtLeastOnce, true, payload).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut acknowledged = 0;
+    let result = loop {
+        match timeout(TIMEOUT, eventloop.poll()).await {
+            Ok(Ok(Event::Incoming(Packet::PubAck(_)))) => {
+                acknowledged += 1;
+                if acknowledged == expected {
+                    let _ = client.try_disconnect();
+                }
+            }
+            Ok(Ok(Event::Outgoing(Outgoing::Disconnect))) => break Ok(()),
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => break Err(e.to_string()),
+            Err(_) => break Err(format!("no answer from {}:{} after {} seconds", broker.host, broker.port, TIMEOUT.as_secs())),
+        }
+    };
+    queue.abort();
+    result
+}
+
+// Publish the update entity of every fetched watch and remove those of the watches that are gone
+pub async fn publish_updates(
+    broker: &Broker,
+    watched: &[(WatchKind, String)],
+    github_releases: &[GithubReleaseInfo],
+    docker_releases: &[DockerReleaseInfo],
+    conn: &Connection,
+) -> SqliteResult<()> {
+    let entities: Vec<Entity> = github_releases.iter().map(Entity::github)
+        .chain(docker_releases.iter().map(Entity::docker))
+        .collect();
+    let current: HashSet<String> = watched.iter().map(|(kind, repo)| object_id(*kind, repo)).collect();
+    let removed: Vec<String> = database::get_mqtt_entities(conn)?.into_iter()
+        .filter(|object_id| !current.contains(object_id))
+        .collect();
+    if entities.is_empty() && removed.is_empty() {
+        return Ok(());
+    }
+
+    let mut messages = Vec::new();
+    for entity in &entities {
+        messages.push((config_topic(broker, &entity.object_id), entity.discovery(broker).to_string().into_bytes()));
+        messages.push((state_topic(broker, &entity.object_id), entity.state().to_string().into_bytes()));
+    }
+    // An empty retained message deletes the entity in Home Assistant and clears the topic
+    for object_id in &removed {
+        messages.push((config_topic(broker, object_id), Vec::new()));
+        messages.push((state_topic(broker, object_id), Vec::new()));
+    }
+
+    match send(broker, messages).await {
+        Ok(()) => {
+            info!("Published {} update entities to MQTT, removed {}", entities.len(), removed.len());
+            for entity in &entities {
+                database::save_mqtt_entity(conn, &entity.object_id)?;
+            }
+            for object_id in &removed {
+                database::delete_mqtt_entity(conn, object_id)?;
+            }
+        }
+        Err(e) => error!("Failed to publish update entities to MQTT. {}", e),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{MqttBroker, Publication};
+    use serde_json::Value;
+
+    fn broker(port: u16) -> Broker {
+        Broker {
+            host: "127.0.0.1".to_string(),
+            port,
+            username: None,
+            password: None,
+            tls: false,
+            client_id: "github-ntfy".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+            topic_prefix: "github-ntfy".to_string(),
+        }
+    }
+
+    fn database() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE mqtt_entities (object_id TEXT PRIMARY KEY)", []).unwrap();
+        conn
+    }
+
+    fn github_release() -> GithubReleaseInfo {
+        GithubReleaseInfo {
+            repo: "BreizhHardware/ntfy_alerts".to_string(),
+            tag_name: "v2.1.0".to_string(),
+            html_url: "https://github.com/BreizhHardware/ntfy_alerts/releases/tag/v2.1.0".to_string(),
+            changelog: "## What's Changed\n- Add configuration file support".to_string(),
+            published_at: "2025-06-01T12:00:00Z".to_string(),
+            deployed_version: Some("v2.0.0".to_string()),
+            drift: None,
+            priority: None,
+        }
+    }
+
+    fn docker_release() -> DockerReleaseInfo {
+        DockerReleaseInfo {
+            repo: "breizhhardware/github-ntfy".to_string(),
+            digest: "sha256:4f1c3b6e9a2d7c8b5e0f1a2b3c4d5e6f".to_string(),
+            html_url: "https://hub.docker.com/r/breizhhardware/github-ntfy".to_string(),
+            published_at: "2025-06-01T12:00:00Z".to_string(),
+            deployed_version: None,
+            drift: None,
+            priority: None,
+        }
+    }
+
+    fn find<'a>(publications: &'a [Publication], topic: &str) -> &'a Publication {
+        publications.iter().find(|publication| publication.topic == topic).unwrap_or_else(|| panic!("nothing published on {}", topic))
+    }
+
+    fn json(publication: &Publication) -> Value {
+        serde_json::from_slice(&publication.payload).unwrap()
+    }
+
+    #[tokio::test]
+    async fn entities_are_published_as_retained_messages() {
+        let server = MqttBroker::start().await;
+        let conn = database();
+        let watched = [
+            (WatchKind::Github, "BreizhHardware/ntfy_alerts".to_string()),
+            (WatchKind::Docker, "breizhhardware/github-ntfy".to_string()),
+        ];
+
+        publish_updates(&broker(server.port), &watched, &[github_release()], &[docker_release()], &conn).await.unwrap();
+
+        let publications = server.publications();
+        assert_eq!(publications.len(), 4);
+        assert!(publications.iter().all(|publication| publication.retain));
+
+        let github = object_id(WatchKind::Github, "BreizhHardware/ntfy_alerts");
+        let discovery = json(find(&publications, &format!("homeassistant/update/github-ntfy/{}/config", github)));
+        assert_eq!(discovery["name"], "BreizhHardware/ntfy_alerts");
+        assert_eq!(discovery["unique_id"], format!("github-ntfy_{}", github));
+        assert_eq!(discovery["state_topic"], format!("github-ntfy/{}/state", github));
+        let state = json(find(&publications, &format!("github-ntfy/{}/state", github)));
+        assert_eq!(state["installed_version"], "v2.0.0");
+        assert_eq!(state["latest_version"], "v2.1.0");
+        assert_eq!(state["release_summary"], "What's Changed\n\n• Add configuration file support");
+
+        let docker = object_id(WatchKind::Docker, "breizhhardware/github-ntfy");
+        let state = json(find(&publications, &format!("github-ntfy/{}/state", docker)));
+        assert_eq!(state["installed_version"], "sha256:4f1c3b6e9a2d");
+        assert_eq!(state["latest_version"], "sha256:4f1c3b6e9a2d");
+
+        let mut saved = database::get_mqtt_entities(&conn).unwrap();
+        saved.sort();
+        assert_eq!(saved, [docker, github]);
+    }
+
+    #[tokio::test]
+    async fn removed_watches_clear_their_topics() {
+        let server = MqttBroker::start().await;
+        let conn = database();
+        let removed = object_id(WatchKind::Github, "BreizhHardware/old");
+        database::save_mqtt_entity(&conn, &removed).unwrap();
+        let watched = [(WatchKind::Github, "BreizhHardware/ntfy_alerts".to_string())];
+
+        publish_updates(&broker(server.port), &watched, &[github_release()], &[], &conn).await.unwrap();
+
+        let publications = server.publications();
+        assert_eq!(publications.len(), 4);
+        for topic in [format!("homeassistant/update/github-ntfy/{}/config", removed), format!("github-ntfy/{}/state", removed)] {
+            let publication = find(&publications, &topic);
+            assert!(publication.retain);
+            assert!(publication.payload.is_empty());
+        }
+        assert_eq!(database::get_mqtt_entities(&conn).unwrap(), [object_id(WatchKind::Github, "BreizhHardware/ntfy_alerts")]);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_recorded_when_the_broker_is_unreachable() {
+        let conn = database();
+        // Nothing listens on the port once the listener is dropped
+        let unused = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let watched = [(WatchKind::Github, "BreizhHardware/ntfy_alerts".to_string())];
+
+        publish_updates(&broker(unused), &watched, &[github_release()], &[], &conn).await.unwrap();
+
+        assert!(database::get_mqtt_entities(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn object_ids_are_topic_safe() {
+        assert_eq!(object_id(WatchKind::Github, "BreizhHardware/ntfy_alerts"), "github_breizhhardware_ntfy_alerts_58f944ac");
+        assert!(object_id(WatchKind::Docker, "library/nginx").starts_with("docker_library_nginx_"));
+    }
+
+    #[test]
+    fn object_ids_keep_similar_names_apart() {
+        assert_ne!(object_id(WatchKind::Github, "a/b-c"), object_id(WatchKind::Github, "a/b_c"));
+        assert_ne!(object_id(WatchKind::Github, "a/b.c"), object_id(WatchKind::Github, "a/b-c"));
+        assert_ne!(object_id(WatchKind::Github, "a/b"), object_id(WatchKind::Docker, "a/b"));
+    }
+}
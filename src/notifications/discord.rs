@@ -1,69 +1,120 @@
 use log::{error, info};
-use serde_json::json;
-use reqwest::header::HeaderMap;
-use crate::models::{GithubReleaseInfo, DockerReleaseInfo};
+use serde_json::{json, Value};
+use crate::drift::Bump;
+use crate::models::WatchKind;
+use crate::notifications::markdown::truncate_chars;
+use crate::notifications::template::{Message, MessageContext};
 
-pub async fn send_github_notification(release: &GithubReleaseInfo, webhook_url: &str) {
-    let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
-
-    let mut message = format!(
-        "📌 *New version*: {}\n\n📦*For*: {}\n\n📅 *Published on*: {}\n\n📝 *Changelog*:\n\n```{}```",
-        release.tag_name,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", ""),
-        release.changelog
-    );
-
-    if message.len() > 2000 {
-        message = format!(
-            "📌 *New version*: {}\n\n📦*For*: {}\n\n📅 *Published on*: {}\n\n🔗 *Release Link*: {}",
-            release.tag_name,
-            app_name,
-            release.published_at.replace("T", " ").replace("Z", ""),
-            release.html_url
-        );
+// Limits of an embed, in characters
+pub const MAX_LENGTH: usize = 4096;
+const MAX_TITLE: usize = 256;
+const MAX_FIELD_NAME: usize = 256;
+const MAX_FIELD_VALUE: usize = 1024;
+const MAX_FOOTER: usize = 2048;
+const MAX_EMBED: usize = 6000;
+
+fn color(context: &MessageContext) -> u32 {
+    match (context.event, context.bump) {
+        (WatchKind::Docker, _) => 0x1d63ed,
+        (_, Some(Bump::Major)) => 0xe74c3c,
+        (_, Some(Bump::Minor)) => 0xf39c12,
+        (_, Some(Bump::Patch)) => 0x2ecc71,
+        (_, None) => 0x95a5a6,
     }
+}
 
-    let data = json!({
-        "content": message,
-        "username": "GitHub Ntfy"
+fn field(name: &str, value: &str, inline: bool) -> Value {
+    json!({
+        "name": truncate_chars(name, MAX_FIELD_NAME),
+        "value": truncate_chars(value, MAX_FIELD_VALUE),
+        "inline": inline
+    })
+}
+
+fn embed(message: &Message, context: &MessageContext) -> Value {
+    let mut fields = Vec::new();
+    match context.event {
+        WatchKind::Github => {
+            if let Some(tag) = &context.tag {
+                fields.push(field("Version", tag, true));
+            }
+            if let Some(previous) = &context.previous_tag {
+                fields.push(field("Previous", previous, true));
+            }
+        }
+        WatchKind::Docker => {
+            if let Some(digest) = &context.digest {
+                fields.push(field("Digest", &format!("`{}`", digest), false));
+            }
+        }
+    }
+    fields.push(field("Published", &context.published_at, true));
+    if let Some(deployed) = &context.deployed_version {
+        let value = match &context.drift {
+            Some(drift) => format!("{} ({})", deployed, drift),
+            None => deployed.clone(),
+        };
+        fields.push(field("Deployed", &value, true));
+    }
+
+    let title = truncate_chars(&message.title, MAX_TITLE);
+    let footer = truncate_chars(&format!("github-ntfy • {}", context.repo), MAX_FOOTER);
+    // The whole embed is limited too, the description gives way first
+    let used = title.chars().count() + footer.chars().count() + fields.iter()
+        .map(|f| f["name"].as_str().unwrap_or_default().chars().count() + f["value"].as_str().unwrap_or_default().chars().count())
+        .sum::<usize>();
+    let description = truncate_chars(&message.body, MAX_LENGTH.min(MAX_EMBED.saturating_sub(used)));
+
+    let mut embed = json!({
+        "title": title,
+        "url": context.url,
+        "description": description,
+        "color": color(context),
+        "fields": fields,
+        "footer": { "text": footer }
     });
+    if let Some(avatar) = &context.avatar_url {
+        embed["author"] = json!({ "name": truncate_chars(&context.repo, MAX_TITLE), "url": format!("https://github.com/{}", context.repo), "icon_url": avatar });
+    }
+    if let Ok(published) = chrono::NaiveDateTime::parse_from_str(&context.published_at, "%Y-%m-%d %H:%M:%S%.f") {
+        embed["timestamp"] = json!(published.and_utc().to_rfc3339());
+    }
+    embed
+}
 
-    let headers = HeaderMap::new();
+pub async fn send_notification(message: &Message, context: &MessageContext, webhook_url: &str) -> bool {
+    let client = reqwest::Client::new();
+
+    let data = json!({
+        "username": "GitHub Ntfy",
+        "embeds": [embed(message, context)]
+    });
 
     match client.post(webhook_url)
-        .headers(headers)
         .json(&data)
         .send()
         .await
     {
         Ok(response) if response.status().is_success() => {
-            info!("Message sent to Discord for {}", app_name);
+            info!("Message sent to Discord for {}", context.app_name);
+            true
         },
         Ok(response) => {
             error!("Failed to send message to Discord. Status code: {}", response.status());
+            false
         },
         Err(e) => {
             error!("Error sending to Discord: {}", e);
+            false
         }
     }
 }
 
-pub async fn send_docker_notification(release: &DockerReleaseInfo, webhook_url: &str) {
+pub async fn send_alert(title: &str, message: &str, webhook_url: &str) -> bool {
     let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
-
-    let message = format!(
-        "🐳 *Docker Image Updated!*\n\n🔐 *New Digest*: `{}`\n\n📦 *App*: {}\n\n📢 *Published*: {}\n\n🔗 *Link*: {}",
-        release.digest,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", ""),
-        release.html_url
-    );
 
     let data = json!({
-        "content": message,
+        "content": format!("**{}**\n\n{}", title, message),
         "username": "GitHub Ntfy"
     });
 
@@ -73,13 +124,105 @@ pub async fn send_docker_notification(release: &DockerReleaseInfo, webhook_url:
         .await
     {
         Ok(response) if response.status().is_success() => {
-            info!("Message sent to Discord for {}", app_name);
+            info!("Alert sent to Discord: {}", title);
+            true
         },
         Ok(response) => {
-            error!("Failed to send message to Discord. Status code: {}", response.status());
+            error!("Failed to send alert to Discord. Status code: {}", response.status());
+            false
         },
         Err(e) => {
             error!("Error sending to Discord: {}", e);
+            false
         }
     }
-}
\ No newline at end of file
+}
+
+// Longest message content, in characters
+pub const MAX_CONTENT: usize = 2000;
+const MAX_THREAD_NAME: usize = 100;
+
+async fn post(client: &reqwest::Client, url: &str, data: &Value) -> Result<Value, String> {
+    match client.post(url).json(data).send().await {
+        Ok(response) if response.status().is_success() => {
+            Ok(response.json::<Value>().await.unwrap_or(Value::Null))
+        },
+        Ok(response) => Err(format!("status code {}", response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn with_query(webhook_url: &str, query: &str) -> String {
+    let separator = if webhook_url.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", webhook_url, separator, query)
+}
+
+// Post the summary embed in a new thread named after the release, then the changelog chunks in it.
+// Only forum channels accept thread_name, elsewhere the chunks follow the summary in the channel.
+// Once the summary is posted the release counts as sent, even when some chunks are not.
+pub async fn send_threaded(message: &Message, context: &MessageContext, chunks: &[String], webhook_url: &str) -> bool {
+    let client = reqwest::Client::new();
+
+    let mut data = json!({
+        "username": "GitHub Ntfy",
+        "embeds": [embed(message, context)],
+        "thread_name": truncate_chars(&message.title, MAX_THREAD_NAME)
+    });
+
+    let thread_id = match post(&client, &with_query(webhook_url, "wait=true"), &data).await {
+        Ok(response) => response["channel_id"].as_str().map(str::to_string),
+        Err(e) => {
+            info!("Unable to open a Discord thread for {} ({}), posting in the channel", context.app_name, e);
+            data.as_object_mut().map(|data| data.remove("thread_name"));
+            if let Err(e) = post(&client, webhook_url, &data).await {
+                error!("Failed to send message to Discord: {}", e);
+                return false;
+            }
+            None
+        }
+    };
+
+    let chunk_url = match &thread_id {
+        Some(id) => with_query(webhook_url, &format!("thread_id={}", id)),
+        None => webhook_url.to_string(),
+    };
+    // The summary is posted: a failed chunk leaves the changelog incomplete, retrying would open a second thread
+    for (index, chunk) in chunks.iter().enumerate() {
+        let data = json!({
+            "username": "GitHub Ntfy",
+            "content": truncate_chars(chunk, MAX_CONTENT)
+        });
+        if let Err(e) = post(&client, &chunk_url, &data).await {
+            error!("Failed to send changelog to Discord for {}, parts {} to {} are missing: {}", context.app_name, index + 1, chunks.len(), e);
+            return true;
+        }
+    }
+
+    info!("Message sent to Discord for {} with the changelog in {} parts", context.app_name, chunks.len());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::template::TemplateEvent;
+    use crate::test_support::MockServer;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn failed_changelog_chunks_keep_the_thread_delivered() {
+        let server = MockServer::start(|request| match request.json()["content"].as_str() {
+            Some("part 2") => (StatusCode::INTERNAL_SERVER_ERROR, json!({})),
+            _ => (StatusCode::OK, json!({ "id": "1", "channel_id": "999" })),
+        }).await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+        let message = Message { title: "New version for ntfy_alerts".to_string(), body: "v2.1.0".to_string() };
+        let chunks = ["part 1".to_string(), "part 2".to_string(), "part 3".to_string()];
+
+        assert!(send_threaded(&message, &context, &chunks, &format!("{}/api/webhooks/1/token", server.url)).await);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].json()["thread_name"].is_string());
+    }
+}
@@ -0,0 +1,225 @@
+use lettre::message::{header::ContentType, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use log::{error, info};
+use serde::Deserialize;
+use std::str::FromStr;
+use crate::config::Patterns;
+use crate::notifications::markdown::{self, Dialect};
+use crate::notifications::template::{Message, MessageContext};
+
+// How the connection to the SMTP server is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    // Plain connection upgraded with STARTTLS, port 587
+    #[default]
+    Starttls,
+    // TLS from the start, port 465
+    Tls,
+    // No encryption, for a relay on the local network
+    None,
+}
+
+impl FromStr for Security {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "starttls" => Ok(Security::Starttls),
+            "tls" => Ok(Security::Tls),
+            "none" => Ok(Security::None),
+            _ => Err(()),
+        }
+    }
+}
+
+// Additional recipients of the repositories matching the patterns
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecipientRoute {
+    pub repos: Patterns,
+    pub to: Vec<String>,
+}
+
+// SMTP settings of a target, with the recipients of the repository being notified
+pub struct Smtp<'a> {
+    pub host: &'a str,
+    pub port: Option<u16>,
+    pub security: Security,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub from: &'a str,
+    pub to: Vec<&'a str>,
+    pub cc: &'a [String],
+    pub bcc: &'a [String],
+}
+
+fn transport(smtp: &Smtp) -> Result<AsyncSmtpTransport<Tokio1Executor>, String> {
+    let mut builder = match smtp.security {
+        Security::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(smtp.host).map_err(|e| e.to_string())?,
+        Security::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(smtp.host).map_err(|e| e.to_string())?,
+        Security::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(smtp.host),
+    };
+    if let Some(port) = smtp.port {
+        builder = builder.port(port);
+    }
+    if let (Some(username), Some(password)) = (smtp.username, smtp.password) {
+        builder = builder.credentials(Credentials::new(username.to_string(), password.to_string()));
+    }
+    Ok(builder.build())
+}
+
+fn mailbox(address: &str) -> Result<Mailbox, String> {
+    address.parse().map_err(|e| format!("invalid address '{}': {}", address, e))
+}
+
+// Check an address when the configuration is loaded
+pub fn check_address(address: &str) -> Result<(), String> {
+    mailbox(address).map(|_| ())
+}
+
+fn html_document(title: &str, content: &str, url: &str, button: &str) -> String {
+    let escape = |text: &str| text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n\
+         <body style=\"font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; line-height: 1.5; color: #24292f; max-width: 720px;\">\n\
+         <h2>{title}</h2>\n{content}\n\
+         <p><a href=\"{url}\" style=\"display: inline-block; padding: 8px 16px; background: #1f883d; color: #ffffff; border-radius: 6px; text-decoration: none;\">{button}</a></p>\n\
+         <p style=\"color: #57606a; font-size: 12px;\">Sent by github-ntfy</p>\n</body>\n</html>\n",
+        title = escape(title),
+        content = content,
+        url = escape(url),
+        button = button,
+    )
+}
+
+// The message is rendered in markdown, sent as HTML with a plain text alternative
+async fn send(smtp: &Smtp<'_>, subject: &str, body: &str, url: &str, button: &str) -> Result<(), String> {
+    let mut email = lettre::Message::builder()
+        .from(mailbox(smtp.from)?)
+        .subject(subject);
+    for address in &smtp.to {
+        email = email.to(mailbox(address)?);
+    }
+    for address in smtp.cc {
+        email = email.cc(mailbox(address)?);
+    }
+    for address in smtp.bcc {
+        email = email.bcc(mailbox(address)?);
+    }
+
+    let text = format!("{}\n\n{}\n", markdown::convert(body, Dialect::Plain, None), url);
+    let html = html_document(subject, &markdown::convert(body, Dialect::Html, None), url, button);
+    let email = email
+        .multipart(MultiPart::alternative()
+            .singlepart(SinglePart::builder().header(ContentType::TEXT_PLAIN).body(text))
+            .singlepart(SinglePart::builder().header(ContentType::TEXT_HTML).body(html)))
+        .map_err(|e| e.to_string())?;
+
+    transport(smtp)?.send(email).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, smtp: Smtp<'_>) -> bool {
+    let button = if context.digest.is_some() { "Docker Hub" } else { "View Release" };
+    match send(&smtp, &message.title, &message.body, &context.url, button).await {
+        Ok(()) => {
+            info!("Email sent for {}", context.app_name);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send email. {}", e);
+            false
+        }
+    }
+}
+
+pub async fn send_alert(title: &str, message: &str, url: &str, smtp: Smtp<'_>) -> bool {
+    match send(&smtp, title, message, url, "Open release").await {
+        Ok(()) => {
+            info!("Alert sent by email: {}", title);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send alert by email. {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TargetKind;
+    use crate::notifications::template::TemplateEvent;
+    use crate::test_support::SmtpSink;
+
+    fn target(port: u16) -> TargetKind {
+        TargetKind::Email {
+            host: "127.0.0.1".to_string(),
+            port: Some(port),
+            security: Security::None,
+            username: None,
+            password: None,
+            from: "github-ntfy <releases@example.org>".to_string(),
+            to: vec!["ops@example.org".to_string()],
+            cc: Vec::new(),
+            bcc: vec!["audit@example.org".to_string()],
+            recipients: vec![RecipientRoute {
+                repos: Patterns::from(vec!["BreizhHardware/*".to_string()]),
+                to: vec!["team@example.org".to_string(), "ops@example.org".to_string()],
+            }],
+        }
+    }
+
+    fn smtp<'a>(kind: &'a TargetKind, repo: &str) -> Smtp<'a> {
+        let TargetKind::Email { host, port, security, from, cc, bcc, .. } = kind else { unreachable!() };
+        Smtp { host, port: *port, security: *security, username: None, password: None, from, to: kind.email_recipients(repo), cc, bcc }
+    }
+
+    fn message() -> Message {
+        Message { title: "New version for ntfy_alerts".to_string(), body: "**New version**: v2.1.0".to_string() }
+    }
+
+    #[tokio::test]
+    async fn emails_have_a_text_and_an_html_part() {
+        let sink = SmtpSink::start().await;
+        let kind = target(sink.port);
+        let context = MessageContext::sample(TemplateEvent::Github);
+
+        assert!(send_notification(&message(), &context, smtp(&kind, "BreizhHardware/ntfy_alerts")).await);
+
+        let emails = sink.emails();
+        assert_eq!(emails.len(), 1);
+        let data = &emails[0].data;
+        assert!(data.contains("Subject: New version for ntfy_alerts"), "{}", data);
+        assert!(data.contains("Content-Type: multipart/alternative"), "{}", data);
+        let text = data.find("Content-Type: text/plain").expect("no text part");
+        let html = data.find("Content-Type: text/html").expect("no html part");
+        // Clients show the last alternative they support, HTML comes after the plain text
+        assert!(text < html);
+        assert!(data[text..html].contains("New version: v2.1.0"), "{}", data);
+        assert!(data[html..].contains("<strong>New version</strong>"), "{}", data);
+        assert!(data[html..].contains("View Release"), "{}", data);
+    }
+
+    #[tokio::test]
+    async fn recipients_follow_the_repository_routes() {
+        let sink = SmtpSink::start().await;
+        let kind = target(sink.port);
+        let context = MessageContext::sample(TemplateEvent::Github);
+
+        assert!(send_notification(&message(), &context, smtp(&kind, "BreizhHardware/ntfy_alerts")).await);
+        assert!(send_notification(&message(), &context, smtp(&kind, "other/repo")).await);
+
+        let emails = sink.emails();
+        assert_eq!(emails.len(), 2);
+        assert_eq!(emails[0].from, "releases@example.org");
+        // Routed recipients are added once, bcc stays out of the headers
+        assert_eq!(emails[0].recipients, ["ops@example.org", "team@example.org", "audit@example.org"]);
+        assert!(emails[0].data.contains("To: ops@example.org, team@example.org"), "{}", emails[0].data);
+        assert!(!emails[0].data.contains("audit@example.org"));
+        assert_eq!(emails[1].recipients, ["ops@example.org", "audit@example.org"]);
+    }
+}
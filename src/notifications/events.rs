@@ -0,0 +1,147 @@
+use log::{error, info, warn};
+use crate::config::{Config, Target, TargetKind};
+use crate::database::{self, Connection, SqliteResult};
+use crate::metrics;
+use crate::models::PendingEvent;
+use crate::notifications::template::{self, MessageContext};
+use crate::notifications::{dialect, max_length, nats, redis_stream, webhook};
+
+// Days the events of a target that is no longer configured are kept, in case it comes back
+const ORPHANED_EVENTS_DAYS: i64 = 30;
+
+// Targets whose release events are queued in the database until the bus has accepted them
+pub fn is_bus(kind: &TargetKind) -> bool {
+    matches!(kind, TargetKind::Nats { .. } | TargetKind::Redis { .. })
+}
+
+// Same for every target, consumers can use it to drop the events delivered twice
+fn event_id(context: &MessageContext) -> String {
+    let version = context.tag.as_deref().or(context.digest.as_deref()).unwrap_or_default();
+    format!("{}:{}:{}", context.event.label().to_lowercase(), context.repo, version)
+}
+
+fn nats_settings(kind: &TargetKind) -> Option<nats::Nats<'_>> {
+    match kind {
+        TargetKind::Nats { url, subject, token, username, password, jetstream } => Some(nats::Nats {
+            url,
+            subject,
+            token: token.as_deref(),
+            username: username.as_deref(),
+            password: password.as_deref(),
+            jetstream: *jetstream,
+        }),
+        _ => None,
+    }
+}
+
+fn redis_settings(kind: &TargetKind) -> Option<redis_stream::RedisStream<'_>> {
+    match kind {
+        TargetKind::Redis { url, stream, max_len } => Some(redis_stream::RedisStream { url, stream, max_len: *max_len }),
+        _ => None,
+    }
+}
+
+async fn publish(kind: &TargetKind, events: &[PendingEvent], published: &mut usize) -> Result<(), String> {
+    if let Some(nats) = nats_settings(kind) {
+        nats::publish(&nats, events, published).await
+    } else if let Some(redis) = redis_settings(kind) {
+        redis_stream::publish(&redis, events, published).await
+    } else {
+        Err("not an event bus".to_string())
+    }
+}
+
+// Alerts are not queued, the next drift check raises them again
+pub async fn send_alert(title: &str, message: &str, url: &str, target: &Target) -> bool {
+    let payload = match webhook::alert_payload(title, message, url) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("Unable to build the alert for target {}: {}", target.name, e);
+            return false;
+        }
+    };
+    let event_id = format!("alert:{}:{}", url, chrono::Utc::now().timestamp());
+
+    let result = if let Some(nats) = nats_settings(&target.kind) {
+        nats::publish_alert(&nats, &event_id, &payload).await
+    } else if let Some(redis) = redis_settings(&target.kind) {
+        redis_stream::publish_alert(&redis, &event_id, &payload).await
+    } else {
+        Err("not an event bus".to_string())
+    };
+
+    match result {
+        Ok(()) => {
+            info!("Alert published to {}: {}", target.kind.service(), title);
+            true
+        },
+        Err(e) => {
+            error!("Failed to publish alert to {}. {}", target.kind.service(), e);
+            false
+        }
+    }
+}
+
+// Store the event of a release, it is published by the next flush
+pub fn enqueue(db_conn: &Connection, target: &Target, context: &MessageContext) -> SqliteResult<()> {
+    let payload = template::render(&target.templates, context, dialect(&target.kind), max_length(&target.kind))
+        .and_then(|message| webhook::release_payload(&message, context));
+    match payload {
+        Ok(payload) => database::add_pending_event(db_conn, &target.name, &event_id(context), &payload),
+        Err(e) => {
+            error!("Unable to build the event of {} for target {}: {}", context.repo, target.name, e);
+            metrics::record_notification(&target.name, false);
+            Ok(())
+        }
+    }
+}
+
+// Publish the queued events in order, an event only leaves the queue once the bus has accepted it.
+// A target stops at its first failure so that its events are never published out of order.
+pub async fn flush(config: &Config, db_conn: &Connection) -> SqliteResult<()> {
+    let mut pending = database::get_pending_events(db_conn)?;
+
+    while !pending.is_empty() {
+        let name = pending[0].target.clone();
+        let (events, rest): (Vec<PendingEvent>, Vec<PendingEvent>) = pending.into_iter().partition(|event| event.target == name);
+        pending = rest;
+
+        // A target missing after a reload may be a mistake: its events wait for it, up to the retention period
+        let Some(target) = config.targets.iter().find(|target| target.name == name && is_bus(&target.kind)) else {
+            let cutoff = (chrono::Utc::now() - chrono::Duration::days(ORPHANED_EVENTS_DAYS)).to_rfc3339();
+            let dropped = database::delete_pending_events_before(db_conn, &name, &cutoff)?;
+            if dropped > 0 {
+                warn!("Dropped {} events queued for {} more than {} days ago, it is no longer an event bus target", dropped, name, ORPHANED_EVENTS_DAYS);
+            }
+            if events.len() > dropped {
+                warn!(
+                    "{} events queued for {} are kept, it is no longer an event bus target. They are published if it comes back, \
+                     and dropped {} days after they were queued", events.len() - dropped, name, ORPHANED_EVENTS_DAYS
+                );
+            }
+            continue;
+        };
+
+        let mut published = 0;
+        let result = publish(&target.kind, &events, &mut published).await;
+        for event in &events[..published] {
+            database::delete_pending_event(db_conn, event.id)?;
+            metrics::record_notification(&target.name, true);
+        }
+        match result {
+            Ok(()) => info!("Published {} events to {}", published, target.name),
+            Err(e) => {
+                let event = &events[published];
+                error!(
+                    "Failed to publish event {} to {} (attempt {}), {} events kept for the next cycle. {}",
+                    event.event_id, target.name, event.attempts + 1, events.len() - published, e
+                );
+                database::record_event_failure(db_conn, event.id, &e)?;
+                metrics::record_notification(&target.name, false);
+            }
+        }
+    }
+
+    metrics::record_pending_events(database::count_pending_events(db_conn)?);
+    Ok(())
+}
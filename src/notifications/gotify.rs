@@ -1,78 +1,178 @@
 use log::{error, info};
-use serde_json::json;
-use crate::models::{GithubReleaseInfo, DockerReleaseInfo};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use crate::drift::Bump;
+use crate::models::Priority;
+use crate::notifications::template::{Message, MessageContext};
 
-pub async fn send_github_notification(release: &GithubReleaseInfo, token: &str, gotify_url: &str) {
-    let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
+const MAX_PRIORITY: u8 = 10;
+const ALERT_PRIORITY: u8 = 8;
 
-    let url = format!("{}/message?token={}", gotify_url, token);
+// Priority of a release from the size of its version bump, from 0 (silent) to 10
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Priorities {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    // Docker updates and tags that are not versions
+    pub other: u8,
+    // Priority of every release of a repository or image, whatever the bump and the priority of the watch
+    pub repos: BTreeMap<String, u8>,
+}
 
-    let message = format!(
-        "📌 *New version*: {}\n\n📦*For*: {}\n\n📅 *Published on*: {}\n\n📝 *Changelog*:\n\n```{}```\n\n🔗 *Release Url*:{}",
-        release.tag_name,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", ""),
-        release.changelog,
-        release.html_url
-    );
+impl Default for Priorities {
+    fn default() -> Self {
+        Priorities { major: 8, minor: 5, patch: 2, other: 5, repos: BTreeMap::new() }
+    }
+}
 
-    let content = json!({
-        "title": format!("New version for {}", app_name),
-        "message": message,
-        "priority": "2"
-    });
+impl Priorities {
+    pub fn check(&self) -> Result<(), String> {
+        for (name, priority) in [("major", self.major), ("minor", self.minor), ("patch", self.patch), ("other", self.other)] {
+            if priority > MAX_PRIORITY {
+                return Err(format!("priority '{}' is {}, expected 0 to {}", name, priority, MAX_PRIORITY));
+            }
+        }
+        for (repo, priority) in &self.repos {
+            if *priority > MAX_PRIORITY {
+                return Err(format!("priority of {} is {}, expected 0 to {}", repo, priority, MAX_PRIORITY));
+            }
+        }
+        Ok(())
+    }
+
+    // The priority set for the repository wins, then the one of the watch, then the one of the bump
+    fn for_release(&self, context: &MessageContext) -> u8 {
+        if let Some(priority) = self.repos.get(&context.repo) {
+            return *priority;
+        }
+        match (context.priority, context.bump) {
+            (Some(Priority::Min), _) => 0,
+            (Some(Priority::Low), _) => 2,
+            (Some(Priority::Default), _) => 5,
+            (Some(Priority::High), _) => 8,
+            (Some(Priority::Urgent), _) => 10,
+            (None, Some(Bump::Major)) => self.major,
+            (None, Some(Bump::Minor)) => self.minor,
+            (None, Some(Bump::Patch)) => self.patch,
+            (None, None) => self.other,
+        }
+    }
+}
+
+// Server and application token of a target
+pub struct Gotify<'a> {
+    pub url: &'a str,
+    pub token: &'a str,
+    pub priorities: &'a Priorities,
+}
+
+// Clients render the message as markdown and open the release when it is clicked
+fn extras(url: &str) -> Value {
+    json!({
+        "client::display": { "contentType": "text/markdown" },
+        "client::notification": { "click": { "url": url } }
+    })
+}
+
+// The token goes in a header, the query string would end up in proxy logs
+async fn post(gotify: &Gotify<'_>, content: &Value) -> Result<(), String> {
+    let client = reqwest::Client::new();
+    let url = format!("{}/message", gotify.url.trim_end_matches('/'));
 
     match client.post(&url)
-        .json(&content)
+        .header("X-Gotify-Key", gotify.token)
+        .json(content)
         .send()
         .await
     {
-        Ok(response) if response.status().is_success() => {
-            info!("Message sent to Gotify for {}", app_name);
-        },
-        Ok(response) => {
-            error!("Failed to send message to Gotify. Status code: {}", response.status());
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("Status code: {}", response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, gotify: Gotify<'_>) -> bool {
+    let content = json!({
+        "title": message.title,
+        "message": message.body,
+        "priority": gotify.priorities.for_release(context),
+        "extras": extras(&context.url),
+    });
+
+    match post(&gotify, &content).await {
+        Ok(()) => {
+            info!("Message sent to Gotify for {}", context.app_name);
+            true
         },
         Err(e) => {
-            error!("Error sending to Gotify: {}", e);
+            error!("Failed to send message to Gotify. {}", e);
+            false
         }
     }
 }
 
-pub async fn send_docker_notification(release: &DockerReleaseInfo, token: &str, gotify_url: &str) {
-    let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
-
-    let url = format!("{}/message?token={}", gotify_url, token);
-
-    let message = format!(
-        "🐳 *Docker Image Updated!*\n\n🔐 *New Digest*: `{}`\n\n📦 *App*: {}\n\n📢 *Published*: {}\n\n🔗 *Release Url*:{}",
-        release.digest,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", ""),
-        release.html_url
-    );
-
+pub async fn send_alert(title: &str, message: &str, url: &str, gotify: Gotify<'_>) -> bool {
     let content = json!({
-        "title": format!("New version for {}", app_name),
+        "title": title,
         "message": message,
-        "priority": "2"
+        "priority": ALERT_PRIORITY,
+        "extras": extras(url),
     });
 
-    match client.post(&url)
-        .json(&content)
-        .send()
-        .await
-    {
-        Ok(response) if response.status().is_success() => {
-            info!("Message sent to Gotify for {}", app_name);
-        },
-        Ok(response) => {
-            error!("Failed to send message to Gotify. Status code: {}", response.status());
+    match post(&gotify, &content).await {
+        Ok(()) => {
+            info!("Alert sent to Gotify: {}", title);
+            true
         },
         Err(e) => {
-            error!("Error sending to Gotify: {}", e);
+            error!("Failed to send alert to Gotify. {}", e);
+            false
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::template::TemplateEvent;
+
+    fn priorities(repos: &[(&str, u8)]) -> Priorities {
+        Priorities { repos: repos.iter().map(|(repo, priority)| (repo.to_string(), *priority)).collect(), ..Priorities::default() }
+    }
+
+    #[test]
+    fn priority_follows_the_bump() {
+        // The sample goes from v2.0.3 to v2.1.0
+        let context = MessageContext::sample(TemplateEvent::Github);
+
+        assert_eq!(priorities(&[]).for_release(&context), 5);
+        assert_eq!(Priorities { minor: 7, ..Priorities::default() }.for_release(&context), 7);
+    }
+
+    #[test]
+    fn priority_of_the_watch_wins_over_the_bump() {
+        let context = MessageContext { priority: Some(Priority::Urgent), ..MessageContext::sample(TemplateEvent::Github) };
+
+        assert_eq!(priorities(&[]).for_release(&context), 10);
+    }
+
+    #[test]
+    fn priority_of_the_repository_wins() {
+        let context = MessageContext { priority: Some(Priority::Urgent), ..MessageContext::sample(TemplateEvent::Github) };
+
+        assert_eq!(priorities(&[("BreizhHardware/ntfy_alerts", 7)]).for_release(&context), 7);
+        assert_eq!(priorities(&[("BreizhHardware/other", 3)]).for_release(&context), 10);
+    }
+
+    #[test]
+    fn priorities_above_ten_are_rejected() {
+        assert!(priorities(&[("BreizhHardware/ntfy_alerts", 10)]).check().is_ok());
+        assert_eq!(
+            priorities(&[("BreizhHardware/ntfy_alerts", 11)]).check(),
+            Err("priority of BreizhHardware/ntfy_alerts is 11, expected 0 to 10".to_string())
+        );
+    }
+}
@@ -0,0 +1,640 @@
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+
+// Markup understood by a notification service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    // CommonMark, as rendered by ntfy and Gotify
+    Markdown,
+    // Discord markdown: no images, tables or headings below ###
+    Discord,
+    // Slack mrkdwn
+    Slack,
+    // Telegram MarkdownV2, where every special character outside of an entity is escaped
+    Telegram,
+    // Telegram HTML, a handful of inline tags, quotes and code blocks
+    TelegramHtml,
+    Html,
+    Plain,
+}
+
+impl Dialect {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "markdown" => Some(Dialect::Markdown),
+            "discord" => Some(Dialect::Discord),
+            "slack" | "mrkdwn" => Some(Dialect::Slack),
+            "telegram" | "markdownv2" => Some(Dialect::Telegram),
+            "telegram-html" => Some(Dialect::TelegramHtml),
+            "html" => Some(Dialect::Html),
+            "plain" | "text" => Some(Dialect::Plain),
+            _ => None,
+        }
+    }
+
+    fn truncation_marker(&self) -> &'static str {
+        match self {
+            Dialect::Html => "<p>…</p>",
+            _ => "\n\n…",
+        }
+    }
+}
+
+// Characters escaped in Telegram MarkdownV2 text
+const TELEGRAM_SPECIAL: &[char] = &['\\', '_', '*', '[', ']', '(', ')', '~', '`', '>', '#', '+', '-', '=', '|', '{', '}', '.', '!'];
+
+fn escape_chars(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS
+}
+
+// Convert a GitHub-flavored markdown changelog, keeping whole sections when it has to be shortened to max_chars
+pub fn convert(markdown: &str, dialect: Dialect, max_chars: Option<usize>) -> String {
+    let full = render(markdown, dialect);
+    let Some(max) = max_chars else { return full };
+    if full.chars().count() <= max {
+        return full;
+    }
+
+    let marker = dialect.truncation_marker();
+    let budget = max.saturating_sub(marker.chars().count());
+    let blocks = top_level_blocks(markdown);
+
+    // Longest prefix of whole blocks that fits, preferring to stop right before a heading
+    let mut fitting = None;
+    let mut before_heading = None;
+    for (index, block) in blocks.iter().enumerate() {
+        if render(&markdown[..block.end], dialect).chars().count() > budget {
+            break;
+        }
+        fitting = Some(index);
+        if blocks.get(index + 1).is_some_and(|next| next.heading) {
+            before_heading = Some(index);
+        }
+    }
+
+    let cut = match (fitting, before_heading) {
+        // A section boundary is only worth it when it keeps most of the allowed length
+        (Some(_), Some(section)) if render(&markdown[..blocks[section].end], dialect).chars().count() * 2 >= budget => Some(section),
+        (fitting, _) => fitting,
+    };
+
+    match cut {
+        Some(mut last) => {
+            // Never end on a heading without its content
+            while last > 0 && blocks[last].heading {
+                last -= 1;
+            }
+            format!("{}{}", render(&markdown[..blocks[last].end], dialect), marker)
+        }
+        None => format!("{}{}", truncate_chars(&full, budget), marker),
+    }
+}
+
+// Cut a text to at most max characters, on a character boundary and preferably on a whitespace
+pub fn truncate_chars(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        None => text.to_string(),
+        Some((end, _)) => {
+            let cut = &text[..end];
+            match cut.rfind(char::is_whitespace) {
+                Some(space) if space > end / 2 => cut[..space].trim_end().to_string(),
+                _ => cut.to_string(),
+            }
+        }
+    }
+}
+
+// Convert a changelog and split it in chunks of at most size characters, between blocks whenever possible
+pub fn chunks(markdown: &str, dialect: Dialect, size: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+
+    for block in top_level_blocks(markdown) {
+        let source = &markdown[start..block.end];
+        let part = render(source, dialect);
+        start = block.end;
+
+        let pieces = if block.code && part.chars().count() > size {
+            split_code_block(source, dialect, size).iter().flat_map(|piece| split_text(piece, size)).collect()
+        } else {
+            split_text(&part, size)
+        };
+        for piece in pieces {
+            if current.is_empty() {
+                current = piece;
+            } else if current.chars().count() + 2 + piece.chars().count() <= size {
+                current.push_str("\n\n");
+                current.push_str(&piece);
+            } else {
+                chunks.push(std::mem::replace(&mut current, piece));
+            }
+        }
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+// Split a code block larger than size in several code blocks, each opened and closed by its own fences
+fn split_code_block(source: &str, dialect: Dialect, size: usize) -> Vec<String> {
+    let source = source.trim_matches('\n');
+    let first = source.lines().next().unwrap_or_default();
+    let fence: String = first.trim_start().chars().take_while(|c| *c == '`' || *c == '~').collect();
+    let mut lines: Vec<&str> = source.lines().collect();
+    if !fence.is_empty() {
+        lines.remove(0);
+        if lines.last().is_some_and(|line| line.trim_start().starts_with(&fence)) {
+            lines.pop();
+        }
+    }
+    // Indented code blocks keep their indentation, fenced ones get the opening fence (and its language) again
+    let block = |lines: &[&str]| {
+        let code = lines.join("\n");
+        render(&if fence.is_empty() { code } else { format!("{}\n{}\n{}", first, code, fence) }, dialect)
+    };
+
+    let mut pieces = Vec::new();
+    let mut group = 0;
+    for end in 2..=lines.len() {
+        if block(&lines[group..end]).chars().count() > size {
+            pieces.push(block(&lines[group..end - 1]));
+            group = end - 1;
+        }
+    }
+    pieces.push(block(&lines[group..]));
+    pieces
+}
+
+// Split a block larger than size on line boundaries, and lines larger than size on characters
+fn split_text(text: &str, size: usize) -> Vec<String> {
+    if text.chars().count() <= size {
+        return if text.is_empty() { Vec::new() } else { vec![text.to_string()] };
+    }
+
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    for line in text.lines() {
+        let mut line = line.to_string();
+        while line.chars().count() > size {
+            let piece = truncate_chars(&line, size);
+            line = line[piece.len()..].trim_start().to_string();
+            if !current.is_empty() {
+                pieces.push(std::mem::take(&mut current));
+            }
+            pieces.push(piece);
+        }
+        if !current.is_empty() && current.chars().count() + 1 + line.chars().count() > size {
+            pieces.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(&line);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+
+    pieces
+}
+
+struct Block {
+    end: usize,
+    heading: bool,
+    code: bool,
+}
+
+fn top_level_blocks(markdown: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut depth = 0;
+    let mut heading = false;
+    let mut code = false;
+
+    for (event, range) in Parser::new_ext(markdown, options()).into_offset_iter() {
+        match event {
+            Event::Start(tag) => {
+                if depth == 0 {
+                    heading = matches!(tag, Tag::Heading { .. });
+                    code = matches!(tag, Tag::CodeBlock(_));
+                }
+                depth += 1;
+            }
+            Event::End(_) => {
+                depth -= 1;
+                if depth == 0 {
+                    blocks.push(Block { end: range.end, heading, code });
+                }
+            }
+            Event::Rule if depth == 0 => blocks.push(Block { end: range.end, heading: false, code: false }),
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+fn render(markdown: &str, dialect: Dialect) -> String {
+    // Raw HTML is dropped, GitHub renders line breaks inside paragraphs
+    let events = Parser::new_ext(markdown, options())
+        .filter(|event| !matches!(event, Event::Html(_) | Event::InlineHtml(_)))
+        .map(|event| match event {
+            Event::SoftBreak => Event::HardBreak,
+            event => event,
+        });
+
+    if dialect == Dialect::Html {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, events);
+        return html.trim().to_string();
+    }
+
+    let mut writer = Writer { dialect, buffers: vec![String::new()], lists: Vec::new(), links: Vec::new(), cell: 0, item_start: false, code_block: false };
+    for event in events {
+        writer.event(event);
+    }
+    writer.buffers.pop().unwrap_or_default().trim().to_string()
+}
+
+struct Writer {
+    dialect: Dialect,
+    // Links, images and quotes are rendered in a buffer of their own
+    buffers: Vec<String>,
+    // Next number of each ordered list, None for bullet lists
+    lists: Vec<Option<u64>>,
+    links: Vec<(String, bool)>,
+    cell: usize,
+    // Right after a list bullet, where the first paragraph of the item goes
+    item_start: bool,
+    code_block: bool,
+}
+
+impl Writer {
+    fn out(&mut self) -> &mut String {
+        self.buffers.last_mut().expect("writer buffer")
+    }
+
+    fn push(&mut self, text: &str) {
+        self.item_start = false;
+        self.out().push_str(text);
+    }
+
+    fn text(&mut self, text: &str) {
+        let escaped = match self.dialect {
+            Dialect::Slack | Dialect::TelegramHtml => escape_html(text),
+            Dialect::Telegram if self.code_block => escape_chars(text, &['\\', '`']),
+            _ if self.code_block => text.to_string(),
+            Dialect::Markdown | Dialect::Discord => escape_chars(text, &['\\', '*', '_', '~', '`']),
+            Dialect::Telegram => escape_chars(text, TELEGRAM_SPECIAL),
+            Dialect::Html | Dialect::Plain => text.to_string(),
+        };
+        self.push(&escaped);
+    }
+
+    fn newline(&mut self) {
+        let out = self.out();
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+
+    // Start a block, separated by a blank line except inside list items
+    fn block(&mut self) {
+        if !self.lists.is_empty() {
+            self.newline();
+            return;
+        }
+        let out = self.out();
+        if out.is_empty() || out.ends_with("\n\n") {
+            return;
+        }
+        out.push_str(if out.ends_with('\n') { "\n" } else { "\n\n" });
+    }
+
+    fn emphasis(&self, end: bool) -> &'static str {
+        match self.dialect {
+            Dialect::Slack | Dialect::Telegram => "_",
+            Dialect::TelegramHtml => if end { "</i>" } else { "<i>" },
+            Dialect::Plain => "",
+            _ => "*",
+        }
+    }
+
+    fn strong(&self, end: bool) -> &'static str {
+        match self.dialect {
+            Dialect::Slack | Dialect::Telegram => "*",
+            Dialect::TelegramHtml => if end { "</b>" } else { "<b>" },
+            Dialect::Plain => "",
+            _ => "**",
+        }
+    }
+
+    fn strikethrough(&self, end: bool) -> &'static str {
+        match self.dialect {
+            Dialect::Slack | Dialect::Telegram => "~",
+            Dialect::TelegramHtml => if end { "</s>" } else { "<s>" },
+            Dialect::Plain => "",
+            _ => "~~",
+        }
+    }
+
+    fn heading(&self, level: HeadingLevel) -> (&'static str, &'static str) {
+        match (self.dialect, level) {
+            (Dialect::Markdown, HeadingLevel::H1) | (Dialect::Discord, HeadingLevel::H1) => ("# ", ""),
+            (Dialect::Markdown, HeadingLevel::H2) | (Dialect::Discord, HeadingLevel::H2) => ("## ", ""),
+            (Dialect::Markdown, HeadingLevel::H3) | (Dialect::Discord, HeadingLevel::H3) => ("### ", ""),
+            (Dialect::Markdown, _) => ("#### ", ""),
+            (Dialect::Discord, _) => ("**", "**"),
+            (Dialect::Slack, _) | (Dialect::Telegram, _) => ("*", "*"),
+            (Dialect::TelegramHtml, _) => ("<b>", "</b>"),
+            _ => ("", ""),
+        }
+    }
+
+    fn link(&self, text: &str, url: &str, image: bool) -> String {
+        match self.dialect {
+            Dialect::Telegram => {
+                let text = if text.is_empty() { escape_chars(url, TELEGRAM_SPECIAL) } else { text.to_string() };
+                return format!("[{}]({})", text, escape_chars(url, &['\\', ')']));
+            }
+            Dialect::TelegramHtml => {
+                let text = if text.is_empty() { escape_html(url) } else { text.to_string() };
+                return format!("<a href=\"{}\">{}</a>", escape_html(url).replace('"', "&quot;"), text);
+            }
+            _ => {}
+        }
+        let text = if text.is_empty() { url } else { text };
+        match self.dialect {
+            Dialect::Slack => format!("<{}|{}>", url, text),
+            Dialect::Markdown if image => format!("![{}]({})", text, url),
+            Dialect::Markdown | Dialect::Discord => format!("[{}]({})", text, url),
+            _ if text == url => url.to_string(),
+            _ => format!("{} ({})", text, url),
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => {
+                let code = match self.dialect {
+                    Dialect::Slack => format!("`{}`", escape_html(&code)),
+                    Dialect::TelegramHtml => format!("<code>{}</code>", escape_html(&code)),
+                    Dialect::Telegram => format!("`{}`", escape_chars(&code, &['\\', '`'])),
+                    Dialect::Plain => code.to_string(),
+                    _ => format!("`{}`", code),
+                };
+                self.push(&code);
+            }
+            Event::SoftBreak | Event::HardBreak => self.push("\n"),
+            Event::Rule => {
+                self.block();
+                let rule = match self.dialect {
+                    Dialect::Plain | Dialect::Telegram | Dialect::TelegramHtml => "———",
+                    _ => "---",
+                };
+                self.push(rule);
+            }
+            Event::TaskListMarker(done) => self.push(if done { "☑ " } else { "☐ " }),
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            // The first paragraph of a list item goes right after its bullet
+            Tag::Paragraph if !self.item_start => self.block(),
+            Tag::Heading { level, .. } => {
+                self.block();
+                let (prefix, _) = self.heading(level);
+                self.push(prefix);
+            }
+            Tag::BlockQuote(_) => {
+                self.block();
+                self.buffers.push(String::new());
+            }
+            Tag::CodeBlock(kind) => {
+                self.block();
+                let lang = match kind {
+                    CodeBlockKind::Fenced(lang) if self.dialect != Dialect::Slack => lang.to_string(),
+                    _ => String::new(),
+                };
+                match self.dialect {
+                    Dialect::Plain => {}
+                    Dialect::TelegramHtml if lang.is_empty() => self.push("<pre><code>"),
+                    Dialect::TelegramHtml => self.push(&format!("<pre><code class=\"language-{}\">", escape_html(&lang))),
+                    _ => self.push(&format!("```{}\n", lang)),
+                }
+                self.code_block = true;
+            }
+            Tag::List(start) => {
+                if self.lists.is_empty() {
+                    self.block();
+                }
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.newline();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        let dot = if self.dialect == Dialect::Telegram { "\\." } else { "." };
+                        format!("{}{} ", *number - 1, dot)
+                    }
+                    _ if !matches!(self.dialect, Dialect::Markdown | Dialect::Discord) => "• ".to_string(),
+                    _ => "- ".to_string(),
+                };
+                self.push(&format!("{}{}", indent, bullet));
+                self.item_start = true;
+            }
+            Tag::Emphasis => {
+                let marker = self.emphasis(false);
+                self.push(marker);
+            }
+            Tag::Strong => {
+                let marker = self.strong(false);
+                self.push(marker);
+            }
+            Tag::Strikethrough => {
+                let marker = self.strikethrough(false);
+                self.push(marker);
+            }
+            Tag::Link { dest_url, .. } => {
+                self.links.push((dest_url.to_string(), false));
+                self.buffers.push(String::new());
+            }
+            Tag::Image { dest_url, .. } => {
+                self.links.push((dest_url.to_string(), true));
+                self.buffers.push(String::new());
+            }
+            Tag::Table(_) => self.block(),
+            Tag::TableRow | Tag::TableHead => self.cell = 0,
+            Tag::TableCell => {
+                if self.cell > 0 {
+                    self.push(if self.dialect == Dialect::Telegram { " \\| " } else { " | " });
+                }
+                self.cell += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Heading(level) => {
+                let (_, suffix) = self.heading(level);
+                self.push(suffix);
+            }
+            TagEnd::BlockQuote(_) => {
+                let quote = self.buffers.pop().unwrap_or_default();
+                if self.dialect == Dialect::TelegramHtml {
+                    self.push(&format!("<blockquote>{}</blockquote>", quote.trim()));
+                    return;
+                }
+                let prefix = match self.dialect {
+                    Dialect::Plain => "| ",
+                    Dialect::Telegram => ">",
+                    _ => "> ",
+                };
+                let quoted: Vec<String> = quote.trim().lines().map(|line| format!("{}{}", prefix, line)).collect();
+                self.push(&quoted.join("\n"));
+            }
+            TagEnd::CodeBlock => {
+                self.code_block = false;
+                match self.dialect {
+                    Dialect::Plain => {}
+                    // A newline before the closing tag would show as an empty line
+                    Dialect::TelegramHtml => {
+                        let trimmed = self.out().trim_end_matches('\n').len();
+                        self.out().truncate(trimmed);
+                        self.push("</code></pre>");
+                    }
+                    _ => {
+                        self.newline();
+                        self.push("```");
+                    }
+                }
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+            }
+            TagEnd::Emphasis => {
+                let marker = self.emphasis(true);
+                self.push(marker);
+            }
+            TagEnd::Strong => {
+                let marker = self.strong(true);
+                self.push(marker);
+            }
+            TagEnd::Strikethrough => {
+                let marker = self.strikethrough(true);
+                self.push(marker);
+            }
+            TagEnd::Link | TagEnd::Image => {
+                let text = self.buffers.pop().unwrap_or_default();
+                let (url, image) = self.links.pop().unwrap_or_default();
+                let link = self.link(&text, &url, image);
+                self.push(&link);
+            }
+            TagEnd::TableHead | TagEnd::TableRow => self.newline(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGELOG: &str = "## Fixes\n\n- **Bold** and *em* with `a_b` and [docs](https://x.y/a_(b))\n- ~~old~~ 1.2 > 1!\n\n> quoted\n\n```rust\nlet x = 1 < 2;\n```\n";
+
+    #[test]
+    fn each_dialect_converts_the_changelog() {
+        let expected = [
+            (Dialect::Markdown, "## Fixes\n\n- **Bold** and *em* with `a_b` and [docs](https://x.y/a_(b))\n- ~~old~~ 1.2 > 1!\n\n> quoted\n\n```rust\nlet x = 1 < 2;\n```"),
+            (Dialect::Discord, "## Fixes\n\n- **Bold** and *em* with `a_b` and [docs](https://x.y/a_(b))\n- ~~old~~ 1.2 > 1!\n\n> quoted\n\n```rust\nlet x = 1 < 2;\n```"),
+            (Dialect::Slack, "*Fixes*\n\n• *Bold* and _em_ with `a_b` and <https://x.y/a_(b)|docs>\n• ~old~ 1.2 &gt; 1!\n\n> quoted\n\n```\nlet x = 1 &lt; 2;\n```"),
+            (Dialect::Telegram, "*Fixes*\n\n• *Bold* and _em_ with `a_b` and [docs](https://x.y/a_(b\\))\n• ~old~ 1\\.2 \\> 1\\!\n\n>quoted\n\n```rust\nlet x = 1 < 2;\n```"),
+            (
+                Dialect::TelegramHtml,
+                "<b>Fixes</b>\n\n• <b>Bold</b> and <i>em</i> with <code>a_b</code> and <a href=\"https://x.y/a_(b)\">docs</a>\n• <s>old</s> 1.2 &gt; 1!\n\n\
+                 <blockquote>quoted</blockquote>\n\n<pre><code class=\"language-rust\">let x = 1 &lt; 2;</code></pre>",
+            ),
+            (
+                Dialect::Html,
+                "<h2>Fixes</h2>\n<ul>\n<li><strong>Bold</strong> and <em>em</em> with <code>a_b</code> and <a href=\"https://x.y/a_(b)\">docs</a></li>\n\
+                 <li><del>old</del> 1.2 &gt; 1!</li>\n</ul>\n<blockquote>\n<p>quoted</p>\n</blockquote>\n<pre><code class=\"language-rust\">let x = 1 &lt; 2;\n</code></pre>",
+            ),
+            (Dialect::Plain, "Fixes\n\n• Bold and em with a_b and docs (https://x.y/a_(b))\n• old 1.2 > 1!\n\n| quoted\n\nlet x = 1 < 2;"),
+        ];
+        for (dialect, output) in expected {
+            assert_eq!(convert(CHANGELOG, dialect, None), output, "{:?}", dialect);
+        }
+    }
+
+    #[test]
+    fn shortened_changelogs_stop_before_a_heading() {
+        let changelog = "## Features\n\nA first section that is long enough to be worth keeping.\n\n## Fixes\n\nSome fix.\n\nAnother fix that does not fit.\n";
+        let full = convert(changelog, Dialect::Markdown, None);
+
+        let shortened = convert(changelog, Dialect::Markdown, Some(full.chars().count() - 5));
+        assert_eq!(shortened, "## Features\n\nA first section that is long enough to be worth keeping.\n\n…");
+
+        // Nothing fits: the text is cut and marked
+        let cut = convert(changelog, Dialect::Markdown, Some(12));
+        assert!(cut.ends_with("\n\n…") && cut.chars().count() <= 12, "{}", cut);
+    }
+
+    #[test]
+    fn truncation_counts_characters_not_bytes() {
+        let text = "🎉🚀 ünïcödé 🎉🚀";
+        assert_eq!(truncate_chars(text, text.chars().count()), text);
+        assert_eq!(truncate_chars(text, 2), "🎉🚀");
+        // Cut on the last whitespace when it keeps more than half of the text
+        assert_eq!(truncate_chars(text, 5), "🎉🚀");
+        assert_eq!(truncate_chars(text, 12), "🎉🚀 ünïcödé");
+        assert_eq!(truncate_chars("ünïcödé🎉", 4), "ünïc");
+        assert_eq!(truncate_chars("", 3), "");
+    }
+
+    #[test]
+    fn chunks_keep_code_blocks_whole() {
+        let code: Vec<String> = (0..30).map(|n| format!("line {:02} of the code", n)).collect();
+        let changelog = format!("Intro paragraph.\n\n```sh\n{}\n```\n\nOutro paragraph.\n", code.join("\n"));
+
+        for size in [120, 300, 2000] {
+            let chunks = chunks(&changelog, Dialect::Discord, size);
+            assert!(chunks.iter().all(|chunk| chunk.chars().count() <= size), "{:?}", chunks);
+            // Every chunk opens and closes its fences, and together they hold every line once
+            for chunk in &chunks {
+                assert_eq!(chunk.matches("```").count() % 2, 0, "{}", chunk);
+            }
+            let joined = chunks.join("\n");
+            assert!(code.iter().all(|line| joined.matches(line.as_str()).count() == 1));
+            assert!(joined.starts_with("Intro paragraph.") && joined.ends_with("Outro paragraph."));
+        }
+
+        // A code block that fits stays in one chunk
+        let chunks = chunks(&changelog, Dialect::Discord, 700);
+        assert!(chunks.iter().any(|chunk| chunk.contains("```sh\nline 00") && chunk.contains("line 29 of the code\n```")), "{:?}", chunks);
+    }
+}
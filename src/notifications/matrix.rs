@@ -0,0 +1,229 @@
+use log::{error, info, warn};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::notifications::markdown::{self, Dialect};
+use crate::notifications::template::{Message, MessageContext};
+
+// Events are limited to 64 KiB and carry the message twice, as text and as HTML
+pub const MAX_LENGTH: usize = 16000;
+const MAX_RETRIES: u32 = 3;
+// Longest wait accepted from the homeserver before giving up on a rate limited message
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+static TRANSACTION: AtomicU64 = AtomicU64::new(0);
+
+// Transaction ids make retried requests idempotent, they must be unique for the access token
+fn transaction_id() -> String {
+    format!("ghntfy-{}-{}", chrono::Utc::now().timestamp_millis(), TRANSACTION.fetch_add(1, Ordering::Relaxed))
+}
+
+fn endpoint(homeserver: &str, segments: &[&str]) -> Result<reqwest::Url, String> {
+    let mut url = reqwest::Url::parse(homeserver).map_err(|e| e.to_string())?;
+    url.path_segments_mut()
+        .map_err(|_| format!("invalid homeserver URL {}", homeserver))?
+        .pop_if_empty()
+        .extend(["_matrix", "client", "v3"])
+        .extend(segments);
+    Ok(url)
+}
+
+// Room ids are used as is, aliases (#room:server) are resolved through the directory
+async fn room_id(client: &reqwest::Client, homeserver: &str, access_token: &str, room: &str) -> Result<String, String> {
+    if !room.starts_with('#') {
+        return Ok(room.to_string());
+    }
+
+    let response = client.get(endpoint(homeserver, &["directory", "room", room])?)
+        .bearer_auth(access_token)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("unable to resolve {}, status code {}", room, response.status()));
+    }
+
+    let body: Value = response.json().await.map_err(|e| e.to_string())?;
+    body["room_id"].as_str().map(str::to_string).ok_or_else(|| format!("unable to resolve {}", room))
+}
+
+// Send an event, waiting as long as the homeserver asks when it answers M_LIMIT_EXCEEDED
+async fn send_event(client: &reqwest::Client, homeserver: &str, access_token: &str, room: &str, content: &Value) -> Result<(), String> {
+    let room_id = room_id(client, homeserver, access_token, room).await?;
+    let url = endpoint(homeserver, &["rooms", &room_id, "send", "m.room.message", &transaction_id()])?;
+
+    for attempt in 0..=MAX_RETRIES {
+        let response = client.put(url.clone())
+            .bearer_auth(access_token)
+            .json(content)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        let errcode = body["errcode"].as_str().unwrap_or_default();
+        if status != StatusCode::TOO_MANY_REQUESTS && errcode != "M_LIMIT_EXCEEDED" {
+            return Err(format!("status code {} {}", status, errcode).trim_end().to_string());
+        }
+
+        let delay = Duration::from_millis(body["retry_after_ms"].as_u64().unwrap_or(1000 << attempt));
+        if attempt == MAX_RETRIES || delay > MAX_RETRY_DELAY {
+            break;
+        }
+        warn!("Rate limited by the Matrix homeserver, retrying in {:?}", delay);
+        tokio::time::sleep(delay).await;
+    }
+
+    Err("rate limited by the homeserver".to_string())
+}
+
+// The message is rendered in markdown, sent as plain text with an HTML version
+fn content(title: &str, body: &str) -> Value {
+    let markdown = format!("**{}**\n\n{}", title, body);
+    json!({
+        "msgtype": "m.notice",
+        "body": markdown::convert(&markdown, Dialect::Plain, None),
+        "format": "org.matrix.custom.html",
+        "formatted_body": markdown::convert(&markdown, Dialect::Html, None)
+    })
+}
+
+async fn send_to_rooms(content: &Value, homeserver: &str, access_token: &str, rooms: &[String]) -> Result<(), String> {
+    let client = reqwest::Client::new();
+
+    let mut errors = Vec::new();
+    for room in rooms {
+        if let Err(e) = send_event(&client, homeserver, access_token, room, content).await {
+            errors.push(format!("{}: {}", room, e));
+        }
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors.join(", ")) }
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, homeserver: &str, access_token: &str, rooms: &[String]) -> bool {
+    match send_to_rooms(&content(&message.title, &message.body), homeserver, access_token, rooms).await {
+        Ok(()) => {
+            info!("Message sent to Matrix for {}", context.app_name);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send message to Matrix. {}", e);
+            false
+        }
+    }
+}
+
+pub async fn send_alert(title: &str, message: &str, homeserver: &str, access_token: &str, rooms: &[String]) -> bool {
+    match send_to_rooms(&content(title, message), homeserver, access_token, rooms).await {
+        Ok(()) => {
+            info!("Alert sent to Matrix: {}", title);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send alert to Matrix. {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::template::TemplateEvent;
+    use crate::test_support::MockServer;
+    use std::sync::atomic::AtomicUsize;
+    use warp::http::{Method, StatusCode};
+
+    fn message() -> Message {
+        Message { title: "New version for ntfy_alerts".to_string(), body: "📌 *New version*: v2.1.0".to_string() }
+    }
+
+    // Homeserver knowing one alias, rate limiting the first `limited` events
+    async fn homeserver(limited: usize) -> MockServer {
+        let sent = AtomicUsize::new(0);
+        MockServer::start(move |request| {
+            if request.method == Method::GET {
+                return match request.path.as_str() {
+                    "/_matrix/client/v3/directory/room/%23releases:example.org" => {
+                        (StatusCode::OK, json!({ "room_id": "!resolved:example.org", "servers": ["example.org"] }))
+                    }
+                    _ => (StatusCode::NOT_FOUND, json!({ "errcode": "M_NOT_FOUND" })),
+                };
+            }
+            if sent.fetch_add(1, Ordering::Relaxed) < limited {
+                return (StatusCode::TOO_MANY_REQUESTS, json!({ "errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 10 }));
+            }
+            (StatusCode::OK, json!({ "event_id": "$event" }))
+        }).await
+    }
+
+    #[tokio::test]
+    async fn aliases_are_resolved() {
+        let server = homeserver(0).await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+        let rooms = ["#releases:example.org".to_string(), "!direct:example.org".to_string()];
+
+        assert!(send_notification(&message(), &context, &server.url, "syt_token", &rooms).await);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, Method::GET);
+        assert_eq!(requests[0].header("authorization"), Some("Bearer syt_token"));
+        assert!(requests[1].path.starts_with("/_matrix/client/v3/rooms/!resolved:example.org/send/m.room.message/ghntfy-"));
+        assert_eq!(requests[1].method, Method::PUT);
+        assert!(requests[2].path.starts_with("/_matrix/client/v3/rooms/!direct:example.org/send/m.room.message/"));
+    }
+
+    #[tokio::test]
+    async fn unknown_aliases_fail() {
+        let server = homeserver(0).await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+
+        assert!(!send_notification(&message(), &context, &server.url, "syt_token", &["#unknown:example.org".to_string()]).await);
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn messages_are_notices_with_html() {
+        let server = homeserver(0).await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+
+        assert!(send_notification(&message(), &context, &server.url, "syt_token", &["!room:example.org".to_string()]).await);
+
+        let event = server.requests()[0].json();
+        assert_eq!(event["msgtype"], "m.notice");
+        assert_eq!(event["format"], "org.matrix.custom.html");
+        let html = event["formatted_body"].as_str().unwrap();
+        assert!(html.contains("<strong>New version for ntfy_alerts</strong>"), "{}", html);
+        assert!(html.contains("<em>New version</em>"), "{}", html);
+        let text = event["body"].as_str().unwrap();
+        assert!(!text.contains('*'), "{}", text);
+        assert!(text.contains("New version for ntfy_alerts"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_events_are_retried_with_the_same_transaction() {
+        let server = homeserver(2).await;
+
+        assert!(send_alert("Drift", "ntfy_alerts is behind", &server.url, "syt_token", &["!room:example.org".to_string()]).await);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|request| request.path == requests[0].path));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_gives_up_after_the_retries() {
+        let server = homeserver(usize::MAX).await;
+
+        assert!(!send_alert("Drift", "ntfy_alerts is behind", &server.url, "syt_token", &["!room:example.org".to_string()]).await);
+        assert_eq!(server.requests().len(), MAX_RETRIES as usize + 1);
+    }
+}
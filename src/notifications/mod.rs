@@ -2,15 +2,348 @@ pub mod ntfy;
 pub mod gotify;
 pub mod discord;
 pub mod slack;
-pub mod github;
-pub mod docker;
+pub mod teams;
+pub mod matrix;
+pub mod telegram;
+pub mod email;
+pub mod webhook;
+pub mod pushover;
+pub mod nats;
+pub mod redis_stream;
+pub mod events;
+pub mod apprise;
+pub mod template;
+pub mod markdown;
 
+use std::collections::HashSet;
 use tokio::task;
-use crate::models::{GithubReleaseInfo, DockerReleaseInfo};
-use crate::config::Config;
-use crate::database::{Connection, is_new_version, update_version};
+use crate::models::{GithubReleaseInfo, DockerReleaseInfo, SlackThread, WatchKind};
+use crate::config::{Config, Target, TargetKind};
+use crate::drift::DriftAlert;
+use crate::metrics;
+use crate::database::{Connection, get_changelog, get_slack_thread, get_version, is_delivered, save_delivery, save_slack_thread, update_version};
+use crate::github::CHANGELOG_UNAVAILABLE;
+use log::{error, warn};
+use markdown::Dialect;
+use template::MessageContext;
 use rusqlite::Result as SqliteResult;
 
+// Markup used for changelogs sent to a service
+pub fn dialect(kind: &TargetKind) -> Dialect {
+    match kind {
+        TargetKind::Ntfy { .. } | TargetKind::Gotify { .. } | TargetKind::Teams { .. } | TargetKind::Matrix { .. }
+        | TargetKind::Telegram { .. } | TargetKind::Email { .. } | TargetKind::Webhook { .. } | TargetKind::Pushover { .. }
+        | TargetKind::Nats { .. } | TargetKind::Redis { .. } => Dialect::Markdown,
+        TargetKind::Discord { .. } => Dialect::Discord,
+        TargetKind::Slack { .. } => Dialect::Slack,
+    }
+}
+
+// Longest message a service accepts, in characters
+pub fn max_length(kind: &TargetKind) -> Option<usize> {
+    match kind {
+        TargetKind::Discord { .. } => Some(discord::MAX_LENGTH),
+        TargetKind::Slack { .. } => Some(slack::MAX_LENGTH),
+        TargetKind::Teams { .. } => Some(teams::MAX_LENGTH),
+        TargetKind::Matrix { .. } => Some(matrix::MAX_LENGTH),
+        TargetKind::Telegram { .. } => Some(telegram::MAX_LENGTH),
+        TargetKind::Pushover { .. } => Some(pushover::MAX_LENGTH),
+        _ => None,
+    }
+}
+
+// SMTP settings of an email target, with the recipients of a repository or only the default ones without one
+fn smtp<'a>(kind: &'a TargetKind, repo: Option<&str>) -> Option<email::Smtp<'a>> {
+    match kind {
+        TargetKind::Email { host, port, security, username, password, from, to, cc, bcc, .. } => Some(email::Smtp {
+            host,
+            port: *port,
+            security: *security,
+            username: username.as_deref(),
+            password: password.as_deref(),
+            from,
+            to: match repo {
+                Some(repo) => kind.email_recipients(repo),
+                None => to.iter().map(String::as_str).collect(),
+            },
+            cc,
+            bcc,
+        }),
+        _ => None,
+    }
+}
+
+fn ntfy_settings(kind: &TargetKind) -> Option<ntfy::Ntfy<'_>> {
+    match kind {
+        TargetKind::Ntfy { url, auth, token, email, delay, priorities } => Some(ntfy::Ntfy {
+            url,
+            auth: auth.as_deref().filter(|a| !a.is_empty()),
+            token: token.as_deref(),
+            email: email.as_deref(),
+            delay: delay.as_deref(),
+            priorities,
+        }),
+        _ => None,
+    }
+}
+
+fn pushover_settings(kind: &TargetKind) -> Option<pushover::Pushover<'_>> {
+    match kind {
+        TargetKind::Pushover { user_key, token, device, priority, api_url } => Some(pushover::Pushover {
+            user_key,
+            token,
+            device: device.as_deref(),
+            priority: *priority,
+            api_url: api_url.as_deref(),
+        }),
+        _ => None,
+    }
+}
+
+fn telegram_bot(kind: &TargetKind) -> Option<telegram::Bot<'_>> {
+    match kind {
+        TargetKind::Telegram { bot_token, chats, parse_mode, api_url } => Some(telegram::Bot {
+            token: bot_token,
+            chats,
+            parse_mode: *parse_mode,
+            api_url: api_url.as_deref(),
+        }),
+        _ => None,
+    }
+}
+
+// Message size above which the changelog is sent apart from the summary, other services have no way to do it
+fn split_threshold(kind: &TargetKind) -> Option<usize> {
+    match kind {
+        TargetKind::Ntfy { .. } => Some(ntfy::MAX_LENGTH),
+        TargetKind::Discord { .. } => Some(discord::MAX_LENGTH),
+        TargetKind::Slack { .. } => Some(slack::MAX_LENGTH),
+        _ => None,
+    }
+}
+
+// Outcome of a notification, with the Slack message posted by the bot to reply to and update later
+pub struct Delivery {
+    pub sent: bool,
+    pub slack: Option<SlackThread>,
+}
+
+impl From<bool> for Delivery {
+    fn from(sent: bool) -> Self {
+        Delivery { sent, slack: None }
+    }
+}
+
+impl From<Option<SlackThread>> for Delivery {
+    fn from(slack: Option<SlackThread>) -> Self {
+        Delivery { sent: slack.is_some(), slack }
+    }
+}
+
+// Send a summary without the changelog, followed by the full changelog in a thread or an attachment.
+// None when the message fits or the target does not split changelogs.
+async fn send_split(context: &MessageContext, target: &Target, thread: Option<&SlackThread>) -> Result<Option<Delivery>, String> {
+    let (Some(threshold), Some(changelog)) = (split_threshold(&target.kind), context.changelog.as_deref()) else {
+        return Ok(None);
+    };
+    if !target.split_changelog || changelog.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let dialect = dialect(&target.kind);
+    let full = template::render(&target.templates, context, dialect, None)?;
+    // ntfy counts bytes, the others characters
+    let length = match target.kind {
+        TargetKind::Ntfy { .. } => full.body.len(),
+        _ => full.body.chars().count(),
+    };
+    if length <= threshold {
+        return Ok(None);
+    }
+
+    let summary_context = MessageContext { changelog: None, ..context.clone() };
+    let summary = template::render(&target.templates, &summary_context, dialect, max_length(&target.kind))?;
+
+    let delivery = match &target.kind {
+        TargetKind::Ntfy { .. } => match ntfy_settings(&target.kind) {
+            Some(ntfy) => ntfy::send_with_attachment(&summary, context, changelog, ntfy).await.into(),
+            None => false.into(),
+        },
+        TargetKind::Discord { webhook_url } => {
+            let chunks = markdown::chunks(changelog, dialect, discord::MAX_CONTENT);
+            discord::send_threaded(&summary, context, &chunks, webhook_url).await.into()
+        }
+        TargetKind::Slack { .. } => match slack::destination(&target.kind, Some(&context.repo)) {
+            Some(slack::Destination::Bot(bot)) => {
+                let chunks = markdown::chunks(changelog, dialect, slack::MAX_LENGTH);
+                slack::send_bot(&summary, context, &chunks, bot, thread).await.into()
+            }
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(delivery))
+}
+
+// Render the templates of the target and deliver the message, thread is the Slack message
+// posted earlier for the repository
+pub async fn send_to_target(context: MessageContext, target: Target, thread: Option<SlackThread>) -> Delivery {
+    let rendered = match send_split(&context, &target, thread.as_ref()).await {
+        Ok(Some(delivery)) => {
+            metrics::record_notification(&target.name, delivery.sent);
+            return delivery;
+        }
+        Ok(None) => template::render(&target.templates, &context, dialect(&target.kind), max_length(&target.kind)),
+        Err(e) => Err(e),
+    };
+    let message = match rendered {
+        Ok(message) => message,
+        Err(e) => {
+            error!("Unable to render the message of {} for target {}: {}", context.repo, target.name, e);
+            metrics::record_notification(&target.name, false);
+            return false.into();
+        }
+    };
+
+    let delivery: Delivery = match &target.kind {
+        TargetKind::Ntfy { .. } => match ntfy_settings(&target.kind) {
+            Some(ntfy) => ntfy::send_notification(&message, &context, ntfy).await.into(),
+            None => false.into(),
+        },
+        TargetKind::Gotify { url, token, priorities } => {
+            gotify::send_notification(&message, &context, gotify::Gotify { url, token, priorities }).await.into()
+        }
+        TargetKind::Discord { webhook_url } => {
+            discord::send_notification(&message, &context, webhook_url).await.into()
+        }
+        TargetKind::Teams { webhook_url } => {
+            teams::send_notification(&message, &context, webhook_url).await.into()
+        }
+        TargetKind::Matrix { homeserver, access_token, rooms } => {
+            matrix::send_notification(&message, &context, homeserver, access_token, rooms).await.into()
+        }
+        TargetKind::Telegram { .. } => match telegram_bot(&target.kind) {
+            Some(bot) => telegram::send_notification(&message, &context, bot).await.into(),
+            None => false.into(),
+        },
+        TargetKind::Email { .. } => match smtp(&target.kind, Some(&context.repo)) {
+            Some(smtp) => email::send_notification(&message, &context, smtp).await.into(),
+            None => false.into(),
+        },
+        TargetKind::Webhook { url, headers, secret } => {
+            webhook::send_notification(&message, &context, url, headers, secret.as_deref()).await.into()
+        }
+        TargetKind::Pushover { .. } => match pushover_settings(&target.kind) {
+            Some(pushover) => pushover::send_notification(&message, &context, pushover).await.into(),
+            None => false.into(),
+        },
+        // Releases for an event bus are queued by send_notifications and published by events::flush
+        TargetKind::Nats { .. } | TargetKind::Redis { .. } => {
+            error!("Release of {} for event bus target {} was not queued", context.repo, target.name);
+            false.into()
+        }
+        TargetKind::Slack { .. } => match slack::destination(&target.kind, Some(&context.repo)) {
+            Some(slack::Destination::Webhook(webhook_url)) => slack::send_notification(&message, &context, webhook_url).await.into(),
+            Some(slack::Destination::Bot(bot)) => slack::send_bot(&message, &context, &[], bot, thread.as_ref()).await.into(),
+            None => false.into(),
+        },
+    };
+    metrics::record_notification(&target.name, delivery.sent);
+    delivery
+}
+
+// Bring the Slack message of a release up to date after the release has been edited
+async fn update_slack_message(context: MessageContext, target: Target, thread: SlackThread) -> bool {
+    let Some(slack::Destination::Bot(bot)) = slack::destination(&target.kind, Some(&context.repo)) else {
+        return false;
+    };
+    match template::render(&target.templates, &context, dialect(&target.kind), max_length(&target.kind)) {
+        Ok(message) => slack::update_message(&message, &context, bot, &thread).await,
+        Err(e) => {
+            error!("Unable to render the message of {} for target {}: {}", context.repo, target.name, e);
+            false
+        }
+    }
+}
+
+// Alerts about a repository follow its routes (Slack channel, email recipients), the others use the defaults
+pub async fn send_alert(target: Target, title: String, message: String, url: String, repo: Option<String>) -> bool {
+    let sent = match &target.kind {
+        TargetKind::Ntfy { .. } => match ntfy_settings(&target.kind) {
+            Some(ntfy) => ntfy::send_alert(&title, &message, &url, ntfy).await,
+            None => false,
+        },
+        TargetKind::Gotify { url: gotify_url, token, priorities } => {
+            gotify::send_alert(&title, &message, &url, gotify::Gotify { url: gotify_url, token, priorities }).await
+        }
+        TargetKind::Discord { webhook_url } => {
+            discord::send_alert(&title, &message, webhook_url).await
+        }
+        TargetKind::Slack { .. } => match slack::destination(&target.kind, repo.as_deref()) {
+            Some(destination) => slack::send_alert(&title, &message, &url, destination).await,
+            None => false,
+        },
+        TargetKind::Teams { webhook_url } => {
+            teams::send_alert(&title, &message, &url, webhook_url).await
+        }
+        TargetKind::Matrix { homeserver, access_token, rooms } => {
+            matrix::send_alert(&title, &message, homeserver, access_token, rooms).await
+        }
+        TargetKind::Telegram { .. } => match telegram_bot(&target.kind) {
+            Some(bot) => telegram::send_alert(&title, &message, &url, bot).await,
+            None => false,
+        },
+        TargetKind::Email { .. } => match smtp(&target.kind, repo.as_deref()) {
+            Some(smtp) => email::send_alert(&title, &message, &url, smtp).await,
+            None => false,
+        },
+        TargetKind::Webhook { url: webhook_url, headers, secret } => {
+            webhook::send_alert(&title, &message, &url, webhook_url, headers, secret.as_deref()).await
+        }
+        TargetKind::Pushover { .. } => match pushover_settings(&target.kind) {
+            Some(pushover) => pushover::send_alert(&title, &message, &url, pushover).await,
+            None => false,
+        },
+        TargetKind::Nats { .. } | TargetKind::Redis { .. } => {
+            events::send_alert(&title, &message, &url, &target).await
+        }
+    };
+    metrics::record_notification(&target.name, sent);
+    sent
+}
+
+pub async fn send_drift_alerts(alerts: Vec<DriftAlert>, config: &Config) {
+    let mut tasks = Vec::new();
+
+    for alert in alerts {
+        let context = MessageContext::drift(&alert);
+
+        for target in config.targets_for(alert.kind, &alert.repo) {
+            match template::render(&target.templates, &context, dialect(&target.kind), max_length(&target.kind)) {
+                Ok(message) => {
+                    tasks.push(task::spawn(send_alert(target.clone(), message.title, message.body, alert.html_url.clone(), Some(alert.repo.clone()))));
+                }
+                Err(e) => {
+                    error!("Unable to render the drift alert of {} for target {}: {}", alert.repo, target.name, e);
+                    metrics::record_notification(&target.name, false);
+                }
+            }
+        }
+    }
+
+    for task in tasks {
+        let _ = task.await;
+    }
+}
+
+// Slack message posted by the bot for the last release of a repository, if the target uses the bot API
+fn slack_thread(db_conn: &Connection, target: &Target, repo: &str) -> SqliteResult<Option<SlackThread>> {
+    match slack::destination(&target.kind, Some(repo)) {
+        Some(slack::Destination::Bot(_)) => get_slack_thread(db_conn, &target.name, repo),
+        _ => Ok(None),
+    }
+}
+
 pub async fn send_notifications(
     github_releases: Vec<GithubReleaseInfo>,
     docker_releases: Vec<DockerReleaseInfo>,
@@ -18,92 +351,148 @@ pub async fn send_notifications(
     db_conn: &Connection,
 ) -> SqliteResult<()> {
     let mut tasks = Vec::new();
+    let mut updates = Vec::new();
+    // Versions are only recorded once every target got their notification, so a release that failed
+    // or was interrupted by a shutdown or a crash is notified again to the targets that missed it
+    let mut delivered = Vec::new();
 
     // Create tasks for GitHub notifications
     for release in &github_releases {
-        if is_new_version(db_conn, &release.repo, &release.tag_name)? {
-            if let Some(url) = &config.ntfy_url {
-                let release = release.clone();
-                let auth = config.auth.clone();
-                let url = url.clone();
-                tasks.push(task::spawn(async move {
-                    github::send_to_ntfy(release, &auth, &url).await;
-                }));
+        let previous = get_version(db_conn, &release.repo)?;
+        if previous.as_deref() != Some(release.tag_name.as_str()) {
+            metrics::record_release("github");
+            let context = MessageContext::github(release, previous);
+            for target in config.targets_for(WatchKind::Github, &release.repo) {
+                if is_delivered(db_conn, &target.name, &release.repo, &release.tag_name)? {
+                    continue;
+                }
+                // Queued events are kept until published, the queue takes over the delivery
+                if events::is_bus(&target.kind) {
+                    events::enqueue(db_conn, target, &context)?;
+                    save_delivery(db_conn, &target.name, &release.repo, &release.tag_name)?;
+                    continue;
+                }
+                let thread = slack_thread(db_conn, target, &release.repo)?;
+                let task = task::spawn(send_to_target(context.clone(), target.clone(), thread));
+                tasks.push((target.name.as_str(), release.repo.as_str(), release.tag_name.as_str(), task));
             }
 
-            if let (Some(gotify_url), Some(gotify_token)) = (&config.gotify_url, &config.gotify_token) {
-                let release = release.clone();
-                let url = gotify_url.clone();
-                let token = gotify_token.clone();
-                tasks.push(task::spawn(async move {
-                    github::send_to_gotify(release, &token, &url).await;
-                }));
+            delivered.push((release.repo.as_str(), release.tag_name.as_str(), Some(release.changelog.as_str())));
+        } else if release.changelog != CHANGELOG_UNAVAILABLE
+            && get_changelog(db_conn, &release.repo)?.is_some_and(|changelog| changelog != release.changelog)
+        {
+            // Same tag with other release notes: the release was edited, the messages that can be changed are updated
+            let context = MessageContext::github(release, None);
+            for target in config.targets_for(WatchKind::Github, &release.repo) {
+                if let Some(thread) = slack_thread(db_conn, target, &release.repo)?.filter(|t| t.version == release.tag_name) {
+                    updates.push((release.repo.as_str(), task::spawn(update_slack_message(context.clone(), target.clone(), thread))));
+                }
             }
 
-            if let Some(discord_url) = &config.discord_webhook_url {
-                let release = release.clone();
-                let url = discord_url.clone();
-                tasks.push(task::spawn(async move {
-                    github::send_to_discord(release, &url).await;
-                }));
-            }
-
-            if let Some(slack_url) = &config.slack_webhook_url {
-                let release = release.clone();
-                let url = slack_url.clone();
-                tasks.push(task::spawn(async move {
-                    github::send_to_slack(release, &url).await;
-                }));
-            }
-
-            update_version(db_conn, &release.repo, &release.tag_name, Some(release.changelog.as_str()))?;
+            delivered.push((release.repo.as_str(), release.tag_name.as_str(), Some(release.changelog.as_str())));
         }
     }
 
     for release in &docker_releases {
-        if is_new_version(db_conn, &release.repo, &release.digest)? {
-            if let Some(url) = &config.ntfy_url {
-                let release = release.clone();
-                let auth = config.auth.clone();
-                let url = url.clone();
-                tasks.push(task::spawn(async move {
-                    docker::send_to_ntfy(release, &auth, &url).await;
-                }));
+        let previous = get_version(db_conn, &release.repo)?;
+        if previous.as_deref() != Some(release.digest.as_str()) {
+            metrics::record_release("docker");
+            let context = MessageContext::docker(release, previous);
+            for target in config.targets_for(WatchKind::Docker, &release.repo) {
+                if is_delivered(db_conn, &target.name, &release.repo, &release.digest)? {
+                    continue;
+                }
+                // Queued events are kept until published, the queue takes over the delivery
+                if events::is_bus(&target.kind) {
+                    events::enqueue(db_conn, target, &context)?;
+                    save_delivery(db_conn, &target.name, &release.repo, &release.digest)?;
+                    continue;
+                }
+                let thread = slack_thread(db_conn, target, &release.repo)?;
+                let task = task::spawn(send_to_target(context.clone(), target.clone(), thread));
+                tasks.push((target.name.as_str(), release.repo.as_str(), release.digest.as_str(), task));
             }
 
-            if let (Some(gotify_url), Some(gotify_token)) = (&config.gotify_url, &config.gotify_token) {
-                let release = release.clone();
-                let url = gotify_url.clone();
-                let token = gotify_token.clone();
-                tasks.push(task::spawn(async move {
-                    docker::send_to_gotify(release, &token, &url).await;
-                }));
-            }
-
-            if let Some(discord_url) = &config.discord_webhook_url {
-                let release = release.clone();
-                let url = discord_url.clone();
-                tasks.push(task::spawn(async move {
-                    docker::send_to_discord(release, &url).await;
-                }));
-            }
+            delivered.push((release.repo.as_str(), release.digest.as_str(), None));
+        }
+    }
 
-            if let Some(slack_url) = &config.slack_webhook_url {
-                let release = release.clone();
-                let url = slack_url.clone();
-                tasks.push(task::spawn(async move {
-                    docker::send_to_slack(release, &url).await;
-                }));
+    // Wait for all tasks to complete
+    let mut failed = HashSet::new();
+    for (target, repo, version, task) in tasks {
+        let sent = match task.await {
+            Ok(delivery) => {
+                if let Some(thread) = &delivery.slack {
+                    save_slack_thread(db_conn, target, repo, thread)?;
+                }
+                delivery.sent
             }
-
-            update_version(db_conn, &release.repo, &release.digest, None)?;
+            Err(_) => false,
+        };
+        if sent {
+            save_delivery(db_conn, target, repo, version)?;
+        } else {
+            failed.insert(repo);
+        }
+    }
+    for (repo, task) in updates {
+        if !matches!(task.await, Ok(true)) {
+            failed.insert(repo);
         }
     }
 
-    // Wait for all tasks to complete
-    for task in tasks {
-        let _ = task.await;
+    for (repo, version, changelog) in delivered {
+        if failed.contains(repo) {
+            warn!("Notifications of {} failed for some targets, they are sent again on the next cycle", repo);
+            continue;
+        }
+        update_version(db_conn, repo, version, changelog)?;
     }
 
+    // Queued events are retried on every cycle, including those of the releases found earlier
+    events::flush(config, db_conn).await?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::SmtpSink;
+
+    fn email_target(port: u16) -> Target {
+        toml::from_str(&format!(r#"
+            name = "email"
+            type = "email"
+            host = "127.0.0.1"
+            port = {}
+            security = "none"
+            from = "releases@example.org"
+            to = ["ops@example.org"]
+
+            [[recipients]]
+            repos = ["BreizhHardware/*"]
+            to = ["team@example.org"]
+        "#, port)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn alerts_follow_the_routes_of_their_repository() {
+        let sink = SmtpSink::start().await;
+        let target = email_target(sink.port);
+        let alert = |repo: Option<&str>| send_alert(
+            target.clone(),
+            "ntfy_alerts is falling behind".to_string(),
+            "⚠️ *Drift alert*".to_string(),
+            "https://github.com/BreizhHardware/ntfy_alerts/releases/tag/v2.1.0".to_string(),
+            repo.map(str::to_string),
+        );
+
+        assert!(alert(Some("BreizhHardware/ntfy_alerts")).await);
+        assert!(alert(None).await);
+
+        let emails = sink.emails();
+        assert_eq!(emails[0].recipients, ["ops@example.org", "team@example.org"]);
+        assert_eq!(emails[1].recipients, ["ops@example.org"]);
+    }
+}
@@ -0,0 +1,94 @@
+use async_nats::header::HeaderMap;
+use async_nats::{Client, ConnectOptions};
+use std::time::Duration;
+use crate::models::PendingEvent;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Server, credentials and subject of a target
+pub struct Nats<'a> {
+    pub url: &'a str,
+    pub subject: &'a str,
+    pub token: Option<&'a str>,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    // Wait for the acknowledgement of the JetStream stream bound to the subject, core NATS does not store events
+    pub jetstream: bool,
+}
+
+// Check the server URLs when the configuration is loaded, several can be given separated by commas
+pub fn check_url(url: &str) -> Result<(), String> {
+    for server in url.split(',').map(str::trim) {
+        server.parse::<async_nats::ServerAddr>().map_err(|e| format!("invalid NATS URL '{}': {}", server, e))?;
+    }
+    Ok(())
+}
+
+// Check a subject when the configuration is loaded, wildcards are only allowed to subscribe
+pub fn check_subject(subject: &str) -> Result<(), String> {
+    if subject.is_empty() || subject.split('.').any(str::is_empty) {
+        return Err(format!("invalid subject '{}'", subject));
+    }
+    if subject.contains(|c: char| c.is_whitespace() || c == '*' || c == '>') {
+        return Err(format!("subject '{}' must not contain spaces or wildcards", subject));
+    }
+    Ok(())
+}
+
+async fn connect(nats: &Nats<'_>) -> Result<Client, String> {
+    let mut options = ConnectOptions::new()
+        .name("github-ntfy")
+        .connection_timeout(TIMEOUT)
+        .request_timeout(Some(TIMEOUT));
+    if let Some(token) = nats.token {
+        options = options.token(token.to_string());
+    }
+    if let (Some(username), Some(password)) = (nats.username, nats.password) {
+        options = options.user_and_password(username.to_string(), password.to_string());
+    }
+    let servers: Vec<&str> = nats.url.split(',').map(str::trim).collect();
+    let servers: Vec<async_nats::ServerAddr> = servers.iter().filter_map(|server| server.parse().ok()).collect();
+    options.connect(servers.as_slice()).await.map_err(|e| e.to_string())
+}
+
+fn headers(kind: &str, event_id: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Event", kind);
+    // JetStream drops a message whose id was already stored in its duplicate window
+    headers.insert("Nats-Msg-Id", event_id);
+    headers
+}
+
+async fn send(client: &Client, nats: &Nats<'_>, kind: &str, event_id: &str, payload: &[u8]) -> Result<(), String> {
+    let headers = headers(kind, event_id);
+    if nats.jetstream {
+        let context = async_nats::jetstream::new(client.clone());
+        let ack = context.publish_with_headers(nats.subject.to_string(), headers, payload.to_vec().into())
+            .await
+            .map_err(|e| e.to_string())?;
+        ack.await.map_err(|e| e.to_string())?;
+    } else {
+        client.publish_with_headers(nats.subject.to_string(), headers, payload.to_vec().into())
+            .await
+            .map_err(|e| e.to_string())?;
+        // Core NATS is fire-and-forget: the answer to the ping of the flush only tells that the server received
+        // the event, which is lost if no subscriber is connected. JetStream is needed for at least once delivery.
+        client.flush().await.map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Publish the events in order, `published` counts those the server accepted before an error
+pub async fn publish(nats: &Nats<'_>, events: &[PendingEvent], published: &mut usize) -> Result<(), String> {
+    let client = connect(nats).await?;
+    for event in events {
+        send(&client, nats, "release", &event.event_id, &event.payload).await?;
+        *published += 1;
+    }
+    Ok(())
+}
+
+pub async fn publish_alert(nats: &Nats<'_>, event_id: &str, payload: &[u8]) -> Result<(), String> {
+    let client = connect(nats).await?;
+    send(&client, nats, "alert", event_id, payload).await
+}
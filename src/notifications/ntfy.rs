@@ -1,84 +1,195 @@
 use log::{error, info};
-use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
-use crate::models::{GithubReleaseInfo, DockerReleaseInfo};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::Deserialize;
+use crate::drift::Bump;
+use crate::models::{Priority, WatchKind};
+use crate::notifications::template::{Message, MessageContext};
 
-pub async fn send_github_notification(release: &GithubReleaseInfo, auth: &str, ntfy_url: &str) {
-    let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
+// Priority of a release from the size of its version bump, when the watch sets none
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Priorities {
+    pub major: Priority,
+    pub minor: Priority,
+    pub patch: Priority,
+    // Docker updates and tags that are not versions
+    pub other: Priority,
+}
+
+impl Default for Priorities {
+    fn default() -> Self {
+        Priorities {
+            major: Priority::High,
+            minor: Priority::Default,
+            patch: Priority::Low,
+            other: Priority::Default,
+        }
+    }
+}
+
+impl Priorities {
+    pub fn for_bump(&self, bump: Option<Bump>) -> Priority {
+        match bump {
+            Some(Bump::Major) => self.major,
+            Some(Bump::Minor) => self.minor,
+            Some(Bump::Patch) => self.patch,
+            None => self.other,
+        }
+    }
+}
+
+// Topic and delivery settings of a target
+pub struct Ntfy<'a> {
+    pub url: &'a str,
+    // Base64 encoded username:password
+    pub auth: Option<&'a str>,
+    // Access token (tk_...), sent as a bearer token
+    pub token: Option<&'a str>,
+    pub email: Option<&'a str>,
+    pub delay: Option<&'a str>,
+    pub priorities: &'a Priorities,
+}
+
+fn header(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
 
+// Headers shared by releases and alerts, anonymous topics get no Authorization
+fn common_headers(ntfy: &Ntfy, title: &str) -> HeaderMap {
     let mut headers = HeaderMap::new();
-    headers.insert("Authorization", HeaderValue::from_str(&format!("Basic {}", auth))
-        .unwrap_or_else(|_| HeaderValue::from_static("")));
-    headers.insert("Title", HeaderValue::from_str(&format!("New version for {}", app_name))
-        .unwrap_or_else(|_| HeaderValue::from_static("")));
-    headers.insert("Priority", HeaderValue::from_static("urgent"));
+    match (ntfy.token, ntfy.auth) {
+        (Some(token), _) => { headers.insert("Authorization", header(&format!("Bearer {}", token))); }
+        (None, Some(auth)) => { headers.insert("Authorization", header(&format!("Basic {}", auth))); }
+        (None, None) => {}
+    }
+    headers.insert("Title", header(title));
     headers.insert("Markdown", HeaderValue::from_static("yes"));
-    headers.insert("Actions", HeaderValue::from_str(&format!("view, Update {}, {}, clear=true", app_name, release.html_url))
-        .unwrap_or_else(|_| HeaderValue::from_static("")));
-
-    let message = format!(
-        "📌 *New version*: {}\n\n📦*For*: {}\n\n📅 *Published on*: {}\n\n📝 *Changelog*:\n\n```{}```\n\n 🔗 *Release Url*: {}",
-        release.tag_name,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", ""),
-        release.changelog,
-        release.html_url
-    );
-
-    match client.post(ntfy_url)
-        .headers(headers)
-        .body(message)
+    if let Some(email) = ntfy.email {
+        headers.insert("Email", header(email));
+    }
+    headers
+}
+
+fn release_headers(ntfy: &Ntfy, message: &Message, context: &MessageContext) -> HeaderMap {
+    let mut headers = common_headers(ntfy, &message.title);
+    let priority = context.priority.unwrap_or_else(|| ntfy.priorities.for_bump(context.bump));
+    headers.insert("Priority", HeaderValue::from_static(priority.as_str()));
+
+    // The first tag is shown as an emoji, the others as labels
+    let mut tags = match context.event {
+        WatchKind::Github => vec!["package", "github"],
+        WatchKind::Docker => vec!["whale", "docker"],
+    };
+    match context.bump {
+        Some(Bump::Major) => tags.push("major"),
+        Some(Bump::Minor) => tags.push("minor"),
+        Some(Bump::Patch) => tags.push("patch"),
+        None => {}
+    }
+    headers.insert("Tags", header(&tags.join(",")));
+    headers.insert("Click", header(&context.url));
+    if let Some(avatar_url) = &context.avatar_url {
+        headers.insert("Icon", header(avatar_url));
+    }
+    if let Some(delay) = ntfy.delay {
+        headers.insert("Delay", header(delay));
+    }
+    headers.insert("Actions", header(&format!("view, Update {}, {}, clear=true", context.app_name, context.url)));
+    headers
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, ntfy: Ntfy<'_>) -> bool {
+    let client = reqwest::Client::new();
+
+    match client.post(ntfy.url)
+        .headers(release_headers(&ntfy, message, context))
+        .body(message.body.clone())
         .send()
         .await
     {
         Ok(response) if response.status().is_success() => {
-            info!("Message sent to Ntfy for {}", app_name);
+            info!("Message sent to Ntfy for {}", context.app_name);
+            true
         },
         Ok(response) => {
             error!("Failed to send message to Ntfy. Status code: {}", response.status());
+            false
         },
         Err(e) => {
             error!("Error sending to Ntfy: {}", e);
+            false
         }
     }
 }
 
-pub async fn send_docker_notification(release: &DockerReleaseInfo, auth: &str, ntfy_url: &str) {
+// Longest message body ntfy accepts, in bytes, longer ones are turned into attachments
+pub const MAX_LENGTH: usize = 4096;
+
+fn attachment_name(context: &MessageContext) -> String {
+    let name = format!("changelog-{}-{}.md", context.app_name, context.tag.as_deref().unwrap_or("latest"));
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-') { c } else { '_' })
+        .collect()
+}
+
+// Send the summary as the message and the full changelog as a markdown file attached to it
+pub async fn send_with_attachment(message: &Message, context: &MessageContext, changelog: &str, ntfy: Ntfy<'_>) -> bool {
     let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
 
-    let mut headers = HeaderMap::new();
-    headers.insert("Authorization", HeaderValue::from_str(&format!("Basic {}", auth))
-        .unwrap_or_else(|_| HeaderValue::from_static("")));
-    headers.insert("Title", HeaderValue::from_str(&format!("🆕 New version for {}", app_name))
-        .unwrap_or_else(|_| HeaderValue::from_static("")));
-    headers.insert("Priority", HeaderValue::from_static("urgent"));
-    headers.insert("Markdown", HeaderValue::from_static("yes"));
-    headers.insert("Actions", HeaderValue::from_str(&format!("View, Update {}, {}, clear=true", app_name, release.html_url))
-        .unwrap_or_else(|_| HeaderValue::from_static("")));
-
-    let message = format!(
-        "🐳 *Docker Image Updated!*\n\n🔐 *New Digest*: `{}`\n\n📦 *App*: {}\n\n📢 *Published*: {}\n\n 🔗 *Release Url*: {}",
-        release.digest,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", ""),
-        release.html_url
-    );
-
-    match client.post(ntfy_url)
+    let mut headers = release_headers(&ntfy, message, context);
+    headers.insert("Filename", HeaderValue::from_str(&attachment_name(context))
+        .unwrap_or_else(|_| HeaderValue::from_static("changelog.md")));
+
+    // The request body is the attachment, the message goes in the query string
+    match client.put(ntfy.url)
         .headers(headers)
-        .body(message)
+        .query(&[("message", message.body.as_str())])
+        .body(changelog.to_string())
         .send()
         .await
     {
         Ok(response) if response.status().is_success() => {
-            info!("Message sent to Ntfy for {}", app_name);
+            info!("Message sent to Ntfy for {} with the changelog attached", context.app_name);
+            true
         },
         Ok(response) => {
             error!("Failed to send message to Ntfy. Status code: {}", response.status());
+            false
         },
         Err(e) => {
             error!("Error sending to Ntfy: {}", e);
+            false
         }
     }
-}
\ No newline at end of file
+}
+
+// Alerts are never delayed, they report something to act on now
+pub async fn send_alert(title: &str, message: &str, url: &str, ntfy: Ntfy<'_>) -> bool {
+    let client = reqwest::Client::new();
+
+    let mut headers = common_headers(&ntfy, title);
+    headers.insert("Priority", HeaderValue::from_static("high"));
+    headers.insert("Tags", HeaderValue::from_static("warning"));
+    headers.insert("Click", header(url));
+    headers.insert("Actions", header(&format!("view, Open release, {}, clear=true", url)));
+
+    match client.post(ntfy.url)
+        .headers(headers)
+        .body(message.to_string())
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => {
+            info!("Alert sent to Ntfy: {}", title);
+            true
+        },
+        Ok(response) => {
+            error!("Failed to send alert to Ntfy. Status code: {}", response.status());
+            false
+        },
+        Err(e) => {
+            error!("Error sending to Ntfy: {}", e);
+            false
+        }
+    }
+}
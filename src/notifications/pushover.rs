@@ -0,0 +1,98 @@
+use log::{error, info};
+use serde_json::Value;
+use crate::models::WatchKind;
+use crate::notifications::markdown::{self, truncate_chars, Dialect};
+use crate::notifications::template::{Message, MessageContext};
+
+// Limits of the message API, in characters
+pub const MAX_LENGTH: usize = 1024;
+const MAX_TITLE: usize = 250;
+const MAX_URL: usize = 512;
+const MAX_URL_TITLE: usize = 100;
+const DEFAULT_API_URL: &str = "https://api.pushover.net/1";
+
+// Application token and recipient of a target
+pub struct Pushover<'a> {
+    pub user_key: &'a str,
+    pub token: &'a str,
+    pub device: Option<&'a str>,
+    pub priority: Option<i8>,
+    pub api_url: Option<&'a str>,
+}
+
+// Emergency priority (2) needs acknowledgement settings, the configuration only allows -2 to 1
+pub fn check_priority(priority: i8) -> Result<(), String> {
+    if (-2..=1).contains(&priority) {
+        Ok(())
+    } else {
+        Err(format!("priority {} is out of range, expected -2 to 1", priority))
+    }
+}
+
+async fn send(pushover: &Pushover<'_>, title: &str, body: &str, url: &str, url_title: &str) -> Result<(), String> {
+    let client = reqwest::Client::new();
+
+    // Pushover shows plain text, the markdown of the message is flattened
+    let message = truncate_chars(&markdown::convert(body, Dialect::Plain, None), MAX_LENGTH);
+    let mut form = vec![
+        ("token", pushover.token.to_string()),
+        ("user", pushover.user_key.to_string()),
+        ("title", truncate_chars(title, MAX_TITLE)),
+        ("message", message),
+        ("priority", pushover.priority.unwrap_or(0).to_string()),
+    ];
+    if url.chars().count() <= MAX_URL {
+        form.push(("url", url.to_string()));
+        form.push(("url_title", truncate_chars(url_title, MAX_URL_TITLE)));
+    }
+    if let Some(device) = pushover.device {
+        form.push(("device", device.to_string()));
+    }
+
+    let endpoint = format!("{}/messages.json", pushover.api_url.unwrap_or(DEFAULT_API_URL).trim_end_matches('/'));
+    let response = client.post(endpoint)
+        .form(&form)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let status = response.status();
+    let body: Value = response.json().await.unwrap_or(Value::Null);
+    if status.is_success() && body["status"].as_i64() == Some(1) {
+        return Ok(());
+    }
+
+    let errors: Vec<&str> = body["errors"].as_array().map(|errors| errors.iter().filter_map(Value::as_str).collect()).unwrap_or_default();
+    Err(format!("Status code: {} {}", status, errors.join(", ")).trim_end().to_string())
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, pushover: Pushover<'_>) -> bool {
+    let url_title = match context.event {
+        WatchKind::Github => "View Release",
+        WatchKind::Docker => "Docker Hub",
+    };
+
+    match send(&pushover, &message.title, &message.body, &context.url, url_title).await {
+        Ok(()) => {
+            info!("Message sent to Pushover for {}", context.app_name);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send message to Pushover. {}", e);
+            false
+        }
+    }
+}
+
+pub async fn send_alert(title: &str, message: &str, url: &str, pushover: Pushover<'_>) -> bool {
+    match send(&pushover, title, message, url, "Open release").await {
+        Ok(()) => {
+            info!("Alert sent to Pushover: {}", title);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send alert to Pushover. {}", e);
+            false
+        }
+    }
+}
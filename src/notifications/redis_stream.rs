@@ -0,0 +1,58 @@
+use redis::aio::MultiplexedConnection;
+use std::time::Duration;
+use tokio::time::timeout;
+use crate::models::PendingEvent;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Server and stream of a target
+pub struct RedisStream<'a> {
+    pub url: &'a str,
+    pub stream: &'a str,
+    // Approximate length the stream is trimmed to, unbounded otherwise
+    pub max_len: Option<usize>,
+}
+
+// Check the URL when the configuration is loaded, credentials and database are part of it
+pub fn check_url(url: &str) -> Result<(), String> {
+    redis::Client::open(url).map(|_| ()).map_err(|_| "invalid Redis URL, expected redis://, rediss:// or unix://".to_string())
+}
+
+async fn connect(redis: &RedisStream<'_>) -> Result<MultiplexedConnection, String> {
+    let client = redis::Client::open(redis.url).map_err(|e| e.to_string())?;
+    match timeout(TIMEOUT, client.get_multiplexed_async_connection()).await {
+        Ok(connection) => connection.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("no answer after {} seconds", TIMEOUT.as_secs())),
+    }
+}
+
+// Entries have the kind of event, its id and the JSON payload as fields
+async fn send(connection: &mut MultiplexedConnection, redis: &RedisStream<'_>, kind: &str, event_id: &str, payload: &[u8]) -> Result<(), String> {
+    let mut command = redis::cmd("XADD");
+    command.arg(redis.stream);
+    if let Some(max_len) = redis.max_len {
+        command.arg("MAXLEN").arg("~").arg(max_len);
+    }
+    command.arg("*").arg("event").arg(kind).arg("id").arg(event_id).arg("payload").arg(payload);
+
+    // The id of the new entry is the acknowledgement
+    match timeout(TIMEOUT, command.query_async::<String>(connection)).await {
+        Ok(result) => result.map(|_| ()).map_err(|e| e.to_string()),
+        Err(_) => Err(format!("no answer after {} seconds", TIMEOUT.as_secs())),
+    }
+}
+
+// Append the events in order, `published` counts those the server accepted before an error
+pub async fn publish(redis: &RedisStream<'_>, events: &[PendingEvent], published: &mut usize) -> Result<(), String> {
+    let mut connection = connect(redis).await?;
+    for event in events {
+        send(&mut connection, redis, "release", &event.event_id, &event.payload).await?;
+        *published += 1;
+    }
+    Ok(())
+}
+
+pub async fn publish_alert(redis: &RedisStream<'_>, event_id: &str, payload: &[u8]) -> Result<(), String> {
+    let mut connection = connect(redis).await?;
+    send(&mut connection, redis, "alert", event_id, payload).await
+}
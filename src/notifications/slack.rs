@@ -1,52 +1,122 @@
 use log::{error, info};
-use serde_json::json;
+use serde_json::{json, Value};
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use std::iter::FromIterator;
-use crate::models::{GithubReleaseInfo, DockerReleaseInfo};
+use crate::config::TargetKind;
+use crate::models::{SlackThread, WatchKind};
+use crate::notifications::markdown::truncate_chars;
+use crate::notifications::template::{Message, MessageContext};
 
-pub async fn send_github_notification(release: &GithubReleaseInfo, webhook_url: &str) {
-    let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
+// Limits of Block Kit, in characters
+pub const MAX_LENGTH: usize = 3000;
+const MAX_HEADER: usize = 150;
+const MAX_BUTTON_TEXT: usize = 75;
+const MAX_BUTTON_URL: usize = 3000;
+const MAX_CONTEXT_TEXT: usize = 3000;
+const MAX_FALLBACK_TEXT: usize = 4000;
+
+const DEFAULT_API_URL: &str = "https://slack.com/api";
+
+// Web API settings of a target, with the channel of the repository being notified
+#[derive(Clone, Copy)]
+pub struct Bot<'a> {
+    pub api_url: &'a str,
+    pub token: &'a str,
+    pub channel: &'a str,
+}
 
-    let mut message = format!(
-        "📌 *New version*: {}\n\n📦*For*: {}\n\n📅 *Published on*: {}\n\n📝 *Changelog*:\n\n```{}```",
-        release.tag_name,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", ""),
-        release.changelog
-    );
+// Where messages are posted, the bot API is preferred when a token is configured
+#[derive(Clone, Copy)]
+pub enum Destination<'a> {
+    Webhook(&'a str),
+    Bot(Bot<'a>),
+}
 
-    if message.len() > 2000 {
-        message = format!(
-            "📌 *New version*: {}\n\n📦*For*: {}\n\n📅 *Published on*: {}\n\n📝 *Changelog*:\n\n `truncated..` use 🔗 instead",
-            release.tag_name,
-            app_name,
-            release.published_at.replace("T", " ").replace("Z", "")
-        );
+// Destination of the messages of a repository, alerts without one go to the default channel
+pub fn destination<'a>(kind: &'a TargetKind, repo: Option<&str>) -> Option<Destination<'a>> {
+    let TargetKind::Slack { webhook_url, bot_token, channel, api_url, .. } = kind else { return None };
+    let channel = match repo {
+        Some(repo) => kind.slack_channel(repo),
+        None => channel.as_deref(),
+    };
+    match (bot_token, channel, webhook_url) {
+        (Some(token), Some(channel), _) => Some(Destination::Bot(Bot {
+            api_url: api_url.as_deref().unwrap_or(DEFAULT_API_URL).trim_end_matches('/'),
+            token,
+            channel,
+        })),
+        (_, _, Some(webhook_url)) => Some(Destination::Webhook(webhook_url)),
+        _ => None,
     }
+}
 
-    let data = json!({
-        "blocks": [
-            {
-                "type": "section",
-                "text": {
-                    "type": "mrkdwn",
-                    "text": message
-                },
-                "accessory": {
-                    "type": "button",
-                    "text": {
-                        "type": "plain_text",
-                        "text": "View Release"
-                    },
-                    "url": release.html_url,
-                    "action_id": "button-action"
-                }
-            },
-            {
-                "type": "divider"
+fn button(text: &str, url: &str, action_id: &str) -> Option<Value> {
+    // A button with a longer URL is rejected along with the whole message
+    if url.chars().count() > MAX_BUTTON_URL {
+        return None;
+    }
+    Some(json!({
+        "type": "button",
+        "text": { "type": "plain_text", "text": truncate_chars(text, MAX_BUTTON_TEXT) },
+        "url": url,
+        "action_id": action_id
+    }))
+}
+
+fn blocks(message: &Message, context: &MessageContext) -> Vec<Value> {
+    let mut details = vec![format!("*{}*", context.repo)];
+    match context.event {
+        WatchKind::Github => {
+            if let (Some(previous), Some(tag)) = (&context.previous_tag, &context.tag) {
+                details.push(format!("{} → {}", previous, tag));
             }
-        ]
+        }
+        WatchKind::Docker => {
+            if let Some(digest) = &context.digest {
+                details.push(format!("`{}`", digest));
+            }
+        }
+    }
+    details.push(format!("Published {}", context.published_at));
+
+    let mut context_elements = Vec::new();
+    if let Some(avatar) = &context.avatar_url {
+        context_elements.push(json!({ "type": "image", "image_url": avatar, "alt_text": context.repo }));
+    }
+    context_elements.push(json!({ "type": "mrkdwn", "text": truncate_chars(&details.join(" • "), MAX_CONTEXT_TEXT) }));
+
+    let buttons: Vec<Value> = match context.event {
+        WatchKind::Github => [
+            button("View Release", &context.url, "view-release"),
+            context.compare_url.as_deref().and_then(|url| button("Compare", url, "compare")),
+        ].into_iter().flatten().collect(),
+        WatchKind::Docker => [button("Docker Hub", &context.url, "docker-hub")].into_iter().flatten().collect(),
+    };
+
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": truncate_chars(&message.title, MAX_HEADER), "emoji": true }
+        }),
+        json!({ "type": "context", "elements": context_elements }),
+        json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": truncate_chars(&message.body, MAX_LENGTH) }
+        }),
+    ];
+    if !buttons.is_empty() {
+        blocks.push(json!({ "type": "actions", "elements": buttons }));
+    }
+    blocks.push(json!({ "type": "divider" }));
+    blocks
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, webhook_url: &str) -> bool {
+    let client = reqwest::Client::new();
+
+    let data = json!({
+        "text": truncate_chars(&message.title, MAX_FALLBACK_TEXT),
+        "blocks": blocks(message, context)
     });
 
     let headers = HeaderMap::from_iter([(
@@ -61,29 +131,116 @@ pub async fn send_github_notification(release: &GithubReleaseInfo, webhook_url:
         .await
     {
         Ok(response) if response.status().is_success() => {
-            info!("Message sent to Slack for {}", app_name);
+            info!("Message sent to Slack for {}", context.app_name);
+            true
         },
         Ok(response) => {
             error!("Failed to send message to Slack. Status code: {}", response.status());
+            false
         },
         Err(e) => {
             error!("Error sending to Slack: {}", e);
+            false
         }
     }
 }
 
-pub async fn send_docker_notification(release: &DockerReleaseInfo, webhook_url: &str) {
+// Call a method of the Web API, which answers 200 with ok set to false on errors
+async fn call(client: &reqwest::Client, bot: &Bot<'_>, method: &str, data: &Value) -> Result<Value, String> {
+    let response = client.post(format!("{}/{}", bot.api_url, method))
+        .bearer_auth(bot.token)
+        .json(data)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("status code {}", response.status()));
+    }
+
+    let body: Value = response.json().await.map_err(|e| e.to_string())?;
+    if body["ok"].as_bool() != Some(true) {
+        return Err(body["error"].as_str().unwrap_or("unknown error").to_string());
+    }
+    Ok(body)
+}
+
+// Post the message in the thread of the repository, or start it, then the changelog chunks as replies.
+// Returns the posted message, None when it could not be sent, even if some chunks were not.
+pub async fn send_bot(message: &Message, context: &MessageContext, chunks: &[String], bot: Bot<'_>, thread: Option<&SlackThread>) -> Option<SlackThread> {
     let client = reqwest::Client::new();
-    let app_name = release.repo.split('/').last().unwrap_or(&release.repo);
+    // A thread is left behind when the repository is routed to another channel
+    let thread_ts = thread.filter(|thread| thread.channel == bot.channel).map(|thread| thread.thread_ts.clone());
+
+    let mut data = json!({
+        "channel": bot.channel,
+        "text": truncate_chars(&message.title, MAX_FALLBACK_TEXT),
+        "blocks": blocks(message, context)
+    });
+    if let Some(thread_ts) = &thread_ts {
+        // Releases after the first one are replies, also shown in the channel
+        data["thread_ts"] = json!(thread_ts);
+        data["reply_broadcast"] = json!(true);
+    }
 
-    let message = format!(
-        "🐳 *Docker Image Updated!*\n\n🔐 *New Digest*: `{}`\n\n📦 *App*: {}\n\n📢*Published*: {}",
-        release.digest,
-        app_name,
-        release.published_at.replace("T", " ").replace("Z", "")
-    );
+    let ts = match call(&client, &bot, "chat.postMessage", &data).await {
+        Ok(response) => response["ts"].as_str().unwrap_or_default().to_string(),
+        Err(e) => {
+            error!("Failed to send message to Slack: {}", e);
+            return None;
+        }
+    };
+    let thread_ts = thread_ts.unwrap_or_else(|| ts.clone());
+
+    // The message is posted: a failed chunk leaves the changelog incomplete, retrying would post the message twice
+    for (index, chunk) in chunks.iter().enumerate() {
+        let data = json!({
+            "channel": bot.channel,
+            "thread_ts": thread_ts,
+            "text": truncate_chars(chunk, MAX_FALLBACK_TEXT)
+        });
+        if let Err(e) = call(&client, &bot, "chat.postMessage", &data).await {
+            error!("Failed to send changelog to Slack for {}, parts {} to {} are missing: {}", context.app_name, index + 1, chunks.len(), e);
+            break;
+        }
+    }
+
+    info!("Message sent to Slack for {}", context.app_name);
+    Some(SlackThread {
+        channel: bot.channel.to_string(),
+        thread_ts,
+        version: context.tag.clone().or_else(|| context.digest.clone()).unwrap_or_default(),
+        ts,
+    })
+}
+
+// Replace a message posted earlier, when its release has been edited
+pub async fn update_message(message: &Message, context: &MessageContext, bot: Bot<'_>, thread: &SlackThread) -> bool {
+    let client = reqwest::Client::new();
 
     let data = json!({
+        "channel": thread.channel,
+        "ts": thread.ts,
+        "text": truncate_chars(&message.title, MAX_FALLBACK_TEXT),
+        "blocks": blocks(message, context)
+    });
+
+    match call(&client, &bot, "chat.update", &data).await {
+        Ok(_) => {
+            info!("Slack message updated for {}", context.app_name);
+            true
+        },
+        Err(e) => {
+            error!("Failed to update Slack message for {}: {}", context.app_name, e);
+            false
+        }
+    }
+}
+
+pub async fn send_alert(title: &str, message: &str, url: &str, destination: Destination<'_>) -> bool {
+    let client = reqwest::Client::new();
+
+    let mut data = json!({
+        "text": title,
         "blocks": [
             {
                 "type": "section",
@@ -95,37 +252,177 @@ pub async fn send_docker_notification(release: &DockerReleaseInfo, webhook_url:
                     "type": "button",
                     "text": {
                         "type": "plain_text",
-                        "text": "View Image"
+                        "text": "View Release"
                     },
-                    "url": release.html_url,
+                    "url": url,
                     "action_id": "button-action"
                 }
-            },
-            {
-                "type": "divider"
             }
         ]
     });
 
-    let headers = HeaderMap::from_iter([(
-        CONTENT_TYPE,
-        HeaderValue::from_static("application/json")
-    )]);
+    let webhook_url = match destination {
+        Destination::Webhook(webhook_url) => webhook_url,
+        Destination::Bot(bot) => {
+            data["channel"] = json!(bot.channel);
+            return match call(&client, &bot, "chat.postMessage", &data).await {
+                Ok(_) => {
+                    info!("Alert sent to Slack: {}", title);
+                    true
+                },
+                Err(e) => {
+                    error!("Failed to send alert to Slack: {}", e);
+                    false
+                }
+            };
+        }
+    };
 
     match client.post(webhook_url)
-        .headers(headers)
         .json(&data)
         .send()
         .await
     {
         Ok(response) if response.status().is_success() => {
-            info!("Message sent to Slack for {}", app_name);
+            info!("Alert sent to Slack: {}", title);
+            true
         },
         Ok(response) => {
-            error!("Failed to send message to Slack. Status code: {}", response.status());
+            error!("Failed to send alert to Slack. Status code: {}", response.status());
+            false
         },
         Err(e) => {
             error!("Error sending to Slack: {}", e);
+            false
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::template::TemplateEvent;
+    use crate::test_support::MockServer;
+    use warp::http::{Method, StatusCode};
+
+    fn message() -> Message {
+        Message { title: "New version for ntfy_alerts".to_string(), body: "📌 *New version*: v2.1.0".to_string() }
+    }
+
+    fn bot(api_url: &str) -> Bot<'_> {
+        Bot { api_url, token: "xoxb-test", channel: "C123" }
+    }
+
+    async fn slack_api() -> MockServer {
+        MockServer::start(|_| (StatusCode::OK, json!({ "ok": true, "ts": "1700000002.000200" }))).await
+    }
+
+    #[tokio::test]
+    async fn first_release_starts_a_thread() {
+        let server = slack_api().await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+
+        let thread = send_bot(&message(), &context, &[], bot(&server.url), None).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/chat.postMessage");
+        assert_eq!(requests[0].header("authorization"), Some("Bearer xoxb-test"));
+        let data = requests[0].json();
+        assert_eq!(data["channel"], "C123");
+        assert!(data.get("thread_ts").is_none());
+        assert!(data.get("reply_broadcast").is_none());
+        assert_eq!(thread.thread_ts, "1700000002.000200");
+        assert_eq!(thread.ts, "1700000002.000200");
+        assert_eq!(thread.version, "v2.1.0");
+    }
+
+    #[tokio::test]
+    async fn later_releases_reply_in_the_thread() {
+        let server = slack_api().await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+        let previous = SlackThread {
+            channel: "C123".to_string(),
+            thread_ts: "1700000001.000100".to_string(),
+            version: "v2.0.3".to_string(),
+            ts: "1700000001.000100".to_string(),
+        };
+
+        let thread = send_bot(&message(), &context, &["## What's Changed".to_string()], bot(&server.url), Some(&previous)).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        let release = requests[0].json();
+        assert_eq!(release["thread_ts"], "1700000001.000100");
+        assert_eq!(release["reply_broadcast"], true);
+        // Changelog chunks stay in the thread
+        let chunk = requests[1].json();
+        assert_eq!(chunk["thread_ts"], "1700000001.000100");
+        assert!(chunk.get("reply_broadcast").is_none());
+        assert_eq!(thread.thread_ts, "1700000001.000100");
+        assert_eq!(thread.ts, "1700000002.000200");
+    }
+
+    #[tokio::test]
+    async fn thread_of_another_channel_is_not_reused() {
+        let server = slack_api().await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+        let previous = SlackThread {
+            channel: "C999".to_string(),
+            thread_ts: "1700000001.000100".to_string(),
+            version: "v2.0.3".to_string(),
+            ts: "1700000001.000100".to_string(),
+        };
+
+        let thread = send_bot(&message(), &context, &[], bot(&server.url), Some(&previous)).await.unwrap();
+
+        assert!(server.requests()[0].json().get("thread_ts").is_none());
+        assert_eq!(thread.thread_ts, "1700000002.000200");
+    }
+
+    #[tokio::test]
+    async fn edited_release_updates_the_stored_message() {
+        let server = slack_api().await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+        let thread = SlackThread {
+            channel: "C123".to_string(),
+            thread_ts: "1700000001.000100".to_string(),
+            version: "v2.1.0".to_string(),
+            ts: "1700000002.000200".to_string(),
+        };
+
+        assert!(update_message(&message(), &context, bot(&server.url), &thread).await);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, Method::POST);
+        assert_eq!(requests[0].path, "/chat.update");
+        let data = requests[0].json();
+        assert_eq!(data["channel"], "C123");
+        assert_eq!(data["ts"], "1700000002.000200");
+    }
+
+    #[tokio::test]
+    async fn failed_changelog_chunks_keep_the_message_delivered() {
+        let server = MockServer::start(|request| match request.json()["text"].as_str() {
+            Some("part 2") => (StatusCode::OK, json!({ "ok": false, "error": "ratelimited" })),
+            _ => (StatusCode::OK, json!({ "ok": true, "ts": "1700000002.000200" })),
+        }).await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+        let chunks = ["part 1".to_string(), "part 2".to_string(), "part 3".to_string()];
+
+        let thread = send_bot(&message(), &context, &chunks, bot(&server.url), None).await;
+
+        assert_eq!(thread.unwrap().thread_ts, "1700000002.000200");
+        // The changelog stops at the failed part, the rest would be out of order
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn api_errors_are_failures() {
+        let server = MockServer::start(|_| (StatusCode::OK, json!({ "ok": false, "error": "channel_not_found" }))).await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+
+        assert!(send_bot(&message(), &context, &[], bot(&server.url), None).await.is_none());
+    }
+}
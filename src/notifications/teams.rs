@@ -0,0 +1,192 @@
+use log::{error, info};
+use serde_json::{json, Value};
+use crate::models::WatchKind;
+use crate::notifications::markdown::truncate_chars;
+use crate::notifications::template::{Message, MessageContext};
+
+// Workflow webhooks reject payloads above 28 KB, counted in bytes of the serialized card
+const MAX_BYTES: usize = 28 * 1024;
+// Characters of the body the templates aim for, a body this long in ASCII fits with the rest of the card
+pub const MAX_LENGTH: usize = 24 * 1024;
+
+fn fact(title: &str, value: &str) -> Value {
+    json!({ "title": title, "value": value })
+}
+
+fn text(text: &str) -> Value {
+    json!({ "type": "TextBlock", "text": text, "wrap": true })
+}
+
+fn card(body: Vec<Value>, url: &str, button: &str) -> Value {
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "contentUrl": null,
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "msteams": { "width": "Full" },
+                "body": body,
+                "actions": [{ "type": "Action.OpenUrl", "title": button, "url": url }]
+            }
+        }]
+    })
+}
+
+// Cut the text until the serialized card fits in MAX_BYTES, multibyte characters and escapes included
+fn fit(text: &str, card: impl Fn(&str) -> Value) -> Value {
+    let size = |card: &Value| serde_json::to_vec(card).map_or(0, |data| data.len());
+    let full = card(text);
+    if size(&full) <= MAX_BYTES {
+        return full;
+    }
+
+    let (mut low, mut high) = (0, text.chars().count());
+    while low < high {
+        let middle = (low + high).div_ceil(2);
+        if size(&card(&truncate_chars(text, middle))) <= MAX_BYTES {
+            low = middle;
+        } else {
+            high = middle - 1;
+        }
+    }
+    card(&truncate_chars(text, low))
+}
+
+fn release_card(message: &Message, context: &MessageContext) -> Value {
+    let mut facts = Vec::new();
+    match context.event {
+        WatchKind::Github => {
+            if let Some(tag) = &context.tag {
+                facts.push(fact("Version", tag));
+            }
+            if let Some(previous) = &context.previous_tag {
+                facts.push(fact("Previous", previous));
+            }
+        }
+        WatchKind::Docker => {
+            if let Some(digest) = &context.digest {
+                facts.push(fact("Digest", digest));
+            }
+        }
+    }
+    facts.push(fact("Published", &context.published_at));
+    if let Some(deployed) = &context.deployed_version {
+        let value = match &context.drift {
+            Some(drift) => format!("{} ({})", deployed, drift),
+            None => deployed.clone(),
+        };
+        facts.push(fact("Deployed", &value));
+    }
+
+    let mut header = Vec::new();
+    if let Some(avatar) = &context.avatar_url {
+        header.push(json!({
+            "type": "Column",
+            "width": "auto",
+            "items": [{ "type": "Image", "url": avatar, "size": "Small", "style": "Person", "altText": context.repo }]
+        }));
+    }
+    header.push(json!({
+        "type": "Column",
+        "width": "stretch",
+        "verticalContentAlignment": "Center",
+        "items": [
+            { "type": "TextBlock", "text": message.title, "weight": "Bolder", "size": "Large", "wrap": true },
+            { "type": "TextBlock", "text": context.repo, "isSubtle": true, "spacing": "None", "wrap": true }
+        ]
+    }));
+
+    let button = match context.event {
+        WatchKind::Github => "View Release",
+        WatchKind::Docker => "Docker Hub",
+    };
+    fit(&message.body, |body| card(vec![
+        json!({ "type": "ColumnSet", "columns": header.clone() }),
+        json!({ "type": "FactSet", "facts": facts.clone() }),
+        text(body),
+    ], &context.url, button))
+}
+
+async fn post(data: &Value, webhook_url: &str) -> Result<(), String> {
+    let client = reqwest::Client::new();
+
+    match client.post(webhook_url)
+        .json(data)
+        .send()
+        .await
+    {
+        // Workflows answer 202 Accepted
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("Status code: {}", response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, webhook_url: &str) -> bool {
+    match post(&release_card(message, context), webhook_url).await {
+        Ok(()) => {
+            info!("Message sent to Teams for {}", context.app_name);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send message to Teams. {}", e);
+            false
+        }
+    }
+}
+
+pub async fn send_alert(title: &str, message: &str, url: &str, webhook_url: &str) -> bool {
+    let data = fit(message, |message| card(vec![
+        json!({ "type": "TextBlock", "text": title, "weight": "Bolder", "size": "Large", "wrap": true }),
+        text(message),
+    ], url, "Open release"));
+
+    match post(&data, webhook_url).await {
+        Ok(()) => {
+            info!("Alert sent to Teams: {}", title);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send alert to Teams. {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::template::TemplateEvent;
+
+    fn size(card: &Value) -> usize {
+        serde_json::to_vec(card).unwrap().len()
+    }
+
+    #[test]
+    fn short_messages_are_kept_whole() {
+        let message = Message { title: "New version".to_string(), body: "📌 *New version*: v2.1.0".to_string() };
+        let card = release_card(&message, &MessageContext::sample(TemplateEvent::Github));
+
+        assert_eq!(card["attachments"][0]["content"]["body"][2]["text"], message.body);
+    }
+
+    #[test]
+    fn multibyte_bodies_fit_in_the_byte_limit() {
+        // Within MAX_LENGTH characters but three bytes each
+        let message = Message { title: "New version".to_string(), body: "€ ".repeat(MAX_LENGTH / 2) };
+        let card = release_card(&message, &MessageContext::sample(TemplateEvent::Github));
+
+        assert!(size(&card) <= MAX_BYTES);
+        assert!(size(&card) > MAX_BYTES - 64);
+    }
+
+    #[test]
+    fn escaped_characters_count_in_the_limit() {
+        let card = fit(&"\"\n".repeat(MAX_BYTES / 2), text);
+
+        assert!(size(&card) <= MAX_BYTES);
+    }
+}
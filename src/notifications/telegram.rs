@@ -0,0 +1,280 @@
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::time::Duration;
+use crate::models::WatchKind;
+use crate::notifications::markdown::{self, Dialect};
+use crate::notifications::template::{Message, MessageContext};
+
+// Telegram counts the characters left once the entities are parsed, the title and the markup take the rest
+pub const MAX_LENGTH: usize = 3900;
+const MAX_MESSAGE: usize = 4096;
+const MAX_RETRIES: u32 = 3;
+const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    #[default]
+    MarkdownV2,
+    Html,
+}
+
+impl ParseMode {
+    fn dialect(&self) -> Dialect {
+        match self {
+            ParseMode::MarkdownV2 => Dialect::Telegram,
+            ParseMode::Html => Dialect::TelegramHtml,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ParseMode::MarkdownV2 => "MarkdownV2",
+            ParseMode::Html => "HTML",
+        }
+    }
+}
+
+// Numeric id of a chat, or @username of a public channel
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum ChatId {
+    Id(i64),
+    Username(String),
+}
+
+impl fmt::Display for ChatId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChatId::Id(id) => write!(f, "{}", id),
+            ChatId::Username(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// A chat, and the topic to post in for forum supergroups
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Chat {
+    pub id: ChatId,
+    pub topic: Option<i64>,
+}
+
+impl Chat {
+    // "chat" or "chat:topic", as given in TELEGRAM_CHAT_IDS
+    pub fn parse(value: &str) -> Option<Self> {
+        let (id, topic) = match value.split_once(':') {
+            Some((id, topic)) => (id, Some(topic.parse().ok()?)),
+            None => (value, None),
+        };
+        let id = match id.parse() {
+            Ok(id) => ChatId::Id(id),
+            Err(_) if id.starts_with('@') => ChatId::Username(id.to_string()),
+            Err(_) => return None,
+        };
+        Some(Chat { id, topic })
+    }
+}
+
+impl fmt::Display for Chat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.topic {
+            Some(topic) => write!(f, "{}:{}", self.id, topic),
+            None => write!(f, "{}", self.id),
+        }
+    }
+}
+
+// Bot settings of a target
+pub struct Bot<'a> {
+    pub token: &'a str,
+    pub chats: &'a [Chat],
+    pub parse_mode: ParseMode,
+    pub api_url: Option<&'a str>,
+}
+
+// The message is rendered in markdown like for ntfy, then converted as a whole so the template text is escaped too
+fn text(title: &str, body: &str, parse_mode: ParseMode) -> String {
+    let mut source = format!("**{}**\n\n{}", title, body);
+    loop {
+        let text = markdown::convert(&source, parse_mode.dialect(), None);
+        let length = text.chars().count();
+        if length <= MAX_MESSAGE {
+            return text;
+        }
+        // Escapes can push a message over the limit, cutting the markdown keeps the markup valid.
+        // The cut is proportional, escapes are as frequent in what is kept as in what is cut.
+        let source_length = source.chars().count();
+        let cut = (source_length * MAX_MESSAGE / length).min(source_length - 1);
+        source = markdown::truncate_chars(&source, cut);
+    }
+}
+
+fn button(text: &str, url: &str) -> Value {
+    json!({ "text": text, "url": url })
+}
+
+async fn send_to_chat(client: &reqwest::Client, bot: &Bot<'_>, chat: &Chat, data: &Value) -> Result<(), String> {
+    let url = format!("{}/bot{}/sendMessage", bot.api_url.unwrap_or(DEFAULT_API_URL).trim_end_matches('/'), bot.token);
+    let mut data = data.clone();
+    data["chat_id"] = json!(chat.id.to_string());
+    if let Some(topic) = chat.topic {
+        data["message_thread_id"] = json!(topic);
+    }
+
+    for attempt in 0..=MAX_RETRIES {
+        let response = client.post(&url)
+            .json(&data)
+            .send()
+            .await
+            // The error would show the URL, which contains the token
+            .map_err(|e| e.without_url().to_string())?;
+
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if body["ok"].as_bool() == Some(true) {
+            return Ok(());
+        }
+
+        let description = body["description"].as_str().unwrap_or_default().to_string();
+        match body["parameters"]["retry_after"].as_u64() {
+            Some(delay) if attempt < MAX_RETRIES && delay <= 60 => {
+                warn!("Rate limited by Telegram, retrying in {}s", delay);
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+            }
+            _ => return Err(format!("status code {} {}", status, description).trim_end().to_string()),
+        }
+    }
+
+    Err("rate limited by Telegram".to_string())
+}
+
+// Fails only when no chat got the message: a retry would post it again in the chats that did,
+// so the ones that failed next to them are logged and not retried
+async fn send_to_chats(bot: &Bot<'_>, data: &Value) -> Result<(), String> {
+    let client = reqwest::Client::new();
+
+    let mut errors = Vec::new();
+    for chat in bot.chats {
+        if let Err(e) = send_to_chat(&client, bot, chat, data).await {
+            errors.push(format!("{}: {}", chat, e));
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else if errors.len() < bot.chats.len() {
+        warn!("Telegram message not delivered to every chat, it is not sent again. {}", errors.join(", "));
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, bot: Bot<'_>) -> bool {
+    let buttons: Vec<Value> = match context.event {
+        WatchKind::Github => [Some(button("View Release", &context.url)), context.compare_url.as_deref().map(|url| button("Compare", url))]
+            .into_iter().flatten().collect(),
+        WatchKind::Docker => vec![button("Docker Hub", &context.url)],
+    };
+
+    let data = json!({
+        "text": text(&message.title, &message.body, bot.parse_mode),
+        "parse_mode": bot.parse_mode.name(),
+        "link_preview_options": { "is_disabled": true },
+        "reply_markup": { "inline_keyboard": [buttons] }
+    });
+
+    match send_to_chats(&bot, &data).await {
+        Ok(()) => {
+            info!("Message sent to Telegram for {}", context.app_name);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send message to Telegram. {}", e);
+            false
+        }
+    }
+}
+
+pub async fn send_alert(title: &str, message: &str, url: &str, bot: Bot<'_>) -> bool {
+    let data = json!({
+        "text": text(title, message, bot.parse_mode),
+        "parse_mode": bot.parse_mode.name(),
+        "link_preview_options": { "is_disabled": true },
+        "reply_markup": { "inline_keyboard": [[button("Open release", url)]] }
+    });
+
+    match send_to_chats(&bot, &data).await {
+        Ok(()) => {
+            info!("Alert sent to Telegram: {}", title);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send alert to Telegram. {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::template::TemplateEvent;
+    use crate::test_support::MockServer;
+    use warp::http::StatusCode;
+
+    fn bot<'a>(chats: &'a [Chat], parse_mode: ParseMode, api_url: &'a str) -> Bot<'a> {
+        Bot { token: "123:abc", chats, parse_mode, api_url: Some(api_url) }
+    }
+
+    #[test]
+    fn markdown_v2_escapes_every_reserved_character() {
+        // Backslashes make the characters literal in the markdown source, Telegram wants each of them escaped
+        let reserved = "_*[]()~`>#+-=|{}.!\\";
+        let source: String = reserved.chars().map(|c| format!("\\{}", c)).collect();
+        let escaped: String = reserved.chars().map(|c| format!("\\{}", c)).collect();
+        assert_eq!(text("v1.0", &source, ParseMode::MarkdownV2), format!("*v1\\.0*\n\n{}", escaped));
+    }
+
+    #[test]
+    fn html_mode_escapes_tags_and_entities() {
+        assert_eq!(text("a < b", "**R&D** 5 > 3, `<br>`", ParseMode::Html), "<b>a &lt; b</b>\n\n<b>R&amp;D</b> 5 &gt; 3, <code>&lt;br&gt;</code>");
+    }
+
+    #[test]
+    fn messages_fit_telegram_once_escaped() {
+        // Every dot gets escaped, the source has to be cut below the limit
+        let body = "1.2.3 ".repeat(1000);
+        let long = text("Release", &body, ParseMode::MarkdownV2);
+        let length = long.chars().count();
+        assert!(length <= MAX_MESSAGE && length > MAX_MESSAGE - 100, "{}", length);
+        assert!(!long.ends_with("\\"), "an escape must not be cut from its character");
+
+        assert_eq!(text("Release", "1.2.3", ParseMode::MarkdownV2), "*Release*\n\n1\\.2\\.3");
+    }
+
+    #[tokio::test]
+    async fn a_failed_chat_does_not_fail_the_chats_that_got_the_message() {
+        let server = MockServer::start(|request| match request.json()["chat_id"].as_str() {
+            Some("-100") => (StatusCode::BAD_REQUEST, json!({ "ok": false, "description": "Bad Request: chat not found" })),
+            _ => (StatusCode::OK, json!({ "ok": true })),
+        }).await;
+        let context = MessageContext::sample(TemplateEvent::Github);
+        let message = Message { title: "New version".to_string(), body: "v2.1.0".to_string() };
+        let chats = [Chat::parse("42").unwrap(), Chat::parse("-100").unwrap(), Chat::parse("@releases:7").unwrap()];
+
+        assert!(send_notification(&message, &context, bot(&chats, ParseMode::MarkdownV2, &server.url)).await);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].path, "/bot123:abc/sendMessage");
+        assert_eq!(requests[2].json()["chat_id"], "@releases");
+        assert_eq!(requests[2].json()["message_thread_id"], 7);
+
+        // Nobody got it: the target failed and is retried
+        assert!(!send_alert("Drift", "behind", "https://example.com", bot(&chats[1..2], ParseMode::Html, &server.url)).await);
+    }
+}
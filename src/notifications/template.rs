@@ -0,0 +1,317 @@
+use handlebars::{Handlebars, TemplateErrorReason};
+use serde::{Deserialize, Serialize};
+use crate::drift::{self, Bump, Drift, DriftAlert};
+use crate::models::{DockerReleaseInfo, GithubReleaseInfo, Priority, WatchKind};
+use crate::notifications::markdown::{self, Dialect};
+
+const GITHUB_TITLE: &str = "New version for {{app_name}}";
+const GITHUB_BODY: &str = "📌 *New version*: {{tag}}\n\n📦 *For*: {{app_name}}\n\n📅 *Published on*: {{published_at}}\
+{{#if deployed}}\n\n{{deployed}}{{/if}}\
+{{#if changelog}}\n\n📝 *Changelog*:\n\n{{changelog}}{{/if}}\
+\n\n🔗 *Release Url*: {{url}}";
+
+const DOCKER_TITLE: &str = "🆕 New version for {{app_name}}";
+const DOCKER_BODY: &str = "🐳 *Docker Image Updated!*\n\n🔐 *New Digest*: `{{digest}}`\n\n📦 *App*: {{app_name}}\n\n📢 *Published*: {{published_at}}\
+{{#if deployed}}\n\n{{deployed}}{{/if}}\
+\n\n🔗 *Release Url*: {{url}}";
+
+const DRIFT_TITLE: &str = "{{app_name}} is falling behind";
+const DRIFT_BODY: &str = "⚠️ *Drift alert*: {{app_name}} ({{#if digest}}Docker{{else}}GitHub{{/if}}) is {{drift}}\
+\n\n🏷️ *Deployed*: {{deployed_version}}\n\n📌 *Latest*: {{#if digest}}{{digest}}{{else}}{{tag}}{{/if}}\
+\n\n🔗 *Release Url*: {{url}}";
+
+// Title and body templates of a target for one kind of event, unset parts use the defaults
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MessageTemplate {
+    pub title: Option<String>,
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Templates {
+    pub github: Option<MessageTemplate>,
+    pub docker: Option<MessageTemplate>,
+    pub drift: Option<MessageTemplate>,
+}
+
+// Kind of message a template is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TemplateEvent {
+    Github,
+    Docker,
+    Drift,
+}
+
+impl Templates {
+    pub fn get(&self, event: TemplateEvent) -> Option<&MessageTemplate> {
+        match event {
+            TemplateEvent::Github => self.github.as_ref(),
+            TemplateEvent::Docker => self.docker.as_ref(),
+            TemplateEvent::Drift => self.drift.as_ref(),
+        }
+    }
+
+    pub fn set(&mut self, event: TemplateEvent, template: MessageTemplate) {
+        match event {
+            TemplateEvent::Github => self.github = Some(template),
+            TemplateEvent::Docker => self.docker = Some(template),
+            TemplateEvent::Drift => self.drift = Some(template),
+        }
+    }
+}
+
+// Variables available in the templates
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageContext {
+    pub event: WatchKind,
+    pub repo: String,
+    pub app_name: String,
+    pub tag: Option<String>,
+    pub previous_tag: Option<String>,
+    pub changelog: Option<String>,
+    pub url: String,
+    pub published_at: String,
+    pub digest: Option<String>,
+    pub previous_digest: Option<String>,
+    pub deployed_version: Option<String>,
+    pub drift: Option<String>,
+    pub deployed: Option<String>,
+    // Size of the jump from the previous tag, unknown when a tag is not a version
+    pub bump: Option<Bump>,
+    pub compare_url: Option<String>,
+    pub avatar_url: Option<String>,
+    // Priority set on the watch, services derive one from the bump otherwise
+    pub priority: Option<Priority>,
+    // Drift alert rather than a release, `event` is then the kind of the watch
+    #[serde(skip)]
+    pub alert: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    pub title: String,
+    pub body: String,
+}
+
+pub fn app_name(repo: &str) -> String {
+    repo.split('/').next_back().unwrap_or(repo).to_string()
+}
+
+// GitHub serves the avatar of any user or organization at this address
+pub fn avatar_url(repo: &str) -> Option<String> {
+    repo.split('/').next().filter(|owner| !owner.is_empty()).map(|owner| format!("https://github.com/{}.png", owner))
+}
+
+fn format_date(published_at: &str) -> String {
+    published_at.replace("T", " ").replace("Z", "")
+}
+
+// Reminds which version is actually deployed when it is not the new one
+fn deployed_line(deployed_version: Option<&str>, latest: Option<&str>, drift: Option<&Drift>) -> Option<String> {
+    let drift = drift.map(|d| format!(" ({})", d.summary())).unwrap_or_default();
+    match (deployed_version, latest) {
+        (Some(deployed), Some(latest)) if deployed != latest => {
+            Some(format!("🏷️ *Deployed*: you're on {}, latest is {}{}", deployed, latest, drift))
+        }
+        (Some(deployed), None) => Some(format!("🏷️ *Deployed*: you're on {}{}", deployed, drift)),
+        _ => None,
+    }
+}
+
+impl MessageContext {
+    pub fn github(release: &GithubReleaseInfo, previous_tag: Option<String>) -> Self {
+        let bump = previous_tag.as_deref().and_then(|previous| drift::bump(previous, &release.tag_name));
+        let compare_url = previous_tag.as_ref()
+            .map(|previous| format!("https://github.com/{}/compare/{}...{}", release.repo, previous, release.tag_name));
+        MessageContext {
+            event: WatchKind::Github,
+            repo: release.repo.clone(),
+            app_name: app_name(&release.repo),
+            tag: Some(release.tag_name.clone()),
+            previous_tag,
+            changelog: Some(release.changelog.clone()),
+            url: release.html_url.clone(),
+            published_at: format_date(&release.published_at),
+            digest: None,
+            previous_digest: None,
+            deployed_version: release.deployed_version.clone(),
+            drift: release.drift.as_ref().map(Drift::summary),
+            deployed: deployed_line(release.deployed_version.as_deref(), Some(&release.tag_name), release.drift.as_ref()),
+            bump,
+            compare_url,
+            avatar_url: avatar_url(&release.repo),
+            priority: release.priority,
+            alert: false,
+        }
+    }
+
+    pub fn docker(release: &DockerReleaseInfo, previous_digest: Option<String>) -> Self {
+        MessageContext {
+            event: WatchKind::Docker,
+            repo: release.repo.clone(),
+            app_name: app_name(&release.repo),
+            tag: None,
+            previous_tag: None,
+            changelog: None,
+            url: release.html_url.clone(),
+            published_at: format_date(&release.published_at),
+            digest: Some(release.digest.clone()),
+            previous_digest,
+            deployed_version: release.deployed_version.clone(),
+            drift: release.drift.as_ref().map(Drift::summary),
+            deployed: deployed_line(release.deployed_version.as_deref(), None, release.drift.as_ref()),
+            bump: None,
+            compare_url: None,
+            avatar_url: None,
+            priority: release.priority,
+            alert: false,
+        }
+    }
+
+    // The latest version is a tag for GitHub watches and a digest for Docker ones
+    pub fn drift(alert: &DriftAlert) -> Self {
+        let (tag, digest) = match alert.kind {
+            WatchKind::Github => (Some(alert.latest_version.clone()), None),
+            WatchKind::Docker => (None, Some(alert.latest_version.clone())),
+        };
+        let github = alert.kind == WatchKind::Github;
+        MessageContext {
+            event: alert.kind,
+            repo: alert.repo.clone(),
+            app_name: app_name(&alert.repo),
+            tag,
+            previous_tag: None,
+            changelog: None,
+            url: alert.html_url.clone(),
+            published_at: String::new(),
+            digest,
+            previous_digest: None,
+            deployed_version: Some(alert.deployed_version.clone()),
+            drift: Some(alert.drift.summary()),
+            deployed: deployed_line(Some(&alert.deployed_version), Some(&alert.latest_version), Some(&alert.drift)),
+            bump: if github { drift::bump(&alert.deployed_version, &alert.latest_version) } else { None },
+            compare_url: github.then(|| format!("https://github.com/{}/compare/{}...{}", alert.repo, alert.deployed_version, alert.latest_version)),
+            avatar_url: if github { avatar_url(&alert.repo) } else { None },
+            priority: None,
+            alert: true,
+        }
+    }
+
+    pub fn template_event(&self) -> TemplateEvent {
+        match self.event {
+            _ if self.alert => TemplateEvent::Drift,
+            WatchKind::Github => TemplateEvent::Github,
+            WatchKind::Docker => TemplateEvent::Docker,
+        }
+    }
+
+    // Sample data used to preview templates
+    pub fn sample(event: TemplateEvent) -> Self {
+        match event {
+            TemplateEvent::Github => MessageContext::github(&GithubReleaseInfo {
+                repo: "BreizhHardware/ntfy_alerts".to_string(),
+                tag_name: "v2.1.0".to_string(),
+                html_url: "https://github.com/BreizhHardware/ntfy_alerts/releases/tag/v2.1.0".to_string(),
+                changelog: "## What's Changed\n- Add configuration file support\n- Fix Docker digest comparison".to_string(),
+                published_at: "2025-06-01T12:00:00Z".to_string(),
+                deployed_version: Some("v2.0.0".to_string()),
+                drift: Some(Drift { minor: 1, ..Drift::default() }),
+                priority: None,
+            }, Some("v2.0.3".to_string())),
+            TemplateEvent::Docker => MessageContext::docker(&DockerReleaseInfo {
+                repo: "breizhhardware/github-ntfy".to_string(),
+                digest: "sha256:4f1c3b6e9a2d7c8b5e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2d".to_string(),
+                html_url: "https://hub.docker.com/r/breizhhardware/github-ntfy".to_string(),
+                published_at: "2025-06-01T12:00:00Z".to_string(),
+                deployed_version: None,
+                drift: None,
+                priority: None,
+            }, Some("sha256:9e8d7c6b5a4f3e2d1c0b9a8f7e6d5c4b3a2f1e0d9c8b7a6f5e4d3c2b1a0f9e8d".to_string())),
+            TemplateEvent::Drift => MessageContext::drift(&DriftAlert {
+                kind: WatchKind::Github,
+                repo: "BreizhHardware/ntfy_alerts".to_string(),
+                deployed_version: "v1.4.2".to_string(),
+                latest_version: "v2.1.0".to_string(),
+                html_url: "https://github.com/BreizhHardware/ntfy_alerts/releases/tag/v2.1.0".to_string(),
+                drift: Drift { major: 1, minor: 1, patch: 0, days_behind: Some(45) },
+            }),
+        }
+    }
+}
+
+fn engine() -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
+    // Messages are markdown or plain text, not HTML
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars
+}
+
+// Parse a template without rendering it, to report syntax errors when the configuration is loaded
+pub fn check(template: &str) -> Result<(), String> {
+    engine().register_template_string("check", template).map_err(|e| {
+        let reason = match e.reason() {
+            TemplateErrorReason::InvalidSyntax(_) => "invalid syntax".to_string(),
+            reason => reason.to_string(),
+        };
+        match e.pos() {
+            Some((line, column)) => format!("{} at line {}, column {}", reason, line, column),
+            None => reason,
+        }
+    })
+}
+
+fn render_template(template: &str, context: &MessageContext) -> Result<String, String> {
+    check(template)?;
+    engine().render_template(template, context).map_err(|e| e.to_string())
+}
+
+// Below this many characters a shortened changelog is not worth sending, the link is enough
+const MIN_CHANGELOG_LENGTH: usize = 200;
+
+// Render the title and body of a message, with the changelog converted to the markup of the target.
+// When the body exceeds max_len characters the changelog is shortened, then left out, then the body is cut.
+pub fn render(templates: &Templates, context: &MessageContext, dialect: Dialect, max_len: Option<usize>) -> Result<Message, String> {
+    let event = context.template_event();
+    let custom = templates.get(event);
+    let (default_title, default_body) = match event {
+        TemplateEvent::Github => (GITHUB_TITLE, GITHUB_BODY),
+        TemplateEvent::Docker => (DOCKER_TITLE, DOCKER_BODY),
+        TemplateEvent::Drift => (DRIFT_TITLE, DRIFT_BODY),
+    };
+    let title = custom.and_then(|t| t.title.as_deref()).unwrap_or(default_title);
+    let body = custom.and_then(|t| t.body.as_deref()).unwrap_or(default_body);
+
+    let raw_changelog = context.changelog.as_deref();
+    let mut context = MessageContext {
+        changelog: raw_changelog.map(|c| markdown::convert(c, dialect, None)),
+        ..context.clone()
+    };
+    let mut message = Message {
+        title: render_template(title, &context)?,
+        body: render_template(body, &context)?,
+    };
+
+    let Some(max) = max_len else { return Ok(message) };
+    let length = message.body.chars().count();
+    if length <= max {
+        return Ok(message);
+    }
+
+    if let (Some(raw), Some(converted)) = (raw_changelog, &context.changelog) {
+        let budget = converted.chars().count().saturating_sub(length - max);
+        context.changelog = if budget >= MIN_CHANGELOG_LENGTH {
+            Some(markdown::convert(raw, dialect, Some(budget)))
+        } else {
+            None
+        };
+        message.body = render_template(body, &context)?;
+        if message.body.chars().count() > max && context.changelog.is_some() {
+            context.changelog = None;
+            message.body = render_template(body, &context)?;
+        }
+    }
+
+    message.body = markdown::truncate_chars(&message.body, max);
+    Ok(message)
+}
@@ -0,0 +1,208 @@
+use log::{error, info};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, USER_AGENT};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use crate::drift::Bump;
+use crate::models::WatchKind;
+use crate::notifications::template::{Message, MessageContext};
+
+// Bumped on any change receivers could notice: a field removed, renamed or with another meaning.
+// Adding a field keeps the version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Release or Docker image update, with the rendered message for receivers that only forward it
+#[derive(Serialize)]
+struct ReleaseEvent<'a> {
+    schema_version: u32,
+    event: &'static str,
+    source: WatchKind,
+    repo: &'a str,
+    app_name: &'a str,
+    old_version: Option<&'a str>,
+    new_version: Option<&'a str>,
+    digest: Option<&'a str>,
+    old_digest: Option<&'a str>,
+    // Release notes as published on GitHub, in markdown
+    changelog: Option<&'a str>,
+    url: &'a str,
+    compare_url: Option<&'a str>,
+    published_at: &'a str,
+    bump: Option<Bump>,
+    deployed_version: Option<&'a str>,
+    title: &'a str,
+    message: &'a str,
+    sent_at: String,
+}
+
+#[derive(Serialize)]
+struct AlertEvent<'a> {
+    schema_version: u32,
+    event: &'static str,
+    title: &'a str,
+    message: &'a str,
+    url: &'a str,
+    sent_at: String,
+}
+
+// Hex encoded HMAC-SHA256 of the body, sent as `X-Signature: sha256=<hex>`
+pub fn signature(body: &[u8], secret: &str) -> String {
+    let mac = hmac_sha256::HMAC::mac(body, secret.as_bytes());
+    let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+// Check the custom headers when the configuration is loaded
+pub fn check_headers(headers: &BTreeMap<String, String>) -> Result<(), String> {
+    for (name, value) in headers {
+        HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("invalid header name '{}'", name))?;
+        HeaderValue::from_str(value).map_err(|_| format!("invalid value for header '{}'", name))?;
+    }
+    Ok(())
+}
+
+// JSON of a release event, also published by the event bus targets
+pub fn release_payload(message: &Message, context: &MessageContext) -> Result<Vec<u8>, String> {
+    let event = ReleaseEvent {
+        schema_version: SCHEMA_VERSION,
+        event: "release",
+        source: context.event,
+        repo: &context.repo,
+        app_name: &context.app_name,
+        old_version: context.previous_tag.as_deref(),
+        new_version: context.tag.as_deref(),
+        digest: context.digest.as_deref(),
+        old_digest: context.previous_digest.as_deref(),
+        changelog: context.changelog.as_deref(),
+        url: &context.url,
+        compare_url: context.compare_url.as_deref(),
+        published_at: &context.published_at,
+        bump: context.bump,
+        deployed_version: context.deployed_version.as_deref(),
+        title: &message.title,
+        message: &message.body,
+        sent_at: chrono::Utc::now().to_rfc3339(),
+    };
+    serde_json::to_vec(&event).map_err(|e| e.to_string())
+}
+
+pub fn alert_payload(title: &str, message: &str, url: &str) -> Result<Vec<u8>, String> {
+    let event = AlertEvent {
+        schema_version: SCHEMA_VERSION,
+        event: "alert",
+        title,
+        message,
+        url,
+        sent_at: chrono::Utc::now().to_rfc3339(),
+    };
+    serde_json::to_vec(&event).map_err(|e| e.to_string())
+}
+
+// The signature covers the exact bytes sent
+async fn post(body: Vec<u8>, kind: &str, url: &str, headers: &BTreeMap<String, String>, secret: Option<&str>) -> Result<(), String> {
+    let client = reqwest::Client::new();
+
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            header_map.insert(name, value);
+        }
+    }
+    header_map.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    header_map.insert(USER_AGENT, HeaderValue::from_static(concat!("github-ntfy/", env!("CARGO_PKG_VERSION"))));
+    header_map.insert("X-Event", HeaderValue::from_str(kind).unwrap_or_else(|_| HeaderValue::from_static("")));
+    if let Some(secret) = secret {
+        header_map.insert("X-Signature", HeaderValue::from_str(&signature(&body, secret))
+            .unwrap_or_else(|_| HeaderValue::from_static("")));
+    }
+
+    match client.post(url)
+        .headers(header_map)
+        .body(body)
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(format!("Status code: {}", response.status())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub async fn send_notification(message: &Message, context: &MessageContext, url: &str, headers: &BTreeMap<String, String>, secret: Option<&str>) -> bool {
+    let result = match release_payload(message, context) {
+        Ok(body) => post(body, "release", url, headers, secret).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => {
+            info!("Webhook sent for {}", context.app_name);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send webhook. {}", e);
+            false
+        }
+    }
+}
+
+pub async fn send_alert(title: &str, message: &str, release_url: &str, url: &str, headers: &BTreeMap<String, String>, secret: Option<&str>) -> bool {
+    let result = match alert_payload(title, message, release_url) {
+        Ok(body) => post(body, "alert", url, headers, secret).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => {
+            info!("Alert sent by webhook: {}", title);
+            true
+        },
+        Err(e) => {
+            error!("Failed to send alert by webhook. {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::template::TemplateEvent;
+    use serde_json::Value;
+
+    fn fields(payload: &[u8]) -> Vec<String> {
+        let event: Value = serde_json::from_slice(payload).unwrap();
+        let mut fields: Vec<String> = event.as_object().unwrap().keys().cloned().collect();
+        fields.sort();
+        fields
+    }
+
+    #[test]
+    fn signature_is_the_hmac_sha256_of_the_body() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            signature(b"what do ya want for nothing?", "Jefe"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    // Receivers rely on these fields: a change here without a new SCHEMA_VERSION breaks them.
+    // Update the snapshot when a field is added, and bump the version when one is removed, renamed or changes meaning.
+    #[test]
+    fn payload_fields_match_the_schema_version() {
+        let message = Message { title: "New version".to_string(), body: "v2.1.0".to_string() };
+        let release = release_payload(&message, &MessageContext::sample(TemplateEvent::Github)).unwrap();
+        let alert = alert_payload("Drift", "behind", "https://example.com").unwrap();
+
+        assert_eq!(
+            (SCHEMA_VERSION, fields(&release)),
+            (1, [
+                "app_name", "bump", "changelog", "compare_url", "deployed_version", "digest", "event", "message", "new_version",
+                "old_digest", "old_version", "published_at", "repo", "schema_version", "sent_at", "source", "title", "url",
+            ].map(String::from).to_vec())
+        );
+        assert_eq!(
+            (SCHEMA_VERSION, fields(&alert)),
+            (1, ["event", "message", "schema_version", "sent_at", "title", "url"].map(String::from).to_vec())
+        );
+    }
+}
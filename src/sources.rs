@@ -0,0 +1,207 @@
+use log::{error, info};
+use reqwest::header::HeaderMap;
+use std::collections::HashSet;
+use std::time::Duration;
+use crate::database::{self, Connection};
+use crate::models::{GithubRelease, GithubRepo, SourceKind, WatchKind, WatchSource};
+
+const PER_PAGE: usize = 100;
+// Repositories without releases are checked again after this delay, those with releases never are
+const RECHECK_WITHOUT_RELEASES: chrono::Duration = chrono::Duration::days(7);
+
+// Synchronize every source that was never synced or whose last sync is older than `refresh`
+pub async fn sync_sources(conn: &Connection, client: &reqwest::Client, mut headers: HeaderMap, refresh: Duration) {
+    if !headers.contains_key("User-Agent") {
+        headers.insert("User-Agent", "github-ntfy/1.0".parse().unwrap());
+    }
+
+    let sources = match database::get_watch_sources(conn) {
+        Ok(sources) => sources,
+        Err(e) => {
+            error!("Error while reading watch sources: {}", e);
+            return;
+        }
+    };
+
+    for source in sources.iter().filter(|s| needs_sync(s, refresh)) {
+        match fetch_source_repos(source, conn, client, &headers).await {
+            Ok(repos) => {
+                if let Err(e) = apply_source(conn, source, &repos) {
+                    error!("Error while updating repositories of {}: {}", source.marker(), e);
+                }
+            }
+            Err(e) => error!("Error while fetching repositories of {}: {}", source.marker(), e),
+        }
+    }
+}
+
+fn needs_sync(source: &WatchSource, refresh: Duration) -> bool {
+    match source.last_synced.as_deref().map(chrono::DateTime::parse_from_rfc3339) {
+        Some(Ok(last)) => {
+            let elapsed = chrono::Utc::now().signed_duration_since(last);
+            // A last sync in the future (clock skew, edited database) would otherwise never be reached
+            elapsed.to_std().map(|e| e >= refresh).unwrap_or(true)
+        }
+        _ => true,
+    }
+}
+
+pub async fn fetch_source_repos(
+    source: &WatchSource,
+    conn: &Connection,
+    client: &reqwest::Client,
+    headers: &HeaderMap,
+) -> Result<Vec<String>, reqwest::Error> {
+    let base_url = match source.kind {
+        SourceKind::Starred => format!("https://api.github.com/users/{}/starred", source.name),
+        SourceKind::Org => format!("https://api.github.com/orgs/{}/repos?type=public", source.name),
+    };
+    let separator = if base_url.contains('?') { '&' } else { '?' };
+
+    let excludes: Vec<glob::Pattern> = source.exclude.iter()
+        .filter_map(|p| match glob::Pattern::new(p) {
+            Ok(pattern) => Some(pattern),
+            Err(e) => {
+                error!("Invalid exclude pattern '{}' for {}: {}", p, source.marker(), e);
+                None
+            }
+        })
+        .collect();
+
+    let mut repos = Vec::new();
+    let mut page = 1;
+
+    loop {
+        let url = format!("{}{}per_page={}&page={}", base_url, separator, PER_PAGE, page);
+        let batch = client.get(&url)
+            .headers(headers.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Vec<GithubRepo>>()
+            .await?;
+
+        let count = batch.len();
+        for repo in batch {
+            if repo.archived || excludes.iter().any(|p| p.matches(&repo.full_name)) {
+                continue;
+            }
+            if source.releases_only && !has_releases(&repo.full_name, conn, client, headers).await {
+                continue;
+            }
+            repos.push(repo.full_name);
+        }
+
+        if count < PER_PAGE {
+            break;
+        }
+        page += 1;
+    }
+
+    Ok(repos)
+}
+
+// Answers are cached in the database, a sync only asks GitHub about new repositories
+// and about those that had no release at their last check
+async fn has_releases(repo: &str, conn: &Connection, client: &reqwest::Client, headers: &HeaderMap) -> bool {
+    match database::get_release_check(conn, repo) {
+        Ok(Some((true, _))) => return true,
+        Ok(Some((false, checked_at))) => {
+            let recent = chrono::DateTime::parse_from_rfc3339(&checked_at)
+                .map(|checked| chrono::Utc::now().signed_duration_since(checked) < RECHECK_WITHOUT_RELEASES)
+                .unwrap_or(false);
+            if recent {
+                return false;
+            }
+        }
+        Ok(None) => {}
+        Err(e) => error!("Error while reading the release check of {}: {}", repo, e),
+    }
+
+    let url = format!("https://api.github.com/repos/{}/releases?per_page=1", repo);
+    let found = match client.get(&url).headers(headers.clone()).send().await {
+        Ok(response) if response.status().is_success() => {
+            match response.json::<Vec<GithubRelease>>().await {
+                Ok(releases) => !releases.is_empty(),
+                Err(e) => {
+                    error!("Error checking releases for {}: {}", repo, e);
+                    return false;
+                }
+            }
+        }
+        // Rate limits and server errors are not an answer, the repository is checked again on the next sync
+        Ok(response) => {
+            error!("Error checking releases for {}: status {}", repo, response.status());
+            return false;
+        }
+        Err(e) => {
+            error!("Error checking releases for {}: {}", repo, e);
+            return false;
+        }
+    };
+
+    if let Err(e) = database::save_release_check(conn, repo, found) {
+        error!("Error while saving the release check of {}: {}", repo, e);
+    }
+    found
+}
+
+// Add the new repositories of a source and drop the ones that left it
+pub fn apply_source(conn: &Connection, source: &WatchSource, repos: &[String]) -> rusqlite::Result<()> {
+    let marker = source.marker();
+    let current: HashSet<String> = database::get_source_repos(conn, &marker)?.into_iter().collect();
+    let wanted: HashSet<&String> = repos.iter().collect();
+
+    let mut added = 0;
+    for repo in repos {
+        // Repositories already watched by hand or by another source are left untouched
+        if !current.contains(repo) && !database::is_watched(conn, WatchKind::Github, repo)? {
+            database::add_source_watch(conn, repo, &marker)?;
+            added += 1;
+        }
+    }
+
+    let mut removed = 0;
+    for repo in current.iter().filter(|r| !wanted.contains(r)) {
+        database::remove_watch(conn, WatchKind::Github, repo)?;
+        removed += 1;
+    }
+
+    database::mark_source_synced(conn, source.id)?;
+    info!("Source {} synchronized: {} repositories, {} added, {} removed", marker, repos.len(), added, removed);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(last_synced: Option<chrono::DateTime<chrono::Utc>>) -> WatchSource {
+        WatchSource {
+            id: 1,
+            kind: SourceKind::Org,
+            name: "BreizhHardware".to_string(),
+            exclude: Vec::new(),
+            releases_only: true,
+            last_synced: last_synced.map(|time| time.to_rfc3339()),
+        }
+    }
+
+    #[test]
+    fn sources_sync_once_the_refresh_interval_has_passed() {
+        let refresh = Duration::from_secs(3600);
+        let now = chrono::Utc::now();
+
+        assert!(needs_sync(&source(None), refresh));
+        assert!(!needs_sync(&source(Some(now - chrono::Duration::minutes(10))), refresh));
+        assert!(needs_sync(&source(Some(now - chrono::Duration::hours(2))), refresh));
+    }
+
+    #[test]
+    fn sources_synced_in_the_future_sync_now() {
+        let later = chrono::Utc::now() + chrono::Duration::days(1);
+
+        assert!(needs_sync(&source(Some(later)), Duration::from_secs(3600)));
+    }
+}
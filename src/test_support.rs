@@ -0,0 +1,217 @@
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use warp::http::{HeaderMap, Method, StatusCode};
+use warp::Filter;
+
+// Request received by a mock server
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+// HTTP server standing in for a notification service, it records the requests and answers them with `respond`
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockServer {
+    pub async fn start<F>(respond: F) -> MockServer
+    where
+        F: Fn(&Request) -> (StatusCode, Value) + Send + Sync + 'static,
+    {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let respond = Arc::new(respond);
+
+        let route = warp::method()
+            .and(warp::path::full())
+            .and(warp::header::headers_cloned())
+            .and(warp::body::bytes())
+            .map(move |method, path: warp::path::FullPath, headers, body: warp::hyper::body::Bytes| {
+                let request = Request { method, path: path.as_str().to_string(), headers, body: body.to_vec() };
+                let (status, answer) = respond(&request);
+                recorded.lock().unwrap().push(request);
+                warp::reply::with_status(warp::reply::json(&answer), status)
+            });
+
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        MockServer { url: format!("http://{}", addr), requests }
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// Email received by an SMTP sink
+#[derive(Debug, Clone, Default)]
+pub struct Email {
+    pub from: String,
+    pub recipients: Vec<String>,
+    pub data: String,
+}
+
+// SMTP server accepting every email without TLS nor authentication
+pub struct SmtpSink {
+    pub port: u16,
+    emails: Arc<Mutex<Vec<Email>>>,
+}
+
+impl SmtpSink {
+    pub async fn start() -> SmtpSink {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let emails = Arc::new(Mutex::new(Vec::new()));
+        let received = emails.clone();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    let mut email = Email::default();
+                    writer.write_all(b"220 sink ESMTP\r\n").await.ok();
+
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command = line.to_uppercase();
+                        let reply: &[u8] = if command.starts_with("EHLO") || command.starts_with("HELO") {
+                            b"250 sink\r\n"
+                        } else if command.starts_with("MAIL FROM:") {
+                            email.from = address(&line);
+                            b"250 OK\r\n"
+                        } else if command.starts_with("RCPT TO:") {
+                            email.recipients.push(address(&line));
+                            b"250 OK\r\n"
+                        } else if command == "DATA" {
+                            writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.ok();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                email.data.push_str(&line);
+                                email.data.push('\n');
+                            }
+                            received.lock().unwrap().push(std::mem::take(&mut email));
+                            b"250 OK\r\n"
+                        } else if command == "QUIT" {
+                            writer.write_all(b"221 Bye\r\n").await.ok();
+                            break;
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        writer.write_all(reply).await.ok();
+                    }
+                });
+            }
+        });
+
+        SmtpSink { port, emails }
+    }
+
+    pub fn emails(&self) -> Vec<Email> {
+        self.emails.lock().unwrap().clone()
+    }
+}
+
+fn address(line: &str) -> String {
+    line.split(['<', '>']).nth(1).unwrap_or_default().to_string()
+}
+
+// Message published to an MQTT broker
+#[derive(Debug, Clone)]
+pub struct Publication {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+}
+
+// MQTT 3.1.1 broker accepting every client, it records the publications and acknowledges them
+pub struct MqttBroker {
+    pub port: u16,
+    publications: Arc<Mutex<Vec<Publication>>>,
+}
+
+impl MqttBroker {
+    pub async fn start() -> MqttBroker {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let publications = Arc::new(Mutex::new(Vec::new()));
+        let received = publications.clone();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let received = received.clone();
+                tokio::spawn(async move {
+                    while let Some((header, packet)) = read_packet(&mut stream).await {
+                        let reply = match header >> 4 {
+                            // CONNECT
+                            1 => vec![0x20, 0x02, 0x00, 0x00],
+                            // PUBLISH
+                            3 => {
+                                let topic_length = u16::from_be_bytes([packet[0], packet[1]]) as usize;
+                                let topic = String::from_utf8_lossy(&packet[2..2 + topic_length]).to_string();
+                                let mut payload = &packet[2 + topic_length..];
+                                let mut reply = Vec::new();
+                                if (header >> 1) & 0x03 > 0 {
+                                    reply = vec![0x40, 0x02, payload[0], payload[1]];
+                                    payload = &payload[2..];
+                                }
+                                received.lock().unwrap().push(Publication { topic, payload: payload.to_vec(), retain: header & 0x01 == 1 });
+                                reply
+                            }
+                            // PINGREQ
+                            12 => vec![0xd0, 0x00],
+                            // DISCONNECT
+                            14 => break,
+                            _ => Vec::new(),
+                        };
+                        if stream.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        MqttBroker { port, publications }
+    }
+
+    pub fn publications(&self) -> Vec<Publication> {
+        self.publications.lock().unwrap().clone()
+    }
+}
+
+// Fixed header byte and variable part of the next packet
+async fn read_packet(stream: &mut tokio::net::TcpStream) -> Option<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await.ok()?;
+    let (mut length, mut shift) = (0usize, 0);
+    loop {
+        let byte = stream.read_u8().await.ok()?;
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    let mut packet = vec![0; length];
+    stream.read_exact(&mut packet).await.ok()?;
+    Some((header, packet))
+}
@@ -0,0 +1,265 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::Path;
+use crate::database::{self, Connection};
+use crate::models::{Priority, WatchKind, WatchSource};
+
+// One watched repository, as written in export files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchEntry {
+    #[serde(rename = "type")]
+    pub kind: WatchKind,
+    pub repo: String,
+    // Version running in production, drift is measured from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployed_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+}
+
+// CSV rows need every column, empty when the watch has no value
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    #[serde(rename = "type")]
+    kind: WatchKind,
+    repo: &'a str,
+    deployed_version: Option<&'a str>,
+    priority: Option<Priority>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WatchList {
+    pub watches: Vec<WatchEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<WatchSource>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Csv,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    // Guess the format from a file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Format::from_name)
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Yaml => "application/yaml",
+            Format::Csv => "text/csv",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportError {
+    pub entry: usize,
+    pub repo: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub duplicates: Vec<String>,
+    pub errors: Vec<ImportError>,
+}
+
+// Exported watch list, with the number of sources left out because the format cannot hold them
+pub struct Export {
+    pub data: String,
+    pub skipped_sources: usize,
+}
+
+pub fn export_watches(conn: &Connection, format: Format) -> Result<Export, Box<dyn Error>> {
+    let mut list = WatchList::default();
+
+    // Repositories managed by a source are recreated by the source itself
+    let repos = [
+        (WatchKind::Github, database::get_manual_watched_repos(conn)?),
+        (WatchKind::Docker, database::get_docker_watched_repos(conn)?),
+    ];
+    for (kind, repos) in repos {
+        let mut deployed = database::get_deployed_versions(conn, kind)?;
+        let mut priorities = database::get_priorities(conn, kind)?;
+        for repo in repos {
+            list.watches.push(WatchEntry {
+                kind,
+                deployed_version: deployed.remove(&repo),
+                priority: priorities.remove(&repo),
+                repo,
+            });
+        }
+    }
+
+    list.sources = database::get_watch_sources(conn)?
+        .into_iter()
+        .map(|source| WatchSource { id: 0, last_synced: None, ..source })
+        .collect();
+
+    let mut skipped_sources = 0;
+    let data = match format {
+        Format::Json => serde_json::to_string_pretty(&list)?,
+        Format::Yaml => serde_yaml::to_string(&list)?,
+        Format::Csv => {
+            // CSV has one row per watch, the sources are only kept by JSON and YAML
+            skipped_sources = list.sources.len();
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for entry in &list.watches {
+                writer.serialize(CsvRow {
+                    kind: entry.kind,
+                    repo: &entry.repo,
+                    deployed_version: entry.deployed_version.as_deref(),
+                    priority: entry.priority,
+                })?;
+            }
+            String::from_utf8(writer.into_inner()?)?
+        }
+    };
+
+    Ok(Export { data, skipped_sources })
+}
+
+pub fn parse_watches(data: &str, format: Format) -> Result<WatchList, Box<dyn Error>> {
+    let list = match format {
+        Format::Json => serde_json::from_str::<WatchList>(data)?,
+        Format::Yaml => serde_yaml::from_str::<WatchList>(data)?,
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(data.as_bytes());
+            let mut watches = Vec::new();
+            for record in reader.deserialize() {
+                watches.push(record?);
+            }
+            WatchList { watches, sources: Vec::new() }
+        }
+    };
+
+    Ok(list)
+}
+
+// Import every entry, reporting duplicates and invalid entries instead of failing the whole file
+pub fn import_watches(conn: &Connection, data: &str, format: Format) -> Result<ImportReport, Box<dyn Error>> {
+    let list = parse_watches(data, format)?;
+    let mut report = ImportReport::default();
+
+    for (index, entry) in list.watches.into_iter().enumerate() {
+        let repo = entry.repo.trim().to_string();
+
+        if let Err(e) = validate_repo(entry.kind, &repo) {
+            report.errors.push(ImportError { entry: index + 1, repo, error: e });
+            continue;
+        }
+
+        match database::is_watched(conn, entry.kind, &repo) {
+            Ok(true) => report.duplicates.push(repo),
+            Ok(false) => match add_entry(conn, &entry, &repo) {
+                Ok(_) => report.added.push(repo),
+                Err(e) => report.errors.push(ImportError { entry: index + 1, repo, error: e.to_string() }),
+            },
+            Err(e) => report.errors.push(ImportError { entry: index + 1, repo, error: e.to_string() }),
+        }
+    }
+
+    for source in list.sources {
+        let source = WatchSource { name: source.name.trim().to_string(), ..source };
+        let marker = source.marker();
+        if let Err(e) = validate_source(&source) {
+            report.errors.push(ImportError { entry: 0, repo: marker, error: e });
+            continue;
+        }
+        match database::source_exists(conn, source.kind, &source.name) {
+            Ok(true) => report.duplicates.push(marker),
+            Ok(false) => match database::add_watch_source(conn, &source) {
+                Ok(_) => report.added.push(marker),
+                Err(e) => report.errors.push(ImportError { entry: 0, repo: marker, error: e.to_string() }),
+            },
+            Err(e) => report.errors.push(ImportError { entry: 0, repo: marker, error: e.to_string() }),
+        }
+    }
+
+    Ok(report)
+}
+
+fn add_entry(conn: &Connection, entry: &WatchEntry, repo: &str) -> database::SqliteResult<()> {
+    database::add_watch(conn, entry.kind, repo)?;
+    if let Some(version) = entry.deployed_version.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+        database::set_deployed_version(conn, entry.kind, repo, Some(version))?;
+    }
+    if entry.priority.is_some() {
+        database::set_priority(conn, entry.kind, repo, entry.priority)?;
+    }
+    Ok(())
+}
+
+// Sources name a GitHub user or organization and exclude repositories with glob patterns
+pub fn validate_source(source: &WatchSource) -> Result<(), String> {
+    if source.name.is_empty() || source.name.contains(['/', ' ']) {
+        return Err(format!("'{}' is not a GitHub user or organization", source.name));
+    }
+    if let Some(pattern) = source.exclude.iter().find(|p| glob::Pattern::new(p).is_err()) {
+        return Err(format!("invalid exclude pattern '{}'", pattern));
+    }
+    Ok(())
+}
+
+pub fn validate_repo(kind: WatchKind, repo: &str) -> Result<(), String> {
+    let parts: Vec<&str> = repo.split('/').collect();
+    let valid = match kind {
+        WatchKind::Github => parts.len() == 2,
+        WatchKind::Docker => parts.len() <= 2,
+    } && parts.iter().all(|p| !p.is_empty() && !p.contains(char::is_whitespace));
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not a valid {} repository name", repo, kind.label()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_sources_are_reported_and_not_imported() {
+        // Rejected before the database is reached, it has no tables
+        let conn = Connection::open_in_memory().unwrap();
+        let data = r#"{
+            "watches": [],
+            "sources": [
+                {"kind": "org", "name": "  "},
+                {"kind": "starred", "name": "owner/repo"},
+                {"kind": "org", "name": "BreizhHardware", "exclude": ["[test"]}
+            ]
+        }"#;
+
+        let report = import_watches(&conn, data, Format::Json).unwrap();
+
+        assert!(report.added.is_empty());
+        let errors: Vec<(&str, &str)> = report.errors.iter().map(|e| (e.repo.as_str(), e.error.as_str())).collect();
+        assert_eq!(errors, [
+            ("org:", "'' is not a GitHub user or organization"),
+            ("starred:owner/repo", "'owner/repo' is not a GitHub user or organization"),
+            ("org:BreizhHardware", "invalid exclude pattern '[test'"),
+        ]);
+    }
+}
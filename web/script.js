@@ -13,6 +13,7 @@ document.getElementById('addRepoForm').addEventListener('submit', function(event
             if (response.ok) {
                 // Si la requête s'est bien déroulée, actualiser la liste des dépôts surveillés
                 refreshWatchedRepos();
+refreshWatchSources();
             } else {
                 throw new Error('Erreur lors de l\'ajout du dépôt');
             }
@@ -37,6 +38,7 @@ document.getElementById('addDockerRepoForm').addEventListener('submit', function
             if (response.ok) {
                 // Si la requête s'est bien déroulée, actualiser la liste des dépôts surveillés
                 refreshWatchedRepos();
+refreshWatchSources();
             } else {
                 throw new Error('Erreur lors de l\'ajout du dépôt');
             }
@@ -46,6 +48,141 @@ document.getElementById('addDockerRepoForm').addEventListener('submit', function
         });
 });
 
+document.getElementById('addSourceForm').addEventListener('submit', function(event) {
+    event.preventDefault();
+    const exclude = document.getElementById('sourceExclude').value
+        .split('\n')
+        .map(pattern => pattern.trim())
+        .filter(pattern => pattern.length > 0);
+    fetch('/app_source', {
+        method: 'POST',
+        headers: {
+            'Content-Type': 'application/json'
+        },
+        body: JSON.stringify({
+            kind: document.getElementById('sourceKind').value,
+            name: document.getElementById('sourceName').value,
+            exclude: exclude
+        })
+    })
+        .then(response => {
+            if (response.ok) {
+                refreshWatchSources();
+            } else {
+                throw new Error('Error while adding the source');
+            }
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+});
+
+function refreshDrift() {
+    fetch('/drift')
+        .then(response => response.json())
+        .then(data => {
+            const driftTable = document.getElementById('driftTable');
+            driftTable.innerHTML = '';
+            data.forEach(watch => {
+                const row = document.createElement('tr');
+                let drift = 'up to date';
+                if (watch.drift) {
+                    const parts = [];
+                    if (watch.drift.major) parts.push(`${watch.drift.major} major`);
+                    if (watch.drift.minor) parts.push(`${watch.drift.minor} minor`);
+                    if (watch.drift.patch) parts.push(`${watch.drift.patch} patch`);
+                    if (watch.drift.days_behind !== null) parts.push(`${watch.drift.days_behind} days`);
+                    drift = parts.join(', ') + ' behind';
+                }
+                [watch.repo, watch.deployed_version, watch.latest_version || '?', drift].forEach(value => {
+                    const cell = document.createElement('td');
+                    cell.className = 'py-1 pr-2 break-all';
+                    cell.textContent = value;
+                    row.appendChild(cell);
+                });
+                if (watch.drift) {
+                    row.className = 'text-yellow-400';
+                }
+                driftTable.appendChild(row);
+            });
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+function refreshWatchSources() {
+    fetch('/watch_sources')
+        .then(response => response.json())
+        .then(data => {
+            const watchSourcesList = document.getElementById('watchSourcesList');
+            watchSourcesList.innerHTML = '';
+            data.forEach(source => {
+                const listItem = document.createElement('li');
+                const sourceName = document.createElement('span');
+                sourceName.textContent = `${source.kind}:${source.name}` + (source.last_synced ? '' : ' (pending)');
+                listItem.appendChild(sourceName);
+
+                const deleteButton = document.createElement('button');
+                deleteButton.textContent = ' X';
+                deleteButton.className = 'delete-btn text-red-500 ml-2';
+                deleteButton.addEventListener('click', () => {
+                    fetch('/delete_source', {
+                        method: 'POST',
+                        headers: {
+                            'Content-Type': 'application/json'
+                        },
+                        body: JSON.stringify({id: source.id})
+                    })
+                        .then(() => {
+                            listItem.remove();
+                            refreshWatchedRepos();
+refreshWatchSources();
+                        })
+                        .catch(error => {
+                            console.error('Error:', error);
+                        });
+                });
+                listItem.appendChild(deleteButton);
+
+                watchSourcesList.appendChild(listItem);
+            });
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+}
+
+document.getElementById('importForm').addEventListener('submit', function(event) {
+    event.preventDefault();
+    const file = document.getElementById('importFile').files[0];
+    if (!file) {
+        return;
+    }
+    const format = file.name.split('.').pop();
+    file.text()
+        .then(content => fetch('/import_watches?format=' + encodeURIComponent(format), {
+            method: 'POST',
+            body: content
+        }))
+        .then(response => response.json())
+        .then(report => {
+            const lines = [];
+            if (report.error) {
+                lines.push(report.error);
+            } else {
+                lines.push(`${report.added.length} added, ${report.duplicates.length} duplicates, ${report.errors.length} errors`);
+                report.errors.forEach(e => lines.push(`entry ${e.entry}: ${e.error}`));
+            }
+            document.getElementById('importReport').textContent = lines.join('\n');
+            refreshWatchedRepos();
+refreshWatchSources();
+        })
+        .catch(error => {
+            console.error('Error:', error);
+        });
+});
+
 function refreshWatchedRepos() {
     fetch('/watched_repos')
         .then(response => response.json())
@@ -155,4 +292,6 @@ function removeDockerRepoFromWatchedRepos(repo) {
 }
 
 // Appeler la fonction pour charger les dépôts surveillés au chargement de la page
-refreshWatchedRepos();
\ No newline at end of file
+refreshWatchedRepos();
+refreshWatchSources();
+refreshDrift();